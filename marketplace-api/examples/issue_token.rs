@@ -0,0 +1,61 @@
+//! Issues a bearer token for local testing.
+//!
+//! `MARKETPLACE_JWT_SECRET=... cargo run -p marketplace-api --example issue_token -- <user id>`
+//! signs with HS256. Pass `--key <private key pem> --alg RS256|EdDSA [--kid <id>]` to sign
+//! with a key whose public half is in the JWKS file instead.
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use jsonwebtoken::{encode, Algorithm, EncodingKey, Header};
+use serde::Serialize;
+
+#[derive(Serialize)]
+struct Claims {
+    sub: String,
+    exp: u64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    iss: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    aud: Option<String>,
+}
+
+fn main() -> Result<(), Box<dyn std::error::Error>> {
+    let mut args = std::env::args().skip(1);
+    let sub = args
+        .next()
+        .ok_or("usage: issue_token <user id> [--key <pem> --alg <alg>]")?;
+    let (mut key_file, mut alg, mut kid) = (None, String::from("HS256"), None);
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--key" => key_file = args.next(),
+            "--alg" => alg = args.next().ok_or("--alg needs a value")?,
+            "--kid" => kid = args.next(),
+            other => return Err(format!("unknown argument {other}").into()),
+        }
+    }
+
+    let (algorithm, key) = match (alg.as_str(), key_file) {
+        ("HS256", _) => (
+            Algorithm::HS256,
+            EncodingKey::from_secret(std::env::var("MARKETPLACE_JWT_SECRET")?.as_bytes()),
+        ),
+        ("RS256", Some(file)) => (
+            Algorithm::RS256,
+            EncodingKey::from_rsa_pem(&std::fs::read(file)?)?,
+        ),
+        ("EdDSA", Some(file)) => (
+            Algorithm::EdDSA,
+            EncodingKey::from_ed_pem(&std::fs::read(file)?)?,
+        ),
+        _ => return Err("RS256 and EdDSA need --key".into()),
+    };
+    let mut header = Header::new(algorithm);
+    header.kid = kid;
+    let claims = Claims {
+        sub,
+        exp: SystemTime::now().duration_since(UNIX_EPOCH)?.as_secs() + 60 * 60,
+        iss: std::env::var("MARKETPLACE_JWT_ISSUER").ok(),
+        aud: std::env::var("MARKETPLACE_JWT_AUDIENCE").ok(),
+    };
+    println!("{}", encode(&header, &claims, &key)?);
+    Ok(())
+}
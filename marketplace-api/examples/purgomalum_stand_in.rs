@@ -0,0 +1,43 @@
+//! Local stand-in for the PurgoMalum profanity filter, answering
+//! `GET /service/containsprofanity?text=...` with `true` or `false`.
+//!
+//! `cargo run -p marketplace-api --example purgomalum_stand_in`, then start the api
+//! with `MARKETPLACE_TEXT_MODERATION_URL=http://localhost:8001`.
+use std::collections::HashSet;
+
+use poem::{
+    get, handler, listener::TcpListener, web::Data, web::Query, EndpointExt, Route, Server,
+};
+use serde::Deserialize;
+
+const WORD_LIST: &str = include_str!("../profanity.txt");
+
+#[derive(Deserialize)]
+struct Params {
+    text: String,
+}
+
+#[handler]
+fn contains_profanity(Query(params): Query<Params>, words: Data<&HashSet<String>>) -> String {
+    let found = params
+        .text
+        .split(|c: char| !c.is_alphanumeric())
+        .any(|word| words.contains(&word.to_lowercase()));
+    found.to_string()
+}
+
+#[tokio::main]
+async fn main() -> Result<(), std::io::Error> {
+    let words: HashSet<String> = WORD_LIST
+        .lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty() && !line.starts_with('#'))
+        .map(String::from)
+        .collect();
+    let app = Route::new()
+        .at("/service/containsprofanity", get(contains_profanity))
+        .data(words);
+    Server::new(TcpListener::bind("127.0.0.1:8001"))
+        .run(app)
+        .await
+}
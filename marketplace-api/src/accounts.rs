@@ -0,0 +1,461 @@
+use std::{
+    collections::HashMap,
+    fmt,
+    sync::{Arc, Mutex, OnceLock},
+    time::{Duration, SystemTime},
+};
+
+use anyhow::{anyhow, Result};
+use argon2::{
+    password_hash::{PasswordHash, PasswordHasher, PasswordVerifier, SaltString},
+    Argon2,
+};
+use marketplace_contracts::accounts::v1;
+use marketplace_domain::{
+    user_account::{
+        AccountEvents, EmailAddress, InvalidCredentials, Password, UserAccount,
+        UserAccountAggregate,
+    },
+    IPasswordHasher, UserId,
+};
+use marketplace_framework::{
+    AggregateRoot, CommandMetadata, ConcurrencyConflict, EntityNotFound, ICommandHandler,
+};
+use poem_openapi::Object;
+use rand::{rngs::OsRng, RngCore};
+use sha2::{Digest, Sha256};
+use uuid::Uuid;
+
+use crate::{
+    mail::{IMailSender, Mail},
+    traits::{IApplicationService, IEntityStore},
+};
+
+/// How long a password reset token can be used for.
+const RESET_TOKEN_LIFETIME: Duration = Duration::from_secs(60 * 60);
+const RESET_TOKEN_BYTES: usize = 32;
+
+/// Raised when registering with an email address another account uses.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct EmailAlreadyRegistered {
+    pub email: String,
+}
+
+impl fmt::Display for EmailAlreadyRegistered {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "An account for {} already exists", self.email)
+    }
+}
+
+impl std::error::Error for EmailAlreadyRegistered {}
+
+// ================================================================================
+// Password hashing
+// ================================================================================
+
+/// Argon2id with the `argon2` crate's default parameters, stored as PHC strings.
+#[derive(Clone, Default)]
+pub struct Argon2idPasswordHasher {
+    _argon2: Argon2<'static>,
+}
+
+impl Argon2idPasswordHasher {
+    pub fn new() -> Self {
+        Self::default()
+    }
+}
+
+impl IPasswordHasher for Argon2idPasswordHasher {
+    fn hash(&self, password: &str) -> Result<String> {
+        let salt = SaltString::generate(&mut OsRng);
+        Ok(self
+            ._argon2
+            .hash_password(password.as_bytes(), &salt)
+            .map_err(|e| anyhow!("Could not hash password: {}", e))?
+            .to_string())
+    }
+
+    fn verify(&self, password: &str, hash: &str) -> Result<bool> {
+        let hash = PasswordHash::new(hash).map_err(|e| anyhow!("Invalid password hash: {}", e))?;
+        Ok(self
+            ._argon2
+            .verify_password(password.as_bytes(), &hash)
+            .is_ok())
+    }
+}
+
+// ================================================================================
+// Storage and read model
+// ================================================================================
+
+pub struct UserAccountStore {
+    _store: HashMap<String, UserAccount>,
+}
+
+impl UserAccountStore {
+    pub fn new() -> Self {
+        Self {
+            _store: HashMap::new(),
+        }
+    }
+}
+
+impl Default for UserAccountStore {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl IEntityStore for UserAccountStore {
+    type Entity = UserAccount;
+
+    fn save(&mut self, account: UserAccount, expected_version: Option<i64>) -> Result<()> {
+        let id = account.id().value().to_string();
+        match (expected_version, self._store.get(&id)) {
+            (None, Some(_)) => return Err(anyhow!("User account with this ID already exists")),
+            (Some(expected_version), Some(stored)) if stored.version() != expected_version => {
+                return Err(ConcurrencyConflict {
+                    expected_version,
+                    actual_version: stored.version(),
+                }
+                .into());
+            }
+            _ => {}
+        }
+        self._store.insert(id, account);
+        Ok(())
+    }
+
+    fn exists(&self, id: String) -> bool {
+        self._store.contains_key(&id)
+    }
+
+    fn load(&self, id: String) -> Result<UserAccount> {
+        match self._store.get(&id) {
+            Some(account) => Ok(account.clone()),
+            None => Err(EntityNotFound { id }.into()),
+        }
+    }
+}
+
+/// Read model finding accounts by email address, kept up to date from account events.
+/// Addresses can also be reserved for a user while their account is being set up.
+#[derive(Default)]
+pub struct AccountEmailIndex {
+    _accounts: HashMap<EmailAddress, UserId>,
+    _reserved: HashMap<EmailAddress, Uuid>,
+}
+
+impl AccountEmailIndex {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn when(&mut self, event: &AccountEvents) {
+        if let AccountEvents::AccountRegistered(e) = event {
+            self._accounts.insert(e.email.clone(), e.id.clone());
+        }
+    }
+
+    pub fn find(&self, email: &EmailAddress) -> Option<UserId> {
+        self._accounts.get(email).cloned()
+    }
+
+    /// Holds an address for `user_id`, unless an account has it or someone
+    /// else is registering with it.
+    pub fn reserve(&mut self, email: &EmailAddress, user_id: Uuid) -> Result<()> {
+        let reserved_by_other = self
+            ._reserved
+            .get(email)
+            .is_some_and(|reserved_for| *reserved_for != user_id);
+        if self.find(email).is_some() || reserved_by_other {
+            return Err(EmailAlreadyRegistered {
+                email: email.value(),
+            }
+            .into());
+        }
+        self._reserved.insert(email.clone(), user_id);
+        Ok(())
+    }
+
+    pub fn release(&mut self, email: &EmailAddress, user_id: Uuid) {
+        if self._reserved.get(email) == Some(&user_id) {
+            self._reserved.remove(email);
+        }
+    }
+}
+
+/// An email address held for a user until dropped.
+pub struct EmailReservation {
+    _emails: Arc<Mutex<AccountEmailIndex>>,
+    _email: EmailAddress,
+    _user_id: Uuid,
+}
+
+impl Drop for EmailReservation {
+    fn drop(&mut self) {
+        self._emails
+            .lock()
+            .unwrap()
+            .release(&self._email, self._user_id);
+    }
+}
+
+// ================================================================================
+// Application service
+// ================================================================================
+
+#[derive(Clone)]
+pub struct UserAccountApplicationService {
+    _repository: Arc<Mutex<dyn IEntityStore<Entity = UserAccount>>>,
+    _emails: Arc<Mutex<AccountEmailIndex>>,
+    _password_hasher: Arc<dyn IPasswordHasher + Send + Sync>,
+    _mail_sender: Arc<dyn IMailSender>,
+    /// Checked against on logins with unknown addresses, so they take as long as any other.
+    _dummy_hash: Arc<OnceLock<String>>,
+}
+
+impl UserAccountApplicationService {
+    pub fn new(
+        password_hasher: Arc<dyn IPasswordHasher + Send + Sync>,
+        mail_sender: Arc<dyn IMailSender>,
+    ) -> Self {
+        Self {
+            _repository: Arc::new(Mutex::new(UserAccountStore::new())),
+            _emails: Arc::new(Mutex::new(AccountEmailIndex::new())),
+            _password_hasher: password_hasher,
+            _mail_sender: mail_sender,
+            _dummy_hash: Arc::new(OnceLock::new()),
+        }
+    }
+
+    pub fn load(&self, id: UserId) -> Result<UserAccount> {
+        self._repository
+            .lock()
+            .unwrap()
+            .load(id.value().to_string())
+    }
+
+    pub fn find_by_email(&self, email: &str) -> Result<Option<UserId>> {
+        Ok(self
+            ._emails
+            .lock()
+            .unwrap()
+            .find(&EmailAddress::new(email)?))
+    }
+
+    /// Holds an email address for a user about to register, so other commands
+    /// sent on their behalf can be run before the account exists.
+    pub fn reserve_email(&self, email: &str, user_id: Uuid) -> Result<EmailReservation> {
+        let email = EmailAddress::new(email)?;
+        self._emails.lock().unwrap().reserve(&email, user_id)?;
+        Ok(EmailReservation {
+            _emails: self._emails.clone(),
+            _email: email,
+            _user_id: user_id,
+        })
+    }
+
+    /// Checks an email address and password, returning whose account they belong to.
+    /// Hashes the password, so call it off the async workers.
+    pub fn login(&self, email: &str, password: &str) -> Result<UserId> {
+        let invalid = || InvalidCredentials {
+            reason: String::from("the email address or password is incorrect"),
+        };
+        let Some(id) = self.find_by_email(email)? else {
+            let dummy_hash = self
+                ._dummy_hash
+                .get_or_init(|| self._password_hasher.hash("").unwrap_or_default());
+            let _ = self._password_hasher.verify(password, dummy_hash);
+            return Err(invalid().into());
+        };
+        let account = self.load(id)?;
+        if !account.verify_password(password, self._password_hasher.clone())? {
+            return Err(invalid().into());
+        }
+        Ok(account.id())
+    }
+
+    /// Saves an account and brings the email index up to date with its new events.
+    fn save(&self, account: UserAccount, loaded_version: Option<i64>) -> Result<()> {
+        let new_events = account.changes()[loaded_version.unwrap_or(0) as usize..].to_vec();
+        self._repository
+            .lock()
+            .unwrap()
+            .save(account, loaded_version)?;
+        let mut emails = self._emails.lock().unwrap();
+        new_events.iter().for_each(|event| emails.when(event));
+        Ok(())
+    }
+
+    fn handle_register(&self, cmd: v1::RegisterAccount) -> Result<()> {
+        let email = EmailAddress::new(&cmd.email)?;
+        let password = Password::new(&cmd.password)?;
+        // The reservation keeps two requests from taking the same address
+        // without holding the index while the password is hashed
+        self._emails.lock().unwrap().reserve(&email, cmd.user_id)?;
+        let result = self.register(cmd.user_id, email.clone(), password);
+        self._emails.lock().unwrap().release(&email, cmd.user_id);
+        result
+    }
+
+    fn register(&self, user_id: Uuid, email: EmailAddress, password: Password) -> Result<()> {
+        if self._repository.lock().unwrap().exists(user_id.to_string()) {
+            return Err(anyhow!("User account with this ID already exists"));
+        }
+        let mut account = UserAccount::new_empty();
+        account.register(
+            UserId::new(user_id),
+            email,
+            password,
+            self._password_hasher.clone(),
+        )?;
+        self.save(account, None)
+    }
+
+    fn handle_request_password_reset(&self, cmd: v1::RequestPasswordReset) -> Result<()> {
+        // Unknown addresses are not reported, so the endpoint cannot be used to probe for accounts
+        let id = match self.find_by_email(&cmd.email)? {
+            Some(id) => id,
+            None => return Ok(()),
+        };
+        let token = generate_reset_token();
+        let expires_at = SystemTime::now() + RESET_TOKEN_LIFETIME;
+        let mut account = self.load(id)?;
+        let loaded_version = account.version();
+        account.request_password_reset(hash_reset_token(&token), expires_at)?;
+        let email = account.email().value();
+        self.save(account, Some(loaded_version))?;
+        self._mail_sender.send(&Mail {
+            to: email,
+            subject: String::from("Reset your password"),
+            body: format!(
+                "Someone asked to reset the password for your account. If it was you, \
+                 use this token within {} minutes to choose a new one:\r\n\r\n{}\r\n\r\n\
+                 If it was not you, you can ignore this message.",
+                RESET_TOKEN_LIFETIME.as_secs() / 60,
+                token
+            ),
+        })
+    }
+
+    fn handle_update<Cmd>(
+        &self,
+        id: UserId,
+        cmd: Cmd,
+        metadata: &CommandMetadata,
+        operation: fn(cmd: Cmd, a: &mut UserAccount) -> Result<()>,
+    ) -> Result<()> {
+        let mut account = self.load(id)?;
+        let loaded_version = account.version();
+        metadata.check_version(loaded_version)?;
+        operation(cmd, &mut account)?;
+        self.save(account, Some(loaded_version))
+    }
+}
+
+impl IApplicationService for UserAccountApplicationService {
+    type Command = v1::Commands;
+    fn handle(&self, command: impl Into<Self::Command>, metadata: &CommandMetadata) -> Result<()> {
+        match command.into() {
+            v1::Commands::RegisterAccount(cmd) => self.handle_register(cmd)?,
+            v1::Commands::ChangePassword(cmd) => {
+                let hasher = self._password_hasher.clone();
+                self.handle_update(
+                    UserId::new(cmd.user_id),
+                    (cmd, hasher),
+                    metadata,
+                    |(cmd, hasher), a| {
+                        a.change_password(
+                            &cmd.current_password,
+                            Password::new(&cmd.new_password)?,
+                            hasher,
+                        )
+                    },
+                )?
+            }
+            v1::Commands::RequestPasswordReset(cmd) => self.handle_request_password_reset(cmd)?,
+            v1::Commands::ResetPassword(cmd) => {
+                let id = self.find_by_email(&cmd.email)?.ok_or(InvalidCredentials {
+                    reason: String::from("the reset token is invalid or has expired"),
+                })?;
+                let hasher = self._password_hasher.clone();
+                self.handle_update(id, (cmd, hasher), metadata, |(cmd, hasher), a| {
+                    a.reset_password(
+                        &hash_reset_token(&cmd.token),
+                        Password::new(&cmd.new_password)?,
+                        hasher,
+                    )
+                })?
+            }
+        };
+        Ok(())
+    }
+}
+
+/// Lets the application service be registered on the command bus for each of its commands.
+impl<C: Into<v1::Commands>> ICommandHandler<C> for UserAccountApplicationService {
+    fn handle(&self, command: C, metadata: &CommandMetadata) -> Result<()> {
+        IApplicationService::handle(self, command, metadata)
+    }
+}
+
+fn generate_reset_token() -> String {
+    let mut bytes = [0u8; RESET_TOKEN_BYTES];
+    OsRng.fill_bytes(&mut bytes);
+    to_hex(&bytes)
+}
+
+/// Tokens are stored hashed, so a leaked account store cannot be used to reset passwords.
+fn hash_reset_token(token: &str) -> String {
+    to_hex(&Sha256::digest(token.trim().as_bytes()))
+}
+
+fn to_hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+// ================================================================================
+// Api contracts
+// ================================================================================
+
+#[derive(Object)]
+pub struct AccountV1Register {
+    pub email: String,
+    pub password: String,
+    pub full_name: String,
+    pub display_name: String,
+}
+#[derive(Object)]
+pub struct AccountV1Registered {
+    pub user_id: String,
+}
+#[derive(Object)]
+pub struct AccountV1Login {
+    pub email: String,
+    pub password: String,
+}
+#[derive(Object)]
+pub struct AccountV1AccessToken {
+    pub access_token: String,
+    /// Always `Bearer`
+    pub token_type: String,
+    /// Seconds until the token expires
+    pub expires_in: u64,
+}
+#[derive(Object)]
+pub struct AccountV1ChangePassword {
+    pub current_password: String,
+    pub new_password: String,
+}
+#[derive(Object)]
+pub struct AccountV1RequestPasswordReset {
+    pub email: String,
+}
+#[derive(Object)]
+pub struct AccountV1ResetPassword {
+    pub email: String,
+    /// Token from the password reset mail
+    pub token: String,
+    pub new_password: String,
+}
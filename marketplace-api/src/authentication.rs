@@ -0,0 +1,426 @@
+use std::{
+    fmt, fs,
+    path::Path,
+    str::FromStr,
+    sync::Arc,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use anyhow::{anyhow, Context, Result};
+use jsonwebtoken::{
+    decode, decode_header, encode,
+    jwk::{AlgorithmParameters, EllipticCurve, JwkSet, KeyAlgorithm},
+    Algorithm, DecodingKey, EncodingKey, Header, Validation,
+};
+use marketplace_domain::{user_profile::Role, UserId};
+use poem::{
+    async_trait,
+    http::{header, Method, StatusCode},
+    Endpoint, IntoResponse, Middleware, Request, Response,
+};
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+
+use crate::authorization::IRoleDirectory;
+
+const SECRET_VAR: &str = "MARKETPLACE_JWT_SECRET";
+const JWKS_FILE_VAR: &str = "MARKETPLACE_JWKS_FILE";
+const ISSUER_VAR: &str = "MARKETPLACE_JWT_ISSUER";
+const AUDIENCE_VAR: &str = "MARKETPLACE_JWT_AUDIENCE";
+const SIGNING_KEY_VAR: &str = "MARKETPLACE_JWT_SIGNING_KEY";
+const SIGNING_ALG_VAR: &str = "MARKETPLACE_JWT_SIGNING_ALG";
+const SIGNING_KID_VAR: &str = "MARKETPLACE_JWT_SIGNING_KID";
+
+/// The authenticated caller of a request, available to endpoints as `Data<&Principal>`.
+#[derive(Clone)]
+pub struct Principal {
+    pub user_id: UserId,
+    pub roles: Vec<Role>,
+}
+
+impl Principal {
+    pub fn has_role(&self, role: Role) -> bool {
+        self.roles.iter().any(|held| held.includes(role))
+    }
+}
+
+#[derive(Deserialize)]
+struct Claims {
+    sub: String,
+}
+
+struct VerificationKey {
+    kid: Option<String>,
+    algorithm: Algorithm,
+    key: DecodingKey,
+}
+
+/// Validates bearer tokens against an HS256 secret and/or RS256 and EdDSA
+/// public keys from a JWKS file.
+pub struct JwtAuthenticator {
+    _keys: Vec<VerificationKey>,
+    _issuer: Option<String>,
+    _audience: Option<String>,
+}
+
+impl JwtAuthenticator {
+    pub fn new(issuer: Option<String>, audience: Option<String>) -> Self {
+        Self {
+            _keys: vec![],
+            _issuer: issuer,
+            _audience: audience,
+        }
+    }
+
+    /// Reads `MARKETPLACE_JWT_SECRET` and `MARKETPLACE_JWKS_FILE`; at least one must be set.
+    /// `MARKETPLACE_JWT_ISSUER` and `MARKETPLACE_JWT_AUDIENCE` are checked when present.
+    pub fn from_env() -> Result<Self> {
+        let mut authenticator = Self::new(
+            std::env::var(ISSUER_VAR).ok(),
+            std::env::var(AUDIENCE_VAR).ok(),
+        );
+        if let Ok(secret) = std::env::var(SECRET_VAR) {
+            authenticator = authenticator.with_secret(secret.as_bytes())?;
+        }
+        if let Ok(path) = std::env::var(JWKS_FILE_VAR) {
+            authenticator = authenticator.with_jwks_file(path)?;
+        }
+        if authenticator._keys.is_empty() {
+            return Err(anyhow!(
+                "No JWT signing keys configured, set {SECRET_VAR} or {JWKS_FILE_VAR}"
+            ));
+        }
+        Ok(authenticator)
+    }
+
+    pub fn with_secret(mut self, secret: &[u8]) -> Result<Self> {
+        if secret.is_empty() {
+            return Err(anyhow!("{SECRET_VAR} cannot be empty"));
+        }
+        self._keys.push(VerificationKey {
+            kid: None,
+            algorithm: Algorithm::HS256,
+            key: DecodingKey::from_secret(secret),
+        });
+        Ok(self)
+    }
+
+    pub fn with_jwks_file(mut self, path: impl AsRef<Path>) -> Result<Self> {
+        let path = path.as_ref();
+        let content = fs::read_to_string(path)
+            .with_context(|| format!("Could not read JWKS from {}", path.display()))?;
+        let jwks: JwkSet = serde_json::from_str(&content)
+            .with_context(|| format!("Invalid JWKS in {}", path.display()))?;
+        for jwk in &jwks.keys {
+            let algorithm = match (&jwk.common.key_algorithm, &jwk.algorithm) {
+                (Some(KeyAlgorithm::RS256), _) | (None, AlgorithmParameters::RSA(_)) => {
+                    Algorithm::RS256
+                }
+                (Some(KeyAlgorithm::EdDSA), _) | (None, AlgorithmParameters::OctetKeyPair(_)) => {
+                    Algorithm::EdDSA
+                }
+                (alg, _) => return Err(anyhow!("Unsupported JWKS key algorithm {:?}", alg)),
+            };
+            if let AlgorithmParameters::OctetKeyPair(params) = &jwk.algorithm {
+                if params.curve != EllipticCurve::Ed25519 {
+                    return Err(anyhow!("Only Ed25519 keys are supported for EdDSA"));
+                }
+            }
+            self._keys.push(VerificationKey {
+                kid: jwk.common.key_id.clone(),
+                algorithm,
+                key: DecodingKey::from_jwk(jwk)?,
+            });
+        }
+        Ok(self)
+    }
+
+    pub fn authenticate(&self, token: &str) -> Result<Principal> {
+        let header = decode_header(token)?;
+        let candidates = self._keys.iter().filter(|k| {
+            k.algorithm == header.alg
+                && match (&header.kid, &k.kid) {
+                    (Some(wanted), Some(kid)) => wanted == kid,
+                    _ => true,
+                }
+        });
+        let mut last_error = anyhow!("No key for {:?} tokens is configured", header.alg);
+        for key in candidates {
+            let mut validation = Validation::new(key.algorithm);
+            if let Some(issuer) = &self._issuer {
+                validation.set_issuer(&[issuer]);
+            }
+            match &self._audience {
+                Some(audience) => validation.set_audience(&[audience]),
+                None => validation.validate_aud = false,
+            }
+            match decode::<Claims>(token, &key.key, &validation) {
+                Ok(data) => {
+                    let user_id = Uuid::from_str(&data.claims.sub)
+                        .map_err(|_| anyhow!("Token subject must be a user id"))?;
+                    return Ok(Principal {
+                        user_id: UserId::new(user_id),
+                        roles: vec![Role::User],
+                    });
+                }
+                Err(e) => last_error = e.into(),
+            }
+        }
+        Err(last_error)
+    }
+}
+
+// ================================================================================
+// Issuing
+// ================================================================================
+
+/// Raised when a token is asked for but no signing key is configured.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TokenIssuingUnavailable;
+
+impl fmt::Display for TokenIssuingUnavailable {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "Login is not available, no token signing key is configured"
+        )
+    }
+}
+
+impl std::error::Error for TokenIssuingUnavailable {}
+
+#[derive(Serialize)]
+struct IssuedClaims {
+    sub: String,
+    iat: u64,
+    exp: u64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    iss: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    aud: Option<String>,
+}
+
+/// Signs access tokens for users who log in with a password.
+pub struct JwtIssuer {
+    _header: Header,
+    _key: Option<EncodingKey>,
+    _issuer: Option<String>,
+    _audience: Option<String>,
+    _lifetime: Duration,
+}
+
+impl JwtIssuer {
+    /// An issuer without a key, which refuses to issue tokens.
+    pub fn new(issuer: Option<String>, audience: Option<String>, lifetime: Duration) -> Self {
+        Self {
+            _header: Header::new(Algorithm::HS256),
+            _key: None,
+            _issuer: issuer,
+            _audience: audience,
+            _lifetime: lifetime,
+        }
+    }
+
+    /// Signs with the PEM private key in `MARKETPLACE_JWT_SIGNING_KEY`, using
+    /// `MARKETPLACE_JWT_SIGNING_ALG` (RS256 or EdDSA) and `MARKETPLACE_JWT_SIGNING_KID`,
+    /// or else with `MARKETPLACE_JWT_SECRET`. Without either, login is unavailable.
+    pub fn from_env(lifetime: Duration) -> Result<Self> {
+        let issuer = Self::new(
+            std::env::var(ISSUER_VAR).ok(),
+            std::env::var(AUDIENCE_VAR).ok(),
+            lifetime,
+        );
+        if let Ok(path) = std::env::var(SIGNING_KEY_VAR) {
+            let algorithm = match std::env::var(SIGNING_ALG_VAR).as_deref() {
+                Ok("EdDSA") => Algorithm::EdDSA,
+                Ok("RS256") | Err(_) => Algorithm::RS256,
+                Ok(other) => return Err(anyhow!("{SIGNING_ALG_VAR}: unsupported {other}")),
+            };
+            return issuer.with_private_key_file(
+                path,
+                algorithm,
+                std::env::var(SIGNING_KID_VAR).ok(),
+            );
+        }
+        match std::env::var(SECRET_VAR) {
+            Ok(secret) => Ok(issuer.with_secret(secret.as_bytes())),
+            Err(_) => Ok(issuer),
+        }
+    }
+
+    pub fn with_secret(mut self, secret: &[u8]) -> Self {
+        self._header = Header::new(Algorithm::HS256);
+        self._key = Some(EncodingKey::from_secret(secret));
+        self
+    }
+
+    pub fn with_private_key_file(
+        mut self,
+        path: impl AsRef<Path>,
+        algorithm: Algorithm,
+        kid: Option<String>,
+    ) -> Result<Self> {
+        let path = path.as_ref();
+        let pem = fs::read(path)
+            .with_context(|| format!("Could not read signing key from {}", path.display()))?;
+        let key = match algorithm {
+            Algorithm::RS256 => EncodingKey::from_rsa_pem(&pem)?,
+            Algorithm::EdDSA => EncodingKey::from_ed_pem(&pem)?,
+            other => return Err(anyhow!("Unsupported signing algorithm {:?}", other)),
+        };
+        self._header = Header::new(algorithm);
+        self._header.kid = kid;
+        self._key = Some(key);
+        Ok(self)
+    }
+
+    pub fn lifetime(&self) -> Duration {
+        self._lifetime
+    }
+
+    pub fn issue(&self, user_id: &UserId) -> Result<String> {
+        let key = self._key.as_ref().ok_or(TokenIssuingUnavailable)?;
+        let now = SystemTime::now().duration_since(UNIX_EPOCH)?.as_secs();
+        let claims = IssuedClaims {
+            sub: user_id.value().to_string(),
+            iat: now,
+            exp: now + self._lifetime.as_secs(),
+            iss: self._issuer.clone(),
+            aud: self._audience.clone(),
+        };
+        Ok(encode(&self._header, &claims, key)?)
+    }
+}
+
+// ================================================================================
+// Middleware
+// ================================================================================
+
+/// Authenticates `Authorization: Bearer` tokens. Endpoints used by clients that
+/// cannot set headers, such as browser WebSockets, can also take an `access_token`
+/// query parameter; nowhere else is one read, as URLs end up in logs. Requests
+/// with a valid token get a `Principal` with the caller's current roles; invalid
+/// tokens, and write requests without a token, get a 401 unless the endpoint
+/// allows anonymous callers.
+pub struct JwtAuthentication {
+    _authenticator: Arc<JwtAuthenticator>,
+    _roles: Arc<dyn IRoleDirectory + Send + Sync>,
+    _anonymous: Arc<Vec<(Method, String)>>,
+    _query_token: Arc<Vec<(Method, String)>>,
+}
+
+impl JwtAuthentication {
+    pub fn new(
+        authenticator: JwtAuthenticator,
+        roles: Arc<dyn IRoleDirectory + Send + Sync>,
+    ) -> Self {
+        Self {
+            _authenticator: Arc::new(authenticator),
+            _roles: roles,
+            _anonymous: Arc::new(vec![]),
+            _query_token: Arc::new(vec![]),
+        }
+    }
+
+    /// Lets write requests to `path` through without a token, e.g. to log in.
+    pub fn allow_anonymous(mut self, method: Method, path: &str) -> Self {
+        Arc::make_mut(&mut self._anonymous).push((method, path.to_string()));
+        self
+    }
+
+    /// Reads the token of requests to `path` from the `access_token` query
+    /// parameter when there is no `Authorization` header.
+    pub fn allow_query_token(mut self, method: Method, path: &str) -> Self {
+        Arc::make_mut(&mut self._query_token).push((method, path.to_string()));
+        self
+    }
+}
+
+impl<E: Endpoint> Middleware<E> for JwtAuthentication {
+    type Output = JwtAuthenticationEndpoint<E>;
+
+    fn transform(&self, ep: E) -> Self::Output {
+        JwtAuthenticationEndpoint {
+            _inner: ep,
+            _authenticator: self._authenticator.clone(),
+            _roles: self._roles.clone(),
+            _anonymous: self._anonymous.clone(),
+            _query_token: self._query_token.clone(),
+        }
+    }
+}
+
+pub struct JwtAuthenticationEndpoint<E> {
+    _inner: E,
+    _authenticator: Arc<JwtAuthenticator>,
+    _roles: Arc<dyn IRoleDirectory + Send + Sync>,
+    _anonymous: Arc<Vec<(Method, String)>>,
+    _query_token: Arc<Vec<(Method, String)>>,
+}
+
+impl<E> JwtAuthenticationEndpoint<E> {
+    fn allows_anonymous(&self, req: &Request) -> bool {
+        is_listed(&self._anonymous, req)
+    }
+
+    fn allows_query_token(&self, req: &Request) -> bool {
+        is_listed(&self._query_token, req)
+    }
+}
+
+fn is_listed(routes: &[(Method, String)], req: &Request) -> bool {
+    let path = req.uri().path().trim_end_matches('/');
+    routes
+        .iter()
+        .any(|(method, listed)| method == req.method() && listed == path)
+}
+
+#[async_trait]
+impl<E: Endpoint> Endpoint for JwtAuthenticationEndpoint<E> {
+    type Output = Response;
+
+    async fn call(&self, mut req: Request) -> poem::Result<Self::Output> {
+        let token = req
+            .headers()
+            .get(header::AUTHORIZATION)
+            .and_then(|value| value.to_str().ok())
+            .and_then(|value| value.strip_prefix("Bearer "))
+            .map(|token| token.trim().to_string())
+            .or_else(|| {
+                self.allows_query_token(&req)
+                    .then(|| req.params::<AccessTokenQuery>().ok())
+                    .flatten()
+                    .and_then(|query| query.access_token)
+            });
+        match token {
+            Some(token) => match self._authenticator.authenticate(&token) {
+                Ok(mut principal) => {
+                    principal.roles = self._roles.roles_of(&principal.user_id);
+                    req.extensions_mut().insert(principal);
+                }
+                Err(e) => return Ok(unauthorized(&format!("Invalid bearer token: {}", e))),
+            },
+            None if is_write(req.method()) && !self.allows_anonymous(&req) => {
+                return Ok(unauthorized("A bearer token is required"));
+            }
+            None => {}
+        }
+        Ok(self._inner.call(req).await?.into_response())
+    }
+}
+
+#[derive(Deserialize)]
+struct AccessTokenQuery {
+    access_token: Option<String>,
+}
+
+fn is_write(method: &Method) -> bool {
+    !matches!(*method, Method::GET | Method::HEAD | Method::OPTIONS)
+}
+
+fn unauthorized(message: &str) -> Response {
+    Response::builder()
+        .status(StatusCode::UNAUTHORIZED)
+        .header(header::WWW_AUTHENTICATE, "Bearer")
+        .body(message.to_string())
+}
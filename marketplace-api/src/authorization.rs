@@ -0,0 +1,307 @@
+use std::{
+    collections::{HashMap, HashSet},
+    sync::Arc,
+};
+
+use anyhow::{anyhow, Result};
+use marketplace_contracts::{accounts, categories, classified_ads::v1, user_profiles};
+use marketplace_domain::{
+    user_profile::{Role, UserProfileAggregate},
+    UserId,
+};
+use marketplace_framework::{CommandEnvelope, CommandMetadata, Forbidden, IAuthorizeCommand};
+use poem::{
+    async_trait,
+    http::{header, Method, StatusCode},
+    Endpoint, IntoResponse, Middleware, Request, Response,
+};
+use uuid::Uuid;
+
+use crate::{authentication::Principal, user_profile::UserProfileApplicationService};
+
+const MODERATORS_VAR: &str = "MARKETPLACE_MODERATORS";
+const ADMINS_VAR: &str = "MARKETPLACE_ADMINS";
+
+// ================================================================================
+// Roles
+// ================================================================================
+
+/// Roles given from configuration rather than granted on a profile, so a fresh
+/// deployment has admins to grant the rest. `MARKETPLACE_MODERATORS` and
+/// `MARKETPLACE_ADMINS` are comma separated lists of user ids.
+#[derive(Clone, Default)]
+pub struct ConfiguredRoles {
+    _roles: HashMap<Uuid, HashSet<Role>>,
+}
+
+impl ConfiguredRoles {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn from_env() -> Result<Self> {
+        let mut roles = Self::new();
+        for (var, role) in [(MODERATORS_VAR, Role::Moderator), (ADMINS_VAR, Role::Admin)] {
+            if let Ok(value) = std::env::var(var) {
+                for id in value.split(',').map(str::trim).filter(|id| !id.is_empty()) {
+                    let id = Uuid::parse_str(id).map_err(|e| anyhow!("{var}: {e}"))?;
+                    roles = roles.with(id, role);
+                }
+            }
+        }
+        Ok(roles)
+    }
+
+    pub fn with(mut self, user_id: Uuid, role: Role) -> Self {
+        self._roles.entry(user_id).or_default().insert(role);
+        self
+    }
+
+    pub fn roles_of(&self, user_id: Uuid) -> impl Iterator<Item = Role> + '_ {
+        self._roles.get(&user_id).into_iter().flatten().copied()
+    }
+}
+
+pub trait IRoleDirectory {
+    /// Every role a user holds, including the user role.
+    fn roles_of(&self, user_id: &UserId) -> Vec<Role>;
+}
+
+/// Looks roles up on user profiles, on top of the configured ones. Roles are
+/// read on every request so revocations take effect straight away.
+pub struct RoleDirectory {
+    _configured: ConfiguredRoles,
+    _profiles: UserProfileApplicationService,
+}
+
+impl RoleDirectory {
+    pub fn new(configured: ConfiguredRoles, profiles: UserProfileApplicationService) -> Self {
+        Self {
+            _configured: configured,
+            _profiles: profiles,
+        }
+    }
+}
+
+impl IRoleDirectory for RoleDirectory {
+    fn roles_of(&self, user_id: &UserId) -> Vec<Role> {
+        let mut roles = vec![Role::User];
+        roles.extend(self._configured.roles_of(user_id.value()));
+        if let Ok(profile) = self._profiles.load(user_id.clone()) {
+            roles.extend(profile.roles());
+        }
+        roles.sort();
+        roles.dedup();
+        roles
+    }
+}
+
+// ================================================================================
+// Command policy
+// ================================================================================
+
+/// Policy applied by the command bus before any command is handled.
+#[derive(Default)]
+pub struct CommandPolicy;
+
+impl CommandPolicy {
+    pub fn new() -> Self {
+        Self
+    }
+}
+
+impl IAuthorizeCommand for CommandPolicy {
+    fn authorize(&self, envelope: &CommandEnvelope) -> Result<()> {
+        // Password resets are sent anonymously and proven by the token mailed to the user
+        if envelope
+            .command::<accounts::v1::RequestPasswordReset>()
+            .is_some()
+            || envelope.command::<accounts::v1::ResetPassword>().is_some()
+        {
+            return Ok(());
+        }
+        let principal = require_principal(envelope.metadata)?;
+        if let Some(cmd) = envelope.command::<accounts::v1::RegisterAccount>() {
+            require_self(principal, cmd.user_id)?;
+        }
+        if let Some(cmd) = envelope.command::<accounts::v1::ChangePassword>() {
+            require_self(principal, cmd.user_id)?;
+        }
+        if let Some(cmd) = envelope.command::<user_profiles::v1::RegisterUser>() {
+            require_self(principal, cmd.user_id)?;
+        }
+        if let Some(cmd) = envelope.command::<user_profiles::v1::UpdateFullName>() {
+            require_self(principal, cmd.user_id)?;
+        }
+        if let Some(cmd) = envelope.command::<user_profiles::v1::UpdateDisplayName>() {
+            require_self(principal, cmd.user_id)?;
+        }
+        if let Some(cmd) = envelope.command::<user_profiles::v1::UpdateProfilePhoto>() {
+            require_self(principal, cmd.user_id)?;
+        }
+        if let Some(cmd) = envelope.command::<v1::Create>() {
+            require_owner_or_moderator(principal, cmd.owner_id)?;
+        }
+        if envelope.command::<v1::Approve>().is_some() || envelope.command::<v1::Reject>().is_some()
+        {
+            require_role(principal, Role::Moderator, "Only moderators can review ads")?;
+        }
+        if envelope.command::<user_profiles::v1::GrantRole>().is_some()
+            || envelope
+                .command::<user_profiles::v1::RevokeRole>()
+                .is_some()
+        {
+            require_role(principal, Role::Admin, "Only admins can change roles")?;
+        }
+        if envelope
+            .command::<categories::v1::CreateCategory>()
+            .is_some()
+            || envelope
+                .command::<categories::v1::SetCategoryAttributes>()
+                .is_some()
+            || envelope
+                .command::<categories::v1::RemoveCategory>()
+                .is_some()
+        {
+            require_role(principal, Role::Admin, "Only admins can change categories")?;
+        }
+        Ok(())
+    }
+}
+
+/// The caller a command is sent by, attached to its metadata by the api.
+pub fn require_principal(metadata: &CommandMetadata) -> Result<&Principal> {
+    metadata.get::<Principal>().ok_or_else(|| {
+        Forbidden {
+            reason: String::from("The command has no authenticated caller"),
+        }
+        .into()
+    })
+}
+
+/// Only the owner of an ad, or a moderator, may change it.
+pub fn require_owner_or_moderator(principal: &Principal, owner_id: Uuid) -> Result<()> {
+    if principal.user_id.value() != owner_id && !principal.has_role(Role::Moderator) {
+        return Err(Forbidden {
+            reason: String::from("Only the owner or a moderator can change this ad"),
+        }
+        .into());
+    }
+    Ok(())
+}
+
+fn require_self(principal: &Principal, user_id: Uuid) -> Result<()> {
+    if principal.user_id.value() != user_id {
+        return Err(Forbidden {
+            reason: String::from("Users can only change their own account and profile"),
+        }
+        .into());
+    }
+    Ok(())
+}
+
+fn require_role(principal: &Principal, role: Role, reason: &str) -> Result<()> {
+    if !principal.has_role(role) {
+        return Err(Forbidden {
+            reason: reason.to_string(),
+        }
+        .into());
+    }
+    Ok(())
+}
+
+// ================================================================================
+// Endpoint policy
+// ================================================================================
+
+struct EndpointRule {
+    method: Method,
+    path: Vec<String>,
+    role: Role,
+}
+
+impl EndpointRule {
+    /// Path segments starting with `:` match any value.
+    fn matches(&self, method: &Method, path: &str) -> bool {
+        let segments: Vec<&str> = path.trim_end_matches('/').split('/').collect();
+        self.method == *method
+            && self.path.len() == segments.len()
+            && self
+                .path
+                .iter()
+                .zip(segments)
+                .all(|(rule, segment)| rule.starts_with(':') || rule == segment)
+    }
+}
+
+/// Declares the role each endpoint requires. Requests to a listed endpoint are
+/// turned away before reaching it unless the `Principal` holds the role;
+/// it must run inside `JwtAuthentication`.
+#[derive(Clone, Default)]
+pub struct EndpointPolicy {
+    _rules: Arc<Vec<EndpointRule>>,
+}
+
+impl EndpointPolicy {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn require(mut self, method: Method, path: &str, role: Role) -> Self {
+        Arc::get_mut(&mut self._rules)
+            .expect("Endpoint rules are declared before the policy is used")
+            .push(EndpointRule {
+                method,
+                path: path.split('/').map(String::from).collect(),
+                role,
+            });
+        self
+    }
+}
+
+impl<E: Endpoint> Middleware<E> for EndpointPolicy {
+    type Output = EndpointPolicyEndpoint<E>;
+
+    fn transform(&self, ep: E) -> Self::Output {
+        EndpointPolicyEndpoint {
+            _inner: ep,
+            _rules: self._rules.clone(),
+        }
+    }
+}
+
+pub struct EndpointPolicyEndpoint<E> {
+    _inner: E,
+    _rules: Arc<Vec<EndpointRule>>,
+}
+
+#[async_trait]
+impl<E: Endpoint> Endpoint for EndpointPolicyEndpoint<E> {
+    type Output = Response;
+
+    async fn call(&self, req: Request) -> poem::Result<Self::Output> {
+        let required = self
+            ._rules
+            .iter()
+            .filter(|rule| rule.matches(req.method(), req.uri().path()))
+            .map(|rule| rule.role)
+            .max();
+        if let Some(role) = required {
+            match req.extensions().get::<Principal>() {
+                Some(principal) if principal.has_role(role) => {}
+                Some(_) => {
+                    return Ok(Response::builder()
+                        .status(StatusCode::FORBIDDEN)
+                        .body(format!("Forbidden: the {} role is required", role)))
+                }
+                None => {
+                    return Ok(Response::builder()
+                        .status(StatusCode::UNAUTHORIZED)
+                        .header(header::WWW_AUTHENTICATE, "Bearer")
+                        .body("A bearer token is required"))
+                }
+            }
+        }
+        Ok(self._inner.call(req).await?.into_response())
+    }
+}
@@ -0,0 +1,76 @@
+use std::{fs, path::PathBuf};
+
+use anyhow::{anyhow, Result};
+use sha2::{Digest, Sha256};
+
+/// Somewhere to keep uploaded files such as ad pictures.
+pub trait IBlobStore: Send + Sync {
+    /// Stores the content under `key`, returning the URL it can be fetched from.
+    fn put(&self, key: &str, content: &[u8]) -> Result<String>;
+    fn get(&self, key: &str) -> Result<Vec<u8>>;
+    fn delete(&self, key: &str) -> Result<()>;
+    /// The key of a URL returned by `put`, if it points into this store.
+    fn key_of(&self, url: &str) -> Option<String>;
+}
+
+/// Keeps blobs as files under a root directory, served by the API under `base_url`.
+pub struct LocalFileBlobStore {
+    _root: PathBuf,
+    _base_url: String,
+}
+
+impl LocalFileBlobStore {
+    pub fn new(root: impl Into<PathBuf>, base_url: impl Into<String>) -> Result<Self> {
+        let root = root.into();
+        fs::create_dir_all(&root)?;
+        Ok(Self {
+            _root: root,
+            _base_url: base_url.into(),
+        })
+    }
+
+    pub fn root(&self) -> PathBuf {
+        self._root.clone()
+    }
+
+    fn path(&self, key: &str) -> Result<PathBuf> {
+        if key.split('/').any(|part| part.is_empty() || part == "..") {
+            return Err(anyhow!("Invalid blob key {}", key));
+        }
+        Ok(self._root.join(key))
+    }
+}
+
+impl IBlobStore for LocalFileBlobStore {
+    fn put(&self, key: &str, content: &[u8]) -> Result<String> {
+        let path = self.path(key)?;
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        fs::write(path, content)?;
+        Ok(format!("{}/{}", self._base_url.trim_end_matches('/'), key))
+    }
+
+    fn get(&self, key: &str) -> Result<Vec<u8>> {
+        Ok(fs::read(self.path(key)?)?)
+    }
+
+    fn delete(&self, key: &str) -> Result<()> {
+        Ok(fs::remove_file(self.path(key)?)?)
+    }
+
+    fn key_of(&self, url: &str) -> Option<String> {
+        url.strip_prefix(self._base_url.trim_end_matches('/'))?
+            .strip_prefix('/')
+            .map(str::to_string)
+    }
+}
+
+/// Hex SHA-256 of a blob's content. Uploads put it in their keys so that a
+/// retried upload with different bytes never overwrites what is already stored.
+pub fn content_digest(content: &[u8]) -> String {
+    Sha256::digest(content)
+        .iter()
+        .map(|b| format!("{:02x}", b))
+        .collect()
+}
@@ -0,0 +1,564 @@
+use std::{
+    collections::{BTreeMap, HashMap, HashSet},
+    str::FromStr,
+    sync::{Arc, RwLock},
+};
+
+use anyhow::{anyhow, Result};
+use futures_util::StreamExt;
+use marketplace_contracts::{categories, classified_ads::v1};
+use marketplace_domain::{
+    category::{AttributeDefinition, AttributeType, AttributeValue, CategoryId, CategorySchema},
+    classified_ad_events::ClassifiedAdEvents,
+    ICategoryLookup,
+};
+use marketplace_framework::{CommandMetadata, EntityNotFound, ICommandHandler};
+use poem_openapi::Object;
+use rust_decimal::{prelude::ToPrimitive, Decimal};
+use uuid::Uuid;
+
+use crate::{
+    event_stream::{ClassifiedAdEventLog, RecordedEvent},
+    traits::IApplicationService,
+};
+
+const CATEGORY_NAME_MAX_LENGTH: usize = 50;
+/// Longest value of a text attribute when the category does not say.
+const DEFAULT_TEXT_MAX_LENGTH: usize = 200;
+
+// ================================================================================
+// Category tree
+// ================================================================================
+
+#[derive(Clone)]
+struct Category {
+    id: Uuid,
+    parent_id: Option<Uuid>,
+    name: String,
+    attributes: Vec<AttributeDefinition>,
+}
+
+/// The categories ads are placed in, managed by admins. A category inherits the
+/// attributes of the categories above it, so an attribute name is used once
+/// along each branch. Which category each ad is in is kept up to date from the
+/// ad event log, so categories in use are not removed.
+#[derive(Default)]
+pub struct CategoryTree {
+    _categories: RwLock<HashMap<Uuid, Category>>,
+    _ads: RwLock<HashMap<Uuid, Uuid>>,
+}
+
+fn not_found(id: Uuid) -> anyhow::Error {
+    EntityNotFound { id: id.to_string() }.into()
+}
+
+impl CategoryTree {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Follows the event log from the start for as long as it is open.
+    pub async fn run(self: Arc<Self>, log: Arc<ClassifiedAdEventLog>) {
+        let mut events = Box::pin(log.subscribe_all(0));
+        while let Some(event) = events.next().await {
+            self.when(&event);
+        }
+    }
+
+    fn when(&self, recorded: &RecordedEvent) {
+        if let ClassifiedAdEvents::CategoryChanged(e) = &recorded.event {
+            self._ads.write().unwrap().insert(e.id, e.category_id);
+        }
+    }
+
+    pub fn add(
+        &self,
+        id: Uuid,
+        parent_id: Option<Uuid>,
+        name: &str,
+        attributes: Vec<AttributeDefinition>,
+    ) -> Result<()> {
+        let name = name.trim();
+        if name.is_empty() || name.chars().count() > CATEGORY_NAME_MAX_LENGTH {
+            return Err(anyhow!(
+                "Category name must be between 1 and {} characters",
+                CATEGORY_NAME_MAX_LENGTH
+            ));
+        }
+        let mut categories = self._categories.write().unwrap();
+        if categories.contains_key(&id) {
+            return Err(anyhow!("Category with this ID already exists"));
+        }
+        if let Some(parent_id) = parent_id {
+            if !categories.contains_key(&parent_id) {
+                return Err(not_found(parent_id));
+            }
+        }
+        if categories
+            .values()
+            .any(|c| c.parent_id == parent_id && c.name.eq_ignore_ascii_case(name))
+        {
+            return Err(anyhow!("There already is a category named {} here", name));
+        }
+        categories.insert(
+            id,
+            Category {
+                id,
+                parent_id,
+                name: name.to_string(),
+                attributes,
+            },
+        );
+        if let Err(e) = schema_of(&categories, id) {
+            categories.remove(&id);
+            return Err(e);
+        }
+        Ok(())
+    }
+
+    /// Replaces the attributes of a category. Ads keep the values they were given
+    /// until they are next placed in a category.
+    pub fn set_attributes(&self, id: Uuid, attributes: Vec<AttributeDefinition>) -> Result<()> {
+        let mut categories = self._categories.write().unwrap();
+        let category = categories.get_mut(&id).ok_or_else(|| not_found(id))?;
+        let previous = std::mem::replace(&mut category.attributes, attributes);
+        // Every category below inherits the new attributes, so check their schemas too
+        for below in subtree_of(&categories, id) {
+            if let Err(e) = schema_of(&categories, below) {
+                categories.get_mut(&id).unwrap().attributes = previous;
+                return Err(e);
+            }
+        }
+        Ok(())
+    }
+
+    /// Removes a category with no categories below it and no ads in it.
+    pub fn remove(&self, id: Uuid) -> Result<()> {
+        let mut categories = self._categories.write().unwrap();
+        if !categories.contains_key(&id) {
+            return Err(not_found(id));
+        }
+        if categories.values().any(|c| c.parent_id == Some(id)) {
+            return Err(anyhow!(
+                "Remove the categories below this one before removing it"
+            ));
+        }
+        let ads_in_category = self
+            ._ads
+            .read()
+            .unwrap()
+            .values()
+            .filter(|category_id| **category_id == id)
+            .count();
+        if ads_in_category > 0 {
+            return Err(anyhow!(
+                "This category still has {} ads; move them to another one before removing it",
+                ads_in_category
+            ));
+        }
+        categories.remove(&id);
+        Ok(())
+    }
+
+    /// The category and every category below it.
+    pub fn subtree(&self, id: Uuid) -> Result<HashSet<Uuid>> {
+        let categories = self._categories.read().unwrap();
+        if !categories.contains_key(&id) {
+            return Err(not_found(id));
+        }
+        Ok(subtree_of(&categories, id))
+    }
+
+    pub fn details(&self, id: Uuid) -> Result<CategoryV1Details> {
+        let categories = self._categories.read().unwrap();
+        details_of(&categories, id)
+    }
+
+    /// Every category, ordered by path so each comes after its parent.
+    pub fn list(&self) -> Vec<CategoryV1Details> {
+        let categories = self._categories.read().unwrap();
+        let mut list: Vec<CategoryV1Details> = categories
+            .keys()
+            .filter_map(|id| details_of(&categories, *id).ok())
+            .collect();
+        list.sort_by(|a, b| a.path.cmp(&b.path));
+        list
+    }
+}
+
+impl ICategoryLookup for CategoryTree {
+    fn category_schema(&self, category_id: CategoryId) -> Result<CategorySchema> {
+        let categories = self._categories.read().unwrap();
+        schema_of(&categories, category_id.value())
+    }
+}
+
+/// The category followed by its ancestors, nearest first.
+fn ancestry(categories: &HashMap<Uuid, Category>, id: Uuid) -> Result<Vec<&Category>> {
+    let mut chain = vec![];
+    let mut next = Some(id);
+    while let Some(id) = next {
+        let category = categories.get(&id).ok_or_else(|| not_found(id))?;
+        chain.push(category);
+        next = category.parent_id;
+    }
+    Ok(chain)
+}
+
+fn schema_of(categories: &HashMap<Uuid, Category>, id: Uuid) -> Result<CategorySchema> {
+    let attributes = ancestry(categories, id)?
+        .into_iter()
+        .rev()
+        .flat_map(|c| c.attributes.iter().cloned())
+        .collect();
+    CategorySchema::new(CategoryId::new(id), attributes)
+}
+
+fn subtree_of(categories: &HashMap<Uuid, Category>, id: Uuid) -> HashSet<Uuid> {
+    let mut subtree = HashSet::from([id]);
+    let mut frontier = vec![id];
+    while let Some(parent) = frontier.pop() {
+        for child in categories.values().filter(|c| c.parent_id == Some(parent)) {
+            if subtree.insert(child.id) {
+                frontier.push(child.id);
+            }
+        }
+    }
+    subtree
+}
+
+fn details_of(categories: &HashMap<Uuid, Category>, id: Uuid) -> Result<CategoryV1Details> {
+    let chain = ancestry(categories, id)?;
+    let category = chain[0];
+    let path: Vec<&str> = chain.iter().rev().map(|c| c.name.as_str()).collect();
+    let schema = schema_of(categories, id)?;
+    Ok(CategoryV1Details {
+        id: category.id.to_string(),
+        parent_id: category.parent_id.map(|id| id.to_string()),
+        name: category.name.clone(),
+        path: path.join(" / "),
+        attributes: schema
+            .attributes()
+            .iter()
+            .map(CategoryV1Attribute::from)
+            .collect(),
+    })
+}
+
+// ================================================================================
+// Application service
+// ================================================================================
+
+/// Handles the commands admins send to change the category tree.
+#[derive(Clone)]
+pub struct CategoryApplicationService {
+    _categories: Arc<CategoryTree>,
+}
+
+impl CategoryApplicationService {
+    pub fn new(categories: Arc<CategoryTree>) -> Self {
+        Self {
+            _categories: categories,
+        }
+    }
+}
+
+impl IApplicationService for CategoryApplicationService {
+    type Command = categories::v1::Commands;
+    fn handle(&self, command: impl Into<Self::Command>, _: &CommandMetadata) -> Result<()> {
+        match command.into() {
+            categories::v1::Commands::CreateCategory(cmd) => self._categories.add(
+                cmd.id,
+                cmd.parent_id,
+                &cmd.name,
+                definitions_from_contract(cmd.attributes)?,
+            ),
+            categories::v1::Commands::SetCategoryAttributes(cmd) => self
+                ._categories
+                .set_attributes(cmd.id, definitions_from_contract(cmd.attributes)?),
+            categories::v1::Commands::RemoveCategory(cmd) => self._categories.remove(cmd.id),
+        }
+    }
+}
+
+impl<C: Into<categories::v1::Commands>> ICommandHandler<C> for CategoryApplicationService {
+    fn handle(&self, command: C, metadata: &CommandMetadata) -> Result<()> {
+        IApplicationService::handle(self, command, metadata)
+    }
+}
+
+fn definitions_from_contract(
+    attributes: Vec<categories::v1::AttributeDefinition>,
+) -> Result<Vec<AttributeDefinition>> {
+    attributes
+        .into_iter()
+        .map(|attribute| {
+            let kind = match attribute.kind {
+                categories::v1::AttributeType::Number { min, max, unit } => {
+                    AttributeType::Number { min, max, unit }
+                }
+                categories::v1::AttributeType::Text { max_length } => {
+                    AttributeType::Text { max_length }
+                }
+                categories::v1::AttributeType::Choice { options } => {
+                    AttributeType::Choice { options }
+                }
+                categories::v1::AttributeType::Boolean => AttributeType::Boolean,
+            };
+            AttributeDefinition::new(&attribute.name, kind, attribute.required)
+        })
+        .collect()
+}
+
+// ================================================================================
+// Attribute values
+// ================================================================================
+
+/// Reads an attribute value sent as JSON: a number, a string or a boolean.
+pub fn attribute_from_json(name: &str, value: &serde_json::Value) -> Result<v1::AttributeValue> {
+    match value {
+        serde_json::Value::Number(number) => Decimal::from_str(&number.to_string())
+            .map(v1::AttributeValue::Number)
+            .map_err(|_| anyhow!("{} is not a number that can be stored", name)),
+        serde_json::Value::String(text) => Ok(v1::AttributeValue::Text(text.clone())),
+        serde_json::Value::Bool(value) => Ok(v1::AttributeValue::Boolean(*value)),
+        _ => Err(anyhow!("{} must be a number, a string or a boolean", name)),
+    }
+}
+
+pub fn attribute_to_json(value: &AttributeValue) -> serde_json::Value {
+    match value {
+        AttributeValue::Number(number) => number
+            .to_f64()
+            .map(serde_json::Value::from)
+            .unwrap_or_default(),
+        AttributeValue::Text(text) => serde_json::Value::from(text.as_str()),
+        AttributeValue::Boolean(value) => serde_json::Value::from(*value),
+    }
+}
+
+pub fn attributes_to_json(
+    values: &BTreeMap<String, AttributeValue>,
+) -> BTreeMap<String, serde_json::Value> {
+    values
+        .iter()
+        .map(|(name, value)| (name.clone(), attribute_to_json(value)))
+        .collect()
+}
+
+pub fn attribute_from_contract(value: v1::AttributeValue) -> AttributeValue {
+    match value {
+        v1::AttributeValue::Number(number) => AttributeValue::Number(number),
+        v1::AttributeValue::Text(text) => AttributeValue::Text(text),
+        v1::AttributeValue::Boolean(value) => AttributeValue::Boolean(value),
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Comparison {
+    Equal,
+    AtLeast,
+    AtMost,
+}
+
+/// A condition on an attribute in a listing filter: `name=value`, `name>=number`
+/// or `name<=number`.
+#[derive(Debug, Clone)]
+pub struct AttributeCondition {
+    name: String,
+    comparison: Comparison,
+    value: String,
+}
+
+impl AttributeCondition {
+    /// Parses comma separated conditions, e.g. `mileage<=100000,fuel=diesel`.
+    pub fn parse_list(conditions: &str) -> Result<Vec<Self>> {
+        conditions
+            .split(',')
+            .map(str::trim)
+            .filter(|c| !c.is_empty())
+            .map(Self::from_str)
+            .collect()
+    }
+
+    pub fn matches(&self, values: &BTreeMap<String, AttributeValue>) -> bool {
+        let Some(value) = values.get(&self.name) else {
+            return false;
+        };
+        match (self.comparison, value) {
+            (Comparison::Equal, AttributeValue::Number(number)) => {
+                Decimal::from_str(&self.value).is_ok_and(|wanted| *number == wanted)
+            }
+            (Comparison::Equal, AttributeValue::Text(text)) => {
+                text.eq_ignore_ascii_case(&self.value)
+            }
+            (Comparison::Equal, AttributeValue::Boolean(value)) => {
+                bool::from_str(&self.value).is_ok_and(|wanted| *value == wanted)
+            }
+            (Comparison::AtLeast, AttributeValue::Number(number)) => {
+                Decimal::from_str(&self.value).is_ok_and(|bound| *number >= bound)
+            }
+            (Comparison::AtMost, AttributeValue::Number(number)) => {
+                Decimal::from_str(&self.value).is_ok_and(|bound| *number <= bound)
+            }
+            _ => false,
+        }
+    }
+}
+
+impl FromStr for AttributeCondition {
+    type Err = anyhow::Error;
+
+    fn from_str(condition: &str) -> Result<Self> {
+        let (name, comparison, value) = if let Some((name, value)) = condition.split_once(">=") {
+            (name, Comparison::AtLeast, value)
+        } else if let Some((name, value)) = condition.split_once("<=") {
+            (name, Comparison::AtMost, value)
+        } else if let Some((name, value)) = condition.split_once('=') {
+            (name, Comparison::Equal, value)
+        } else {
+            return Err(anyhow!(
+                "Attribute filter {} must be name=value, name>=number or name<=number",
+                condition
+            ));
+        };
+        let value = value.trim();
+        if comparison != Comparison::Equal && Decimal::from_str(value).is_err() {
+            return Err(anyhow!(
+                "{} can only be compared with a number",
+                name.trim()
+            ));
+        }
+        Ok(Self {
+            name: name.trim().to_string(),
+            comparison,
+            value: value.to_string(),
+        })
+    }
+}
+
+// ================================================================================
+// DTOs
+// ================================================================================
+
+#[derive(Object)]
+pub struct CategoryV1Attribute {
+    /// Lowercase letters, digits and underscores, e.g. "mileage"
+    pub name: String,
+    /// number, text, choice or boolean
+    #[oai(rename = "type")]
+    pub kind: String,
+    #[oai(default)]
+    pub required: bool,
+    /// Smallest number allowed
+    pub min: Option<f64>,
+    /// Largest number allowed
+    pub max: Option<f64>,
+    /// Unit of a number, e.g. "km"
+    pub unit: Option<String>,
+    /// Longest text allowed, 200 characters unless given
+    pub max_length: Option<u32>,
+    /// Values a choice can take
+    pub options: Option<Vec<String>>,
+}
+
+impl CategoryV1Attribute {
+    pub fn into_definition(self) -> Result<categories::v1::AttributeDefinition> {
+        let decimal = |value: Option<f64>| value.map(Decimal::try_from).transpose();
+        let kind = match self.kind.as_str() {
+            "number" => categories::v1::AttributeType::Number {
+                min: decimal(self.min)?,
+                max: decimal(self.max)?,
+                unit: self.unit,
+            },
+            "text" => categories::v1::AttributeType::Text {
+                max_length: self
+                    .max_length
+                    .map_or(DEFAULT_TEXT_MAX_LENGTH, |length| length as usize),
+            },
+            "choice" => categories::v1::AttributeType::Choice {
+                options: self.options.unwrap_or_default(),
+            },
+            "boolean" => categories::v1::AttributeType::Boolean,
+            other => {
+                return Err(anyhow!(
+                    "Attribute type {} is not number, text, choice or boolean",
+                    other
+                ))
+            }
+        };
+        Ok(categories::v1::AttributeDefinition {
+            name: self.name,
+            kind,
+            required: self.required,
+        })
+    }
+}
+
+impl From<&AttributeDefinition> for CategoryV1Attribute {
+    fn from(definition: &AttributeDefinition) -> Self {
+        let mut attribute = Self {
+            name: definition.name.clone(),
+            kind: definition.kind.type_name().to_string(),
+            required: definition.required,
+            min: None,
+            max: None,
+            unit: None,
+            max_length: None,
+            options: None,
+        };
+        match &definition.kind {
+            AttributeType::Number { min, max, unit } => {
+                attribute.min = min.and_then(|min| min.to_f64());
+                attribute.max = max.and_then(|max| max.to_f64());
+                attribute.unit = unit.clone();
+            }
+            AttributeType::Text { max_length } => attribute.max_length = Some(*max_length as u32),
+            AttributeType::Choice { options } => attribute.options = Some(options.clone()),
+            AttributeType::Boolean => {}
+        }
+        attribute
+    }
+}
+
+pub fn into_definitions(
+    attributes: Vec<CategoryV1Attribute>,
+) -> Result<Vec<categories::v1::AttributeDefinition>> {
+    attributes
+        .into_iter()
+        .map(CategoryV1Attribute::into_definition)
+        .collect()
+}
+
+#[derive(Object)]
+pub struct CategoryV1Create {
+    pub id: String,
+    /// Category to place this one under; a top level category when missing
+    pub parent_id: Option<String>,
+    pub name: String,
+    /// Attributes on top of those inherited from the parent
+    #[oai(default)]
+    pub attributes: Vec<CategoryV1Attribute>,
+}
+
+#[derive(Object)]
+pub struct CategoryV1SetAttributes {
+    pub id: String,
+    pub attributes: Vec<CategoryV1Attribute>,
+}
+
+#[derive(Object)]
+pub struct CategoryV1Remove {
+    pub id: String,
+}
+
+#[derive(Object)]
+pub struct CategoryV1Details {
+    pub id: String,
+    pub parent_id: Option<String>,
+    pub name: String,
+    /// Names from the top level category down, e.g. "Vehicles / Cars"
+    pub path: String,
+    /// Every attribute ads in the category can have, inherited ones first
+    pub attributes: Vec<CategoryV1Attribute>,
+}
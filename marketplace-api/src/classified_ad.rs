@@ -1,14 +1,35 @@
 use std::{
-    collections::HashMap,
+    collections::{BTreeMap, HashMap},
+    str::FromStr,
     sync::{Arc, Mutex},
 };
 
 use anyhow::{anyhow, Result};
+use chrono::{DateTime, Utc};
 use marketplace_contracts::classified_ads::v1::{self};
-use marketplace_domain::{classified_ad::*, UserId};
-use poem_openapi::Object;
+use marketplace_domain::{
+    category::{AdAttributes, CategoryId},
+    classified_ad::*,
+    classified_ad_events::ClassifiedAdCreated,
+    currency_lookup::Iso4217CurrencyLookup,
+    location::Location,
+    picture::{PictureId, PictureSize},
+    CurrencyCode, ExchangeRate, ICategoryLookup, ICurrencyLookup, ITextModeration, Money, Price,
+    UserId,
+};
+use marketplace_framework::{
+    AggregateRoot, CommandMetadata, ConcurrencyConflict, EntityNotFound, ICommandHandler,
+};
+use poem_openapi::{types::multipart::Upload, Multipart, Object};
+use rust_decimal::prelude::ToPrimitive;
 
-use crate::traits::{IApplicationService, IEntityStore, IHandleCommand};
+use crate::{
+    authorization::{require_owner_or_moderator, require_principal},
+    categories::{attribute_from_contract, attributes_to_json, CategoryTree},
+    event_stream::ClassifiedAdEventLog,
+    text_moderation::WordListTextModeration,
+    traits::{IApplicationService, IEntityStore, IHandleCommand},
+};
 
 pub struct ClassifiedAdStore {
     _store: HashMap<String, ClassifiedAd>,
@@ -21,21 +42,40 @@ impl ClassifiedAdStore {
         }
     }
 }
+
+impl Default for ClassifiedAdStore {
+    fn default() -> Self {
+        Self::new()
+    }
+}
 impl IEntityStore for ClassifiedAdStore {
     type Entity = ClassifiedAd;
 
-    fn save(&mut self, ad: ClassifiedAd) -> Result<()> {
-        self._store.insert(ad.id()?.value().to_string(), ad);
+    fn save(&mut self, ad: ClassifiedAd, expected_version: Option<i64>) -> Result<()> {
+        let id = ad.id()?.value().to_string();
+        match (expected_version, self._store.get(&id)) {
+            (None, Some(_)) => return Err(anyhow!("Classified Ad with this ID Already exists")),
+            (Some(expected_version), Some(stored)) if stored.version() != expected_version => {
+                return Err(ConcurrencyConflict {
+                    expected_version,
+                    actual_version: stored.version(),
+                }
+                .into());
+            }
+            _ => {}
+        }
+        self._store.insert(id, ad);
         Ok(())
     }
 
     fn exists(&self, id: String) -> bool {
-        let exists = self._store.get(&id).is_some();
-        exists
+        self._store.contains_key(&id)
     }
-    fn load(&self, id: String) -> ClassifiedAd {
-        let ad = self._store.get(&id).unwrap();
-        ad.clone()
+    fn load(&self, id: String) -> Result<ClassifiedAd> {
+        match self._store.get(&id) {
+            Some(ad) => Ok(ad.clone()),
+            None => Err(EntityNotFound { id }.into()),
+        }
     }
 }
 
@@ -57,8 +97,13 @@ impl IHandleCommand for CreateClassifiedAdHandler {
         let classified_ad = ClassifiedAd::new(
             ClassifiedAdId::new(command.id),
             UserId::new(command.owner_id),
+            Arc::new(Iso4217CurrencyLookup::bundled()),
         );
-        self._store.clone().lock().unwrap().save(classified_ad)?;
+        self._store
+            .clone()
+            .lock()
+            .unwrap()
+            .save(classified_ad, None)?;
         Ok(())
     }
 }
@@ -75,69 +120,216 @@ impl ClassifiedAdsCommandApi {
     }
 }
 
+impl Default for ClassifiedAdsCommandApi {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 #[derive(Clone)]
 pub struct ClassifiedAdsApplicationService {
     _api: ClassifiedAdsCommandApi,
     _repository: Arc<Mutex<dyn IEntityStore<Entity = ClassifiedAd>>>,
+    _currency_lookup: Arc<dyn ICurrencyLookup + Send + Sync>,
+    _text_moderation: Arc<dyn ITextModeration + Send + Sync>,
+    _category_lookup: Arc<dyn ICategoryLookup + Send + Sync>,
+    _event_log: Arc<ClassifiedAdEventLog>,
 }
 
 impl ClassifiedAdsApplicationService {
-    pub fn new() -> Self {
+    pub fn new(
+        currency_lookup: Arc<dyn ICurrencyLookup + Send + Sync>,
+        text_moderation: Arc<dyn ITextModeration + Send + Sync>,
+        category_lookup: Arc<dyn ICategoryLookup + Send + Sync>,
+        event_log: Arc<ClassifiedAdEventLog>,
+    ) -> Self {
         Self {
             _api: ClassifiedAdsCommandApi::new(),
             _repository: Arc::new(Mutex::new(ClassifiedAdStore::new())),
+            _currency_lookup: currency_lookup,
+            _text_moderation: text_moderation,
+            _category_lookup: category_lookup,
+            _event_log: event_log,
         }
     }
+}
+
+impl Default for ClassifiedAdsApplicationService {
+    fn default() -> Self {
+        Self::new(
+            Arc::new(Iso4217CurrencyLookup::bundled()),
+            Arc::new(WordListTextModeration::bundled()),
+            Arc::new(CategoryTree::new()),
+            Arc::new(ClassifiedAdEventLog::new()),
+        )
+    }
+}
+
+impl ClassifiedAdsApplicationService {
+    /// Loads the current state of an ad for queries.
+    pub fn load(&self, id: ClassifiedAdId) -> Result<ClassifiedAd> {
+        self._repository
+            .lock()
+            .unwrap()
+            .load(id.value().to_string())
+    }
+
     fn handle_create(&self, cmd: v1::Create) -> Result<()> {
-        if self._repository.lock().unwrap().exists(cmd.id.to_string()) {
-            return Err(anyhow!("Classified Ad with this ID Already exists"));
-        }
-        let classified_ad =
-            ClassifiedAd::new(ClassifiedAdId::new(cmd.id), UserId::new(cmd.owner_id));
-        self._repository.lock().unwrap().save(classified_ad)?;
+        let classified_ad = ClassifiedAd::new(
+            ClassifiedAdId::new(cmd.id),
+            UserId::new(cmd.owner_id),
+            self._currency_lookup.clone(),
+        );
+        // Saving fails if the id is taken, so a second create never reaches the log
+        let mut repository = self._repository.lock().unwrap();
+        repository.save(classified_ad, None)?;
+        // A new ad is built rather than applying an event, so its creation is recorded here
+        let created = ClassifiedAdCreated {
+            id: cmd.id,
+            owner_id: cmd.owner_id,
+        };
+        self._event_log.append(cmd.id, 0, &[created.into()]);
         Ok(())
     }
     fn handle_update<Cmd>(
         &self,
         id: ClassifiedAdId,
         cmd: Cmd,
+        metadata: &CommandMetadata,
         operation: fn(cmd: Cmd, c: &mut ClassifiedAd) -> Result<()>,
     ) -> Result<()> {
-        let mut classified_ad = self
-            ._repository
-            .lock()
-            .unwrap()
-            .load(id.value().to_string());
+        let principal = require_principal(metadata)?;
+        let mut classified_ad = self.load(id)?;
+        let owner_id = classified_ad
+            .owner_id()
+            .ok_or_else(|| anyhow!("Classified ad has no owner"))?;
+        require_owner_or_moderator(principal, owner_id.value())?;
+        let loaded_version = classified_ad.version();
+        metadata.check_version(loaded_version)?;
         operation(cmd, &mut classified_ad)?;
-        self._repository.lock().unwrap().save(classified_ad)?;
+        let new_events = classified_ad.changes()[loaded_version as usize..].to_vec();
+        let mut repository = self._repository.lock().unwrap();
+        repository.save(classified_ad, Some(loaded_version))?;
+        self._event_log
+            .append(id.value(), loaded_version + 1, &new_events);
         Ok(())
     }
 }
 
 impl IApplicationService for ClassifiedAdsApplicationService {
     type Command = v1::Commands;
-    fn handle(&self, command: impl Into<Self::Command>) -> Result<()> {
+    fn handle(&self, command: impl Into<Self::Command>, metadata: &CommandMetadata) -> Result<()> {
         match command.into() {
             v1::Commands::Create(cmd) => self.handle_create(cmd)?,
             v1::Commands::SetTitle(cmd) => {
-                self.handle_update(ClassifiedAdId::new(cmd.id), cmd, |cmd, c| {
-                    c.set_title(cmd.title).expect("Could not set title");
-                    Ok(())
+                let title = ClassifiedAdTitle::new(cmd.title, self._text_moderation.clone())?;
+                self.handle_update(ClassifiedAdId::new(cmd.id), title, metadata, |title, c| {
+                    c.set_title(title)
                 })?;
             }
             v1::Commands::UpdateText(cmd) => {
-                self.handle_update(ClassifiedAdId::new(cmd.id), cmd, |cmd, c| {
-                    c.set_text(cmd.text).expect("Could not set text");
-                    Ok(())
+                let text = ClassifiedAdText::new(cmd.text, self._text_moderation.clone())?;
+                self.handle_update(ClassifiedAdId::new(cmd.id), text, metadata, |text, c| {
+                    c.set_text(text)
+                })?
+            }
+            v1::Commands::UpdatePrice(cmd) => {
+                let currency = CurrencyCode::from_str(&cmd.currency)?;
+                let price =
+                    Price::from_decimal(cmd.price, Some(currency), self._currency_lookup.clone())?;
+                self.handle_update(ClassifiedAdId::new(cmd.id), price, metadata, |price, c| {
+                    c.update_price(price)
+                })?
+            }
+            v1::Commands::ChangeCategory(cmd) => {
+                let category_id = CategoryId::new(cmd.category_id);
+                let schema = self._category_lookup.category_schema(category_id)?;
+                let values = cmd
+                    .attributes
+                    .into_iter()
+                    .map(|(name, value)| (name, attribute_from_contract(value)))
+                    .collect();
+                let attributes = AdAttributes::new(values, &schema)?;
+                self.handle_update(
+                    ClassifiedAdId::new(cmd.id),
+                    (category_id, attributes),
+                    metadata,
+                    |(category_id, attributes), c| c.change_category(category_id, attributes),
+                )?
+            }
+            v1::Commands::SetLocation(cmd) => {
+                let location = Location::new(cmd.latitude, cmd.longitude, &cmd.postal_area)?;
+                self.handle_update(
+                    ClassifiedAdId::new(cmd.id),
+                    location,
+                    metadata,
+                    |location, c| c.set_location(location),
+                )?
+            }
+            v1::Commands::RequestToPublish(cmd) => {
+                self.handle_update(ClassifiedAdId::new(cmd.id), cmd, metadata, |_, c| {
+                    c.request_to_publish()
+                })?
+            }
+            v1::Commands::Approve(cmd) => {
+                self.handle_update(ClassifiedAdId::new(cmd.id), cmd, metadata, |cmd, c| {
+                    c.approve(UserId::new(cmd.approved_by))
+                })?
+            }
+            v1::Commands::Reject(cmd) => {
+                self.handle_update(ClassifiedAdId::new(cmd.id), cmd, metadata, |cmd, c| {
+                    c.reject(UserId::new(cmd.rejected_by), cmd.reason)
+                })?
+            }
+            v1::Commands::MarkAsSold(cmd) => {
+                self.handle_update(ClassifiedAdId::new(cmd.id), cmd, metadata, |_, c| {
+                    c.mark_as_sold()
+                })?
+            }
+            v1::Commands::Deactivate(cmd) => {
+                self.handle_update(ClassifiedAdId::new(cmd.id), cmd, metadata, |_, c| {
+                    c.deactivate()
+                })?
+            }
+            v1::Commands::Reactivate(cmd) => {
+                self.handle_update(ClassifiedAdId::new(cmd.id), cmd, metadata, |_, c| {
+                    c.reactivate()
+                })?
+            }
+            v1::Commands::AddPicture(cmd) => {
+                self.handle_update(ClassifiedAdId::new(cmd.id), cmd, metadata, |cmd, c| {
+                    c.add_picture(
+                        PictureId::new(cmd.picture_id),
+                        cmd.url,
+                        PictureSize::new(cmd.width, cmd.height)?,
+                    )
+                })?
+            }
+            v1::Commands::ResizePicture(cmd) => {
+                self.handle_update(ClassifiedAdId::new(cmd.id), cmd, metadata, |cmd, c| {
+                    c.resize_picture(
+                        PictureId::new(cmd.picture_id),
+                        PictureSize::new(cmd.width, cmd.height)?,
+                    )
+                })?
+            }
+            v1::Commands::RemovePicture(cmd) => {
+                self.handle_update(ClassifiedAdId::new(cmd.id), cmd, metadata, |cmd, c| {
+                    c.remove_picture(PictureId::new(cmd.picture_id))
                 })?
             }
-            v1::Commands::UpdatePrice(_) => todo!(),
-            v1::Commands::RequestToPublish(_) => todo!(),
         };
         Ok(())
     }
 }
 
+/// Lets the application service be registered on the command bus for each of its commands.
+impl<C: Into<v1::Commands>> ICommandHandler<C> for ClassifiedAdsApplicationService {
+    fn handle(&self, command: C, metadata: &CommandMetadata) -> Result<()> {
+        IApplicationService::handle(self, command, metadata)
+    }
+}
+
 /// Create
 #[derive(Object)]
 pub struct ClassifiedAdsV1Create {
@@ -169,6 +361,169 @@ pub struct ClassifiedAdV1UpdatePrice {
     pub currency: String,
 }
 #[derive(Object)]
+pub struct ClassifiedAdV1ChangeCategory {
+    pub id: String,
+    pub category_id: String,
+    /// Values for the category's attributes: numbers, strings or booleans
+    #[oai(default)]
+    pub attributes: BTreeMap<String, serde_json::Value>,
+}
+#[derive(Object)]
+pub struct ClassifiedAdV1SetLocation {
+    pub id: String,
+    /// Degrees north of the equator, -90 to 90
+    pub latitude: f64,
+    /// Degrees east of Greenwich, -180 to 180
+    pub longitude: f64,
+    /// Postal area shown to buyers, e.g. "2000 Sydney"
+    pub postal_area: String,
+}
+#[derive(Object)]
 pub struct ClassifiedAdV1RequestToPublish {
     pub id: String,
 }
+#[derive(Object)]
+pub struct ClassifiedAdV1Approve {
+    pub id: String,
+}
+#[derive(Object)]
+pub struct ClassifiedAdV1Reject {
+    pub id: String,
+    pub reason: String,
+}
+#[derive(Object)]
+pub struct ClassifiedAdV1MarkAsSold {
+    pub id: String,
+}
+#[derive(Object)]
+pub struct ClassifiedAdV1Deactivate {
+    pub id: String,
+}
+#[derive(Object)]
+pub struct ClassifiedAdV1Reactivate {
+    pub id: String,
+}
+#[derive(Object)]
+pub struct ClassifiedAdV1Watch {
+    pub id: String,
+}
+#[derive(Object)]
+pub struct ClassifiedAdV1Unwatch {
+    pub id: String,
+}
+#[derive(Multipart)]
+pub struct ClassifiedAdV1UploadPicture {
+    pub id: String,
+    /// PNG or JPEG image
+    pub file: Upload,
+}
+#[derive(Object)]
+pub struct ClassifiedAdV1ResizePicture {
+    pub id: String,
+    pub picture_id: String,
+}
+#[derive(Object)]
+pub struct ClassifiedAdV1RemovePicture {
+    pub id: String,
+    pub picture_id: String,
+}
+
+#[derive(Object)]
+pub struct ClassifiedAdV1Picture {
+    pub id: String,
+    pub url: String,
+    pub width: u32,
+    pub height: u32,
+    pub order: u32,
+}
+
+/// An ad's price converted into another currency
+#[derive(Object)]
+pub struct ClassifiedAdV1ConvertedPrice {
+    pub price: Option<f64>,
+    pub currency: String,
+    pub formatted_price: String,
+    /// Units of `currency` per unit of the ad's own currency
+    pub rate: Option<f64>,
+    pub rate_quoted_at: DateTime<Utc>,
+}
+
+impl ClassifiedAdV1ConvertedPrice {
+    pub fn new(money: Money, rate: ExchangeRate) -> Self {
+        Self {
+            price: money.amount.to_f64(),
+            currency: money.currency_code.to_string(),
+            formatted_price: money.to_string(),
+            rate: rate.rate.to_f64(),
+            rate_quoted_at: DateTime::<Utc>::from(rate.quoted_at),
+        }
+    }
+}
+
+#[derive(Object)]
+pub struct ClassifiedAdV1Location {
+    pub latitude: f64,
+    pub longitude: f64,
+    pub postal_area: String,
+}
+
+impl From<&Location> for ClassifiedAdV1Location {
+    fn from(location: &Location) -> Self {
+        Self {
+            latitude: location.latitude(),
+            longitude: location.longitude(),
+            postal_area: location.postal_area().to_string(),
+        }
+    }
+}
+
+/// Current state of a classified ad
+#[derive(Object)]
+pub struct ClassifiedAdV1Details {
+    pub id: String,
+    pub owner_id: Option<String>,
+    pub title: Option<String>,
+    pub text: Option<String>,
+    pub price: Option<f64>,
+    pub currency: Option<String>,
+    /// Price with the currency's decimal places, e.g. "12.50 AUD"
+    pub formatted_price: Option<String>,
+    /// Price in the currency asked for with `?currency=`
+    pub converted_price: Option<ClassifiedAdV1ConvertedPrice>,
+    pub category_id: Option<String>,
+    pub attributes: BTreeMap<String, serde_json::Value>,
+    pub location: Option<ClassifiedAdV1Location>,
+    pub pictures: Vec<ClassifiedAdV1Picture>,
+    pub state: String,
+}
+
+impl From<&ClassifiedAd> for ClassifiedAdV1Details {
+    fn from(ad: &ClassifiedAd) -> Self {
+        let price = ad.price();
+        Self {
+            id: ad.uuid.map(|id| id.value().to_string()).unwrap_or_default(),
+            owner_id: ad.owner_id().map(|id| id.value().to_string()),
+            title: ad.title().map(|title| title.value()),
+            text: ad.text().map(|text| text.value()),
+            price: price.and_then(|p| p.money.amount.to_f64()),
+            currency: price.map(|p| p.money.currency_code.to_string()),
+            formatted_price: price.map(|p| p.money.to_string()),
+            converted_price: None,
+            category_id: ad.category().map(|id| id.value().to_string()),
+            attributes: attributes_to_json(ad.attributes().values()),
+            location: ad.location().as_ref().map(ClassifiedAdV1Location::from),
+            pictures: ad
+                .pictures()
+                .iter()
+                .map(|p| ClassifiedAdV1Picture {
+                    id: p.id().value().to_string(),
+                    url: p.location(),
+                    width: p.size().width(),
+                    height: p.size().height(),
+                    order: p.order(),
+                })
+                .collect(),
+            state: format!("{:?}", ad.state()),
+        }
+    }
+}
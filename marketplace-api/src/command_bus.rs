@@ -0,0 +1,201 @@
+use std::{sync::Arc, time::Duration};
+
+use anyhow::{anyhow, Result};
+use marketplace_contracts::{accounts, categories, classified_ads::v1, user_profiles};
+use marketplace_framework::{
+    AuthorizationMiddleware, CommandBus, CommandMetadata, CommandMetrics, IdempotencyMiddleware,
+    InMemoryIdempotencyStore, LoggingMiddleware, MetricsMiddleware, RetryMiddleware,
+    ValidationMiddleware,
+};
+use serde::Serialize;
+use uuid::Uuid;
+
+use crate::{
+    accounts::UserAccountApplicationService, authorization::CommandPolicy,
+    categories::CategoryApplicationService, classified_ad::ClassifiedAdsApplicationService,
+    user_profile::UserProfileApplicationService,
+};
+
+const MAX_ATTEMPTS: u32 = 3;
+/// How long the outcome of a command sent with an `Idempotency-Key` is kept for replays.
+const IDEMPOTENCY_TTL: Duration = Duration::from_secs(24 * 60 * 60);
+
+/// Builds the bus every command from the API goes through.
+/// Middleware order matters: it runs outermost first.
+pub fn build_command_bus(
+    classified_ads: ClassifiedAdsApplicationService,
+    user_profiles: UserProfileApplicationService,
+    accounts: UserAccountApplicationService,
+    categories: CategoryApplicationService,
+    metrics: CommandMetrics,
+) -> CommandBus {
+    let bus = CommandBus::new()
+        .with(LoggingMiddleware)
+        .with(MetricsMiddleware::new(metrics))
+        .with(AuthorizationMiddleware::new(CommandPolicy::new()))
+        .with(validation())
+        .with(IdempotencyMiddleware::new(
+            InMemoryIdempotencyStore::new(),
+            IDEMPOTENCY_TTL,
+        ))
+        .with(RetryMiddleware::new(MAX_ATTEMPTS));
+
+    let bus = register_classified_ads(bus, classified_ads);
+    let bus = register_user_profiles(bus, user_profiles);
+    let bus = register_accounts(bus, accounts);
+    register_categories(bus, categories)
+}
+
+/// Dispatches a command on behalf of an HTTP request. Commands carrying an
+/// idempotency key also get a fingerprint of their payload.
+///
+/// Handlers may block, on password hashing or calls to other services, so the
+/// dispatch runs on the blocking thread pool rather than the async workers.
+pub async fn dispatch_command<C>(
+    bus: &Arc<CommandBus>,
+    cmd: C,
+    mut metadata: CommandMetadata,
+) -> Result<()>
+where
+    C: Serialize + Send + Sync + 'static,
+{
+    if metadata.idempotency_key.is_some() {
+        metadata = metadata.with_payload_fingerprint(serde_json::to_string(&cmd)?);
+    }
+    let bus = bus.clone();
+    tokio::task::spawn_blocking(move || bus.dispatch_with(cmd, metadata))
+        .await
+        .map_err(|e| anyhow!("Command handler failed: {}", e))?
+}
+
+fn register_classified_ads(
+    bus: CommandBus,
+    service: ClassifiedAdsApplicationService,
+) -> CommandBus {
+    bus.register::<v1::Create>(service.clone())
+        .register::<v1::SetTitle>(service.clone())
+        .register::<v1::UpdateText>(service.clone())
+        .register::<v1::UpdatePrice>(service.clone())
+        .register::<v1::ChangeCategory>(service.clone())
+        .register::<v1::SetLocation>(service.clone())
+        .register::<v1::RequestToPublish>(service.clone())
+        .register::<v1::Approve>(service.clone())
+        .register::<v1::Reject>(service.clone())
+        .register::<v1::MarkAsSold>(service.clone())
+        .register::<v1::Deactivate>(service.clone())
+        .register::<v1::Reactivate>(service.clone())
+        .register::<v1::AddPicture>(service.clone())
+        .register::<v1::ResizePicture>(service.clone())
+        .register::<v1::RemovePicture>(service)
+}
+
+fn register_user_profiles(bus: CommandBus, service: UserProfileApplicationService) -> CommandBus {
+    bus.register::<user_profiles::v1::RegisterUser>(service.clone())
+        .register::<user_profiles::v1::UpdateFullName>(service.clone())
+        .register::<user_profiles::v1::UpdateDisplayName>(service.clone())
+        .register::<user_profiles::v1::UpdateProfilePhoto>(service.clone())
+        .register::<user_profiles::v1::GrantRole>(service.clone())
+        .register::<user_profiles::v1::RevokeRole>(service)
+}
+
+fn register_accounts(bus: CommandBus, service: UserAccountApplicationService) -> CommandBus {
+    bus.register::<accounts::v1::RegisterAccount>(service.clone())
+        .register::<accounts::v1::ChangePassword>(service.clone())
+        .register::<accounts::v1::RequestPasswordReset>(service.clone())
+        .register::<accounts::v1::ResetPassword>(service)
+}
+
+fn register_categories(bus: CommandBus, service: CategoryApplicationService) -> CommandBus {
+    bus.register::<categories::v1::CreateCategory>(service.clone())
+        .register::<categories::v1::SetCategoryAttributes>(service.clone())
+        .register::<categories::v1::RemoveCategory>(service)
+}
+
+fn validation() -> ValidationMiddleware {
+    ValidationMiddleware::new()
+        .validate(|cmd: &v1::Create| {
+            require_id(cmd.id)?;
+            require_id(cmd.owner_id)
+        })
+        .validate(|cmd: &v1::SetTitle| require_id(cmd.id))
+        .validate(|cmd: &v1::UpdateText| require_id(cmd.id))
+        .validate(|cmd: &v1::UpdatePrice| {
+            require_id(cmd.id)?;
+            if cmd.currency.is_empty() {
+                return Err(anyhow!("Currency must be provided"));
+            }
+            Ok(())
+        })
+        .validate(|cmd: &v1::ChangeCategory| {
+            require_id(cmd.id)?;
+            require_id(cmd.category_id)
+        })
+        .validate(|cmd: &v1::SetLocation| require_id(cmd.id))
+        .validate(|cmd: &v1::RequestToPublish| require_id(cmd.id))
+        .validate(|cmd: &v1::Approve| {
+            require_id(cmd.id)?;
+            require_id(cmd.approved_by)
+        })
+        .validate(|cmd: &v1::Reject| {
+            require_id(cmd.id)?;
+            require_id(cmd.rejected_by)
+        })
+        .validate(|cmd: &v1::MarkAsSold| require_id(cmd.id))
+        .validate(|cmd: &v1::Deactivate| require_id(cmd.id))
+        .validate(|cmd: &v1::Reactivate| require_id(cmd.id))
+        .validate(|cmd: &v1::AddPicture| {
+            require_id(cmd.id)?;
+            require_id(cmd.picture_id)?;
+            if cmd.url.is_empty() {
+                return Err(anyhow!("Picture url must be provided"));
+            }
+            Ok(())
+        })
+        .validate(|cmd: &v1::ResizePicture| {
+            require_id(cmd.id)?;
+            require_id(cmd.picture_id)
+        })
+        .validate(|cmd: &v1::RemovePicture| {
+            require_id(cmd.id)?;
+            require_id(cmd.picture_id)
+        })
+        .validate(|cmd: &user_profiles::v1::RegisterUser| require_id(cmd.user_id))
+        .validate(|cmd: &user_profiles::v1::UpdateFullName| require_id(cmd.user_id))
+        .validate(|cmd: &user_profiles::v1::UpdateDisplayName| require_id(cmd.user_id))
+        .validate(|cmd: &user_profiles::v1::UpdateProfilePhoto| {
+            require_id(cmd.user_id)?;
+            if cmd.photo_url.is_empty() || cmd.thumbnail_url.is_empty() {
+                return Err(anyhow!("Photo and thumbnail urls must be provided"));
+            }
+            Ok(())
+        })
+        .validate(|cmd: &user_profiles::v1::GrantRole| {
+            require_id(cmd.user_id)?;
+            require_id(cmd.granted_by)
+        })
+        .validate(|cmd: &user_profiles::v1::RevokeRole| {
+            require_id(cmd.user_id)?;
+            require_id(cmd.revoked_by)
+        })
+        .validate(|cmd: &accounts::v1::RegisterAccount| require_id(cmd.user_id))
+        .validate(|cmd: &accounts::v1::ChangePassword| require_id(cmd.user_id))
+        .validate(|cmd: &accounts::v1::ResetPassword| {
+            if cmd.token.trim().is_empty() {
+                return Err(anyhow!("Reset token must be provided"));
+            }
+            Ok(())
+        })
+        .validate(|cmd: &categories::v1::CreateCategory| {
+            require_id(cmd.id)?;
+            cmd.parent_id.map_or(Ok(()), require_id)
+        })
+        .validate(|cmd: &categories::v1::SetCategoryAttributes| require_id(cmd.id))
+        .validate(|cmd: &categories::v1::RemoveCategory| require_id(cmd.id))
+}
+
+fn require_id(id: Uuid) -> Result<()> {
+    if id.is_nil() {
+        return Err(anyhow!("Id must not be empty"));
+    }
+    Ok(())
+}
@@ -0,0 +1,46 @@
+use marketplace_domain::{
+    classified_ad_state::IllegalTransition, user_account::InvalidCredentials,
+};
+use marketplace_framework::{
+    ConcurrencyConflict, EntityNotFound, ExpectedVersionMismatch, Forbidden,
+    IdempotencyKeyInProgress, IdempotencyKeyReused,
+};
+use poem::{http::StatusCode, Error};
+
+use crate::{
+    accounts::EmailAlreadyRegistered, authentication::TokenIssuingUnavailable,
+    exchange_rates::ExchangeRateUnavailable, text_moderation::TextModerationUnavailable,
+};
+
+/// Maps a failed command or query onto an HTTP error response.
+pub fn into_http_error(err: anyhow::Error) -> Error {
+    let status = if err.is::<IdempotencyKeyReused>() {
+        StatusCode::UNPROCESSABLE_ENTITY
+    } else if err.is::<ExpectedVersionMismatch>() {
+        StatusCode::PRECONDITION_FAILED
+    } else if err.is::<ConcurrencyConflict>()
+        || err.is::<IdempotencyKeyInProgress>()
+        || err.is::<IllegalTransition>()
+        || err.is::<EmailAlreadyRegistered>()
+    {
+        StatusCode::CONFLICT
+    } else if err.is::<InvalidCredentials>() {
+        StatusCode::UNAUTHORIZED
+    } else if err.is::<Forbidden>() {
+        StatusCode::FORBIDDEN
+    } else if err.is::<EntityNotFound>() {
+        StatusCode::NOT_FOUND
+    } else if err.is::<ExchangeRateUnavailable>()
+        || err.is::<TextModerationUnavailable>()
+        || err.is::<TokenIssuingUnavailable>()
+    {
+        StatusCode::SERVICE_UNAVAILABLE
+    } else {
+        StatusCode::BAD_REQUEST
+    };
+    Error::from_string(err.to_string(), status)
+}
+
+pub fn bad_request(err: impl std::fmt::Display) -> Error {
+    Error::from_string(err.to_string(), StatusCode::BAD_REQUEST)
+}
@@ -0,0 +1,40 @@
+use anyhow::{anyhow, Result};
+
+/// Renders an aggregate version as a strong entity tag.
+pub fn to_etag(version: i64) -> String {
+    format!("\"{version}\"")
+}
+
+/// Reads the versions out of an `If-Match` header, a comma separated list of
+/// entity tags. `*` matches any version, so no expectation is returned for it.
+/// If-Match compares tags strongly, so weak tags, and tags this API never
+/// hands out, match no version; a list of only those fails every update.
+pub fn parse_if_match(value: &str) -> Result<Option<Vec<i64>>> {
+    let value = value.trim();
+    if value == "*" {
+        return Ok(None);
+    }
+    let mut versions = vec![];
+    for tag in value
+        .split(',')
+        .map(str::trim)
+        .filter(|tag| !tag.is_empty())
+    {
+        let (weak, tag) = match tag.strip_prefix("W/") {
+            Some(tag) => (true, tag),
+            None => (false, tag),
+        };
+        let opaque = tag
+            .strip_prefix('"')
+            .and_then(|tag| tag.strip_suffix('"'))
+            .filter(|opaque| !opaque.contains('"'))
+            .ok_or_else(|| anyhow!("If-Match must be * or a list of quoted ETags"))?;
+        if let (false, Ok(version)) = (weak, opaque.parse::<i64>()) {
+            versions.push(version);
+        }
+    }
+    if versions.is_empty() && !value.contains('"') {
+        return Err(anyhow!("If-Match must be * or a list of quoted ETags"));
+    }
+    Ok(Some(versions))
+}
@@ -0,0 +1,377 @@
+use std::{
+    collections::{HashMap, VecDeque},
+    str::FromStr,
+    sync::{Arc, RwLock},
+    time::Duration,
+};
+
+use anyhow::anyhow;
+use chrono::{DateTime, Utc};
+use futures_util::{stream, Stream, StreamExt};
+use marketplace_domain::{classified_ad_events::ClassifiedAdEvents, user_profile::Role};
+use poem::{
+    handler,
+    web::{
+        sse::{Event, SSE},
+        Data, Path,
+    },
+    Request, Result,
+};
+use serde::Serialize;
+use serde_json::json;
+use tokio::sync::broadcast::{self, error::RecvError};
+use uuid::Uuid;
+
+use crate::{authentication::Principal, categories::attributes_to_json, errors::bad_request};
+
+/// Events a slow subscriber can fall behind by before it has to catch up from the log.
+const LIVE_EVENTS_CAPACITY: usize = 1024;
+const KEEP_ALIVE: Duration = Duration::from_secs(15);
+
+/// A committed ad event with its place in the ad's stream and in the log as a whole.
+#[derive(Serialize)]
+pub struct RecordedEvent {
+    pub global_position: u64,
+    pub stream_id: Uuid,
+    /// Version of the ad once the event was applied; the ad's creation is 0.
+    pub stream_position: i64,
+    #[serde(rename = "type")]
+    pub event_type: &'static str,
+    pub data: serde_json::Value,
+    pub recorded_at: DateTime<Utc>,
+    #[serde(skip)]
+    pub owner_id: Uuid,
+    /// Whether the ad had been published by this event; from then on its events
+    /// are shown to everyone.
+    #[serde(skip)]
+    pub published: bool,
+    #[serde(skip)]
+    pub event: ClassifiedAdEvents,
+}
+
+#[derive(Default)]
+struct LoggedEvents {
+    /// Every event, the one at global position `n` at index `n - 1`.
+    events: Vec<Arc<RecordedEvent>>,
+    /// Indexes into `events` of each ad's events, in stream order.
+    streams: HashMap<Uuid, Vec<usize>>,
+}
+
+/// In-memory log of every committed ad event, which live subscribers are told about.
+pub struct ClassifiedAdEventLog {
+    _events: RwLock<LoggedEvents>,
+    _live: broadcast::Sender<Arc<RecordedEvent>>,
+}
+
+impl ClassifiedAdEventLog {
+    pub fn new() -> Self {
+        let (live, _) = broadcast::channel(LIVE_EVENTS_CAPACITY);
+        Self {
+            _events: RwLock::new(LoggedEvents::default()),
+            _live: live,
+        }
+    }
+
+    /// Records events committed to an ad, the first of which takes stream position
+    /// `first_position`. Callers hold the ad's store while appending so events of
+    /// one ad are logged in the order they were committed.
+    pub fn append(&self, stream_id: Uuid, first_position: i64, events: &[ClassifiedAdEvents]) {
+        let mut log = self._events.write().unwrap();
+        let LoggedEvents {
+            events: logged,
+            streams,
+        } = &mut *log;
+        let stream = streams.entry(stream_id).or_default();
+        for (offset, event) in events.iter().enumerate() {
+            let (event_type, data) = serialize(event);
+            let owner_id = match event {
+                ClassifiedAdEvents::Created(e) => e.owner_id,
+                _ => stream
+                    .first()
+                    .map(|index| logged[*index].owner_id)
+                    .unwrap_or_default(),
+            };
+            let published = matches!(event, ClassifiedAdEvents::Published(_))
+                || stream.last().is_some_and(|index| logged[*index].published);
+            let recorded = Arc::new(RecordedEvent {
+                global_position: logged.len() as u64 + 1,
+                stream_id,
+                stream_position: first_position + offset as i64,
+                event_type,
+                data,
+                recorded_at: Utc::now(),
+                owner_id,
+                published,
+                event: event.clone(),
+            });
+            stream.push(logged.len());
+            logged.push(recorded.clone());
+            // Nobody listening is fine
+            let _ = self._live.send(recorded);
+        }
+    }
+
+    /// Events matching `filter` after position `after`, found by position rather
+    /// than by going through the whole log.
+    fn read(&self, filter: &EventFilter, after: i64) -> VecDeque<Arc<RecordedEvent>> {
+        let log = self._events.read().unwrap();
+        let candidates: Box<dyn Iterator<Item = &Arc<RecordedEvent>>> = match filter.stream_id {
+            None => {
+                let start = usize::try_from(after).unwrap_or(0).min(log.events.len());
+                Box::new(log.events[start..].iter())
+            }
+            Some(stream_id) => {
+                let stream = log.streams.get(&stream_id).map_or(&[][..], Vec::as_slice);
+                let start =
+                    stream.partition_point(|index| log.events[*index].stream_position <= after);
+                Box::new(stream[start..].iter().map(|index| &log.events[*index]))
+            }
+        };
+        candidates
+            .filter(|event| filter.matches(event))
+            .cloned()
+            .collect()
+    }
+
+    /// Position of the latest event `filter` counts positions in, or -1 if there is none.
+    fn head(&self, filter: &EventFilter) -> i64 {
+        let log = self._events.read().unwrap();
+        match filter.stream_id {
+            None => log.events.len() as i64,
+            Some(stream_id) => log
+                .streams
+                .get(&stream_id)
+                .and_then(|stream| stream.last())
+                .map_or(-1, |index| log.events[*index].stream_position),
+        }
+    }
+
+    /// Every event after global position `after`, followed by new ones as they are committed.
+    pub fn subscribe_all(self: Arc<Self>, after: u64) -> impl Stream<Item = Arc<RecordedEvent>> {
+        self.subscribe(EventFilter::all(), Some(after as i64))
+    }
+
+    /// Events matching `filter` after position `after`, followed by new ones as they are
+    /// committed; only new ones when there is no position to resume from. A subscriber
+    /// that falls too far behind is caught up from the log.
+    fn subscribe(
+        self: Arc<Self>,
+        filter: EventFilter,
+        after: Option<i64>,
+    ) -> impl Stream<Item = Arc<RecordedEvent>> {
+        // Subscribe before reading the log so nothing committed in between is missed
+        let live = self._live.subscribe();
+        let after = after.unwrap_or_else(|| self.head(&filter));
+        let backlog = self.read(&filter, after);
+        let state = (self, filter, after, backlog, live);
+        stream::unfold(
+            state,
+            |(log, filter, mut after, mut backlog, mut live)| async move {
+                loop {
+                    let next = match backlog.pop_front() {
+                        Some(event) => event,
+                        None => match live.recv().await {
+                            Ok(event) => event,
+                            Err(RecvError::Lagged(_)) => {
+                                backlog = log.read(&filter, after);
+                                continue;
+                            }
+                            Err(RecvError::Closed) => return None,
+                        },
+                    };
+                    if filter.matches(&next) && filter.position(&next) > after {
+                        after = filter.position(&next);
+                        return Some((next, (log, filter, after, backlog, live)));
+                    }
+                }
+            },
+        )
+    }
+}
+
+impl Default for ClassifiedAdEventLog {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Which events a subscriber gets: those of one ad, whose event ids are positions
+/// in its stream, or of every ad, counted by global position. Events of ads not
+/// yet published are left out, except for moderators and the ad's owner.
+struct EventFilter {
+    stream_id: Option<Uuid>,
+    unpublished: bool,
+    owner_id: Option<Uuid>,
+}
+
+impl EventFilter {
+    fn all() -> Self {
+        Self {
+            stream_id: None,
+            unpublished: true,
+            owner_id: None,
+        }
+    }
+
+    fn matches(&self, event: &RecordedEvent) -> bool {
+        self.stream_id.is_none_or(|id| event.stream_id == id)
+            && (self.unpublished || event.published || self.owner_id == Some(event.owner_id))
+    }
+
+    fn position(&self, event: &RecordedEvent) -> i64 {
+        match self.stream_id {
+            None => event.global_position as i64,
+            Some(_) => event.stream_position,
+        }
+    }
+}
+
+fn serialize(event: &ClassifiedAdEvents) -> (&'static str, serde_json::Value) {
+    match event {
+        ClassifiedAdEvents::Created(e) => {
+            ("Created", json!({ "id": e.id, "owner_id": e.owner_id }))
+        }
+        ClassifiedAdEvents::TextUpdated(e) => {
+            ("TextUpdated", json!({ "id": e.id, "text": e.ad_text }))
+        }
+        ClassifiedAdEvents::TitleChanged(e) => {
+            ("TitleChanged", json!({ "id": e.id, "title": e.title }))
+        }
+        ClassifiedAdEvents::PriceUpdated(e) => (
+            "PriceUpdated",
+            json!({ "id": e.id, "price": e.price, "currency": e.currency_code }),
+        ),
+        ClassifiedAdEvents::CategoryChanged(e) => (
+            "CategoryChanged",
+            json!({
+                "id": e.id,
+                "category_id": e.category_id,
+                "attributes": attributes_to_json(&e.attributes),
+            }),
+        ),
+        ClassifiedAdEvents::LocationSet(e) => (
+            "LocationSet",
+            json!({
+                "id": e.id,
+                "latitude": e.latitude,
+                "longitude": e.longitude,
+                "postal_area": e.postal_area,
+            }),
+        ),
+        ClassifiedAdEvents::SentForReview(e) => ("SentForReview", json!({ "id": e.id })),
+        ClassifiedAdEvents::Published(e) => (
+            "Published",
+            json!({ "id": e.id, "approved_by": e.approved_by }),
+        ),
+        ClassifiedAdEvents::Rejected(e) => (
+            "Rejected",
+            json!({ "id": e.id, "rejected_by": e.rejected_by, "reason": e.reason }),
+        ),
+        ClassifiedAdEvents::MarkedAsSold(e) => ("MarkedAsSold", json!({ "id": e.id })),
+        ClassifiedAdEvents::Deactivated(e) => ("Deactivated", json!({ "id": e.id })),
+        ClassifiedAdEvents::Reactivated(e) => ("Reactivated", json!({ "id": e.id })),
+        ClassifiedAdEvents::PictureAdded(e) => (
+            "PictureAdded",
+            json!({
+                "id": e.id,
+                "picture_id": e.picture_id,
+                "url": e.url,
+                "width": e.width,
+                "height": e.height,
+                "order": e.order,
+            }),
+        ),
+        ClassifiedAdEvents::PictureResized(e) => (
+            "PictureResized",
+            json!({
+                "id": e.id,
+                "picture_id": e.picture_id,
+                "width": e.width,
+                "height": e.height,
+            }),
+        ),
+        ClassifiedAdEvents::PictureRemoved(e) => (
+            "PictureRemoved",
+            json!({ "id": e.id, "picture_id": e.picture_id }),
+        ),
+    }
+}
+
+// ================================================================================
+// Endpoints
+// ================================================================================
+
+/// Position of the last event a reconnecting client saw, from `Last-Event-ID`.
+fn last_event_id(req: &Request) -> anyhow::Result<Option<i64>> {
+    match req.headers().get("Last-Event-ID") {
+        Some(value) => {
+            let value = value.to_str()?.trim();
+            let position = i64::from_str(value)
+                .map_err(|_| anyhow!("Last-Event-ID {} is not an event position", value))?;
+            Ok(Some(position))
+        }
+        None => Ok(None),
+    }
+}
+
+/// Fields naming the moderator who reviewed an ad, only shown to moderators.
+const MODERATOR_FIELDS: [&str; 2] = ["approved_by", "rejected_by"];
+
+/// Anyone, signed in or not, follows published ads, without who reviewed them.
+/// Owners also follow their own ads before they are published, and moderators
+/// follow every ad.
+fn event_stream(
+    log: &Arc<ClassifiedAdEventLog>,
+    principal: Option<&Principal>,
+    stream_id: Option<Uuid>,
+    after: Option<i64>,
+) -> SSE {
+    let moderator = principal.is_some_and(|p| p.has_role(Role::Moderator));
+    let filter = EventFilter {
+        stream_id,
+        unpublished: moderator,
+        owner_id: principal.map(|p| p.user_id.value()),
+    };
+    let events = log.clone().subscribe(filter, after).map(move |event| {
+        let id = match stream_id {
+            None => event.global_position.to_string(),
+            Some(_) => event.stream_position.to_string(),
+        };
+        let mut data = serde_json::to_value(event.as_ref()).unwrap_or_default();
+        let fields = data.get_mut("data").and_then(|d| d.as_object_mut());
+        if let Some(fields) = fields.filter(|_| !moderator) {
+            for field in MODERATOR_FIELDS {
+                fields.remove(field);
+            }
+        }
+        Event::message(data.to_string()).id(id)
+    });
+    SSE::new(events).keep_alive(KEEP_ALIVE)
+}
+
+/// `GET /ad/:id/events`: changes to one ad. Event ids are positions in the ad's stream.
+#[handler]
+pub async fn ad_events(
+    req: &Request,
+    Path(id): Path<String>,
+    log: Data<&Arc<ClassifiedAdEventLog>>,
+) -> Result<SSE> {
+    let id = Uuid::from_str(&id).map_err(bad_request)?;
+    Ok(event_stream(
+        &log,
+        req.extensions().get::<Principal>(),
+        Some(id),
+        last_event_id(req).map_err(bad_request)?,
+    ))
+}
+
+/// `GET /ads/events`: changes to every ad. Event ids are positions in the whole log.
+#[handler]
+pub async fn all_ad_events(req: &Request, log: Data<&Arc<ClassifiedAdEventLog>>) -> Result<SSE> {
+    Ok(event_stream(
+        &log,
+        req.extensions().get::<Principal>(),
+        None,
+        last_event_id(req).map_err(bad_request)?,
+    ))
+}
@@ -0,0 +1,221 @@
+use std::{
+    collections::HashMap,
+    fmt, fs,
+    path::PathBuf,
+    str::FromStr,
+    sync::{Arc, Mutex},
+    time::{Duration, Instant, SystemTime},
+};
+
+use anyhow::{anyhow, Result};
+use chrono::{DateTime, Utc};
+use marketplace_domain::{CurrencyCode, ExchangeRate, ICurrencyLookup, IExchangeRates, Money};
+use rust_decimal::Decimal;
+use serde::Deserialize;
+
+/// Decimal places kept on cross rates worked out from the base currency.
+const CROSS_RATE_DECIMAL_PLACES: u32 = 10;
+
+/// Raised when no usable exchange rate can be found for a currency pair.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ExchangeRateUnavailable {
+    pub from: CurrencyCode,
+    pub to: CurrencyCode,
+    pub reason: String,
+}
+
+impl fmt::Display for ExchangeRateUnavailable {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "No exchange rate from {} to {}: {}",
+            self.from, self.to, self.reason
+        )
+    }
+}
+
+impl std::error::Error for ExchangeRateUnavailable {}
+
+fn unavailable(from: CurrencyCode, to: CurrencyCode, reason: impl fmt::Display) -> anyhow::Error {
+    ExchangeRateUnavailable {
+        from,
+        to,
+        reason: reason.to_string(),
+    }
+    .into()
+}
+
+// ================================================================================
+// Static rates file
+// ================================================================================
+
+/// Contents of a rates file, e.g.
+/// `{"base": "EUR", "as_of": "2026-10-19T00:00:00Z", "rates": {"AUD": "1.64"}}`.
+/// Every rate is the price of one unit of `base` in that currency, as quoted
+/// at `as_of`.
+#[derive(Deserialize)]
+struct RatesFile {
+    base: String,
+    as_of: DateTime<Utc>,
+    rates: HashMap<String, Decimal>,
+}
+
+/// Reads rates from a JSON file on every lookup. Quotes are dated with the
+/// file's `as_of` time. Wrap it in `CachedExchangeRates` to avoid the reads.
+pub struct StaticFileExchangeRates {
+    _path: PathBuf,
+}
+
+impl StaticFileExchangeRates {
+    pub fn new(path: impl Into<PathBuf>) -> Self {
+        Self { _path: path.into() }
+    }
+
+    fn rate_from_base(
+        rates: &RatesFile,
+        base: CurrencyCode,
+        code: CurrencyCode,
+    ) -> Option<Decimal> {
+        if code == base {
+            return Some(Decimal::ONE);
+        }
+        rates
+            .rates
+            .iter()
+            .find(|(c, _)| CurrencyCode::from_str(c).ok() == Some(code))
+            .map(|(_, rate)| *rate)
+    }
+}
+
+impl IExchangeRates for StaticFileExchangeRates {
+    fn get_rate(&self, from: CurrencyCode, to: CurrencyCode) -> Result<ExchangeRate> {
+        let content = fs::read_to_string(&self._path).map_err(|e| unavailable(from, to, e))?;
+        let rates: RatesFile =
+            serde_json::from_str(&content).map_err(|e| unavailable(from, to, e))?;
+        let quoted_at = SystemTime::from(rates.as_of);
+        let base = CurrencyCode::from_str(&rates.base)?;
+
+        let from_rate = Self::rate_from_base(&rates, base, from)
+            .ok_or_else(|| unavailable(from, to, format!("{} is not in the rates file", from)))?;
+        let to_rate = Self::rate_from_base(&rates, base, to)
+            .ok_or_else(|| unavailable(from, to, format!("{} is not in the rates file", to)))?;
+        let rate = to_rate
+            .checked_div(from_rate)
+            .ok_or_else(|| unavailable(from, to, "invalid rate in the rates file"))?
+            .round_dp(CROSS_RATE_DECIMAL_PLACES);
+
+        Ok(ExchangeRate {
+            from,
+            to,
+            rate,
+            quoted_at,
+        })
+    }
+}
+
+// ================================================================================
+// Cache
+// ================================================================================
+
+struct CachedRate {
+    rate: ExchangeRate,
+    fetched_at: Instant,
+}
+
+/// Keeps rates from another provider for `refresh_after` before asking again.
+/// Quotes older than `max_staleness` are never handed out, but anything younger
+/// is still served when the provider fails.
+pub struct CachedExchangeRates {
+    _inner: Arc<dyn IExchangeRates + Send + Sync>,
+    _refresh_after: Duration,
+    _max_staleness: Duration,
+    _cache: Mutex<HashMap<(CurrencyCode, CurrencyCode), CachedRate>>,
+}
+
+impl CachedExchangeRates {
+    pub fn new(
+        inner: Arc<dyn IExchangeRates + Send + Sync>,
+        refresh_after: Duration,
+        max_staleness: Duration,
+    ) -> Self {
+        Self {
+            _inner: inner,
+            _refresh_after: refresh_after,
+            _max_staleness: max_staleness,
+            _cache: Mutex::new(HashMap::new()),
+        }
+    }
+
+    fn is_stale(&self, rate: &ExchangeRate) -> bool {
+        SystemTime::now()
+            .duration_since(rate.quoted_at)
+            .map(|age| age > self._max_staleness)
+            .unwrap_or(false)
+    }
+}
+
+impl IExchangeRates for CachedExchangeRates {
+    fn get_rate(&self, from: CurrencyCode, to: CurrencyCode) -> Result<ExchangeRate> {
+        let mut cache = self._cache.lock().unwrap();
+        let cached = cache.get(&(from, to));
+        if let Some(cached) = cached {
+            if cached.fetched_at.elapsed() < self._refresh_after && !self.is_stale(&cached.rate) {
+                return Ok(cached.rate);
+            }
+        }
+
+        match self._inner.get_rate(from, to) {
+            Ok(rate) if !self.is_stale(&rate) => {
+                cache.insert(
+                    (from, to),
+                    CachedRate {
+                        rate,
+                        fetched_at: Instant::now(),
+                    },
+                );
+                Ok(rate)
+            }
+            Ok(_) => Err(unavailable(from, to, "the latest quote is too old")),
+            Err(err) => match cached {
+                Some(cached) if !self.is_stale(&cached.rate) => Ok(cached.rate),
+                _ => Err(err),
+            },
+        }
+    }
+}
+
+// ================================================================================
+// Conversion
+// ================================================================================
+
+/// Converts prices into a currency requested by the caller.
+#[derive(Clone)]
+pub struct PriceConverter {
+    _currency_lookup: Arc<dyn ICurrencyLookup + Send + Sync>,
+    _exchange_rates: Arc<dyn IExchangeRates + Send + Sync>,
+}
+
+impl PriceConverter {
+    pub fn new(
+        currency_lookup: Arc<dyn ICurrencyLookup + Send + Sync>,
+        exchange_rates: Arc<dyn IExchangeRates + Send + Sync>,
+    ) -> Self {
+        Self {
+            _currency_lookup: currency_lookup,
+            _exchange_rates: exchange_rates,
+        }
+    }
+
+    pub fn convert(&self, money: &Money, currency: &str) -> Result<(Money, ExchangeRate)> {
+        let currency = self
+            ._currency_lookup
+            .find_currency(CurrencyCode::from_str(currency)?)?;
+        if !currency.in_use {
+            return Err(anyhow!(
+                "Currency code {} is not valid",
+                currency.currency_code
+            ));
+        }
+        money.convert_to(&currency, self._exchange_rates.clone())
+    }
+}
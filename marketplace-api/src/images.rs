@@ -0,0 +1,99 @@
+use std::io::Cursor;
+
+use anyhow::{anyhow, Result};
+use image::{
+    io::{Limits, Reader},
+    ImageFormat, ImageOutputFormat,
+};
+
+/// Largest image accepted by the upload endpoints.
+pub const MAX_UPLOAD_BYTES: usize = 10 * 1024 * 1024;
+/// Largest profile photo accepted.
+pub const MAX_PROFILE_PHOTO_BYTES: usize = 2 * 1024 * 1024;
+/// Widest and tallest image accepted, in pixels.
+pub const MAX_IMAGE_DIMENSION: u32 = 8000;
+/// Most memory the decoder may allocate for one image.
+const MAX_DECODE_ALLOC: u64 = 256 * 1024 * 1024;
+/// Thumbnails fit in a square of this many pixels, keeping the aspect ratio.
+pub const THUMBNAIL_SIZE: u32 = 128;
+
+pub struct UploadedImage {
+    pub format: ImageFormat,
+    pub extension: &'static str,
+    pub width: u32,
+    pub height: u32,
+}
+
+/// Checks an uploaded file is a PNG or JPEG within the size limit and reads its resolution.
+pub fn inspect_upload(content_type: Option<&str>, content: &[u8]) -> Result<UploadedImage> {
+    inspect_upload_with_limit(content_type, content, MAX_UPLOAD_BYTES)
+}
+
+/// Like `inspect_upload`, with a different size limit.
+pub fn inspect_upload_with_limit(
+    content_type: Option<&str>,
+    content: &[u8],
+    max_bytes: usize,
+) -> Result<UploadedImage> {
+    if content.len() > max_bytes {
+        return Err(anyhow!("Images cannot be larger than {} bytes", max_bytes));
+    }
+    let (format, extension) = match content_type {
+        Some("image/png") => (ImageFormat::Png, "png"),
+        Some("image/jpeg") => (ImageFormat::Jpeg, "jpg"),
+        _ => {
+            return Err(anyhow!(
+                "Only image/png and image/jpeg uploads are supported"
+            ))
+        }
+    };
+    let (width, height) = reader(content, format)
+        .into_dimensions()
+        .map_err(|e| anyhow!("Could not read image: {}", e))?;
+    if width > MAX_IMAGE_DIMENSION || height > MAX_IMAGE_DIMENSION {
+        return Err(anyhow!(
+            "Images cannot be wider or taller than {} pixels",
+            MAX_IMAGE_DIMENSION
+        ));
+    }
+    Ok(UploadedImage {
+        format,
+        extension,
+        width,
+        height,
+    })
+}
+
+/// The content type of a stored image, from the extension `inspect_upload` gave its key.
+pub fn content_type_of(key: &str) -> Option<&'static str> {
+    match key.rsplit_once('.')?.1 {
+        "png" => Some("image/png"),
+        "jpg" => Some("image/jpeg"),
+        _ => None,
+    }
+}
+
+/// Scales an image down to fit within `THUMBNAIL_SIZE`, encoded in the same format.
+pub fn make_thumbnail(content: &[u8], format: ImageFormat) -> Result<Vec<u8>> {
+    let image = reader(content, format)
+        .decode()
+        .map_err(|e| anyhow!("Could not read image: {}", e))?;
+    let mut thumbnail = Cursor::new(Vec::new());
+    image
+        .thumbnail(THUMBNAIL_SIZE, THUMBNAIL_SIZE)
+        .write_to(&mut thumbnail, ImageOutputFormat::from(format))
+        .map_err(|e| anyhow!("Could not write thumbnail: {}", e))?;
+    Ok(thumbnail.into_inner())
+}
+
+/// A decoder for the content that gives up on images past the size limits
+/// instead of allocating for them.
+fn reader(content: &[u8], format: ImageFormat) -> Reader<Cursor<&[u8]>> {
+    let mut limits = Limits::default();
+    limits.max_image_width = Some(MAX_IMAGE_DIMENSION);
+    limits.max_image_height = Some(MAX_IMAGE_DIMENSION);
+    limits.max_alloc = Some(MAX_DECODE_ALLOC);
+    let mut reader = Reader::with_format(Cursor::new(content), format);
+    reader.limits(limits);
+    reader
+}
@@ -0,0 +1,227 @@
+use std::{
+    collections::{BTreeMap, HashMap, HashSet},
+    sync::{Arc, RwLock},
+};
+
+use anyhow::{anyhow, Result};
+use chrono::{DateTime, Utc};
+use futures_util::StreamExt;
+use marketplace_domain::{
+    category::AttributeValue, classified_ad_events::ClassifiedAdEvents, location::Location,
+};
+use poem_openapi::Object;
+use rust_decimal::{prelude::ToPrimitive, Decimal};
+use uuid::Uuid;
+
+use crate::{
+    categories::{attributes_to_json, AttributeCondition},
+    classified_ad::ClassifiedAdV1Location,
+    event_stream::{ClassifiedAdEventLog, RecordedEvent},
+    pagination::{paginate, Page, PageRequest, SortField, SortKey},
+};
+
+#[derive(Object)]
+pub struct ClassifiedAdV1Summary {
+    pub id: String,
+    pub owner_id: String,
+    pub title: Option<String>,
+    pub text: Option<String>,
+    pub price: Option<f64>,
+    pub currency: Option<String>,
+    pub category_id: Option<String>,
+    pub attributes: BTreeMap<String, serde_json::Value>,
+    pub location: Option<ClassifiedAdV1Location>,
+    pub created_at: DateTime<Utc>,
+}
+
+#[derive(Object)]
+pub struct ClassifiedAdV1Page {
+    pub items: Vec<ClassifiedAdV1Summary>,
+    /// Pass as `cursor` to get the next page; missing on the last page
+    pub next_cursor: Option<String>,
+}
+
+impl From<Page<ClassifiedAdV1Summary>> for ClassifiedAdV1Page {
+    fn from(page: Page<ClassifiedAdV1Summary>) -> Self {
+        Self {
+            items: page.items,
+            next_cursor: page.next_cursor,
+        }
+    }
+}
+
+#[derive(Clone)]
+struct ListedAd {
+    id: Uuid,
+    owner_id: Uuid,
+    title: Option<String>,
+    text: Option<String>,
+    price: Option<(Decimal, String)>,
+    category_id: Option<Uuid>,
+    attributes: BTreeMap<String, AttributeValue>,
+    location: Option<Location>,
+    created_at: DateTime<Utc>,
+    active: bool,
+}
+
+impl ListedAd {
+    fn sort_key(&self, sort: SortField) -> (SortKey, Uuid) {
+        let key = match sort {
+            // Listings without a point to measure from are never sorted by distance
+            SortField::CreatedAt | SortField::Distance | SortField::Relevance => {
+                SortKey::Time(self.created_at)
+            }
+            SortField::Price => {
+                SortKey::Number(self.price.as_ref().map(|p| p.0).unwrap_or_default())
+            }
+            SortField::Title => {
+                SortKey::Text(self.title.clone().unwrap_or_default().to_lowercase())
+            }
+        };
+        (key, self.id)
+    }
+}
+
+impl From<ListedAd> for ClassifiedAdV1Summary {
+    fn from(ad: ListedAd) -> Self {
+        Self {
+            id: ad.id.to_string(),
+            owner_id: ad.owner_id.to_string(),
+            title: ad.title,
+            text: ad.text,
+            price: ad.price.as_ref().and_then(|p| p.0.to_f64()),
+            currency: ad.price.map(|p| p.1),
+            category_id: ad.category_id.map(|id| id.to_string()),
+            attributes: attributes_to_json(&ad.attributes),
+            location: ad.location.as_ref().map(ClassifiedAdV1Location::from),
+            created_at: ad.created_at,
+        }
+    }
+}
+
+/// Which active ads a listing shows.
+#[derive(Default)]
+pub struct ClassifiedAdFilter {
+    /// Only ads priced in this currency.
+    pub currency: Option<String>,
+    /// Only ads in one of these categories.
+    pub categories: Option<HashSet<Uuid>>,
+    /// Only ads whose attributes meet all of these.
+    pub attributes: Vec<AttributeCondition>,
+}
+
+impl ClassifiedAdFilter {
+    fn matches(&self, ad: &ListedAd) -> bool {
+        let currency = self.currency.as_ref().is_none_or(|currency| {
+            ad.price
+                .as_ref()
+                .is_some_and(|(_, code)| code.eq_ignore_ascii_case(currency))
+        });
+        let category = self.categories.as_ref().is_none_or(|categories| {
+            ad.category_id
+                .is_some_and(|category_id| categories.contains(&category_id))
+        });
+        currency
+            && category
+            && self
+                .attributes
+                .iter()
+                .all(|condition| condition.matches(&ad.attributes))
+    }
+}
+
+/// Read model of the ads buyers can browse, kept up to date from the ad event log.
+#[derive(Default)]
+pub struct ActiveClassifiedAds {
+    _ads: RwLock<HashMap<Uuid, ListedAd>>,
+}
+
+impl ActiveClassifiedAds {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Follows the event log from the start for as long as it is open.
+    pub async fn run(self: Arc<Self>, log: Arc<ClassifiedAdEventLog>) {
+        let mut events = Box::pin(log.subscribe_all(0));
+        while let Some(event) = events.next().await {
+            self.when(&event);
+        }
+    }
+
+    fn when(&self, recorded: &RecordedEvent) {
+        let mut ads = self._ads.write().unwrap();
+        if let ClassifiedAdEvents::Created(e) = &recorded.event {
+            ads.insert(
+                e.id,
+                ListedAd {
+                    id: e.id,
+                    owner_id: e.owner_id,
+                    title: None,
+                    text: None,
+                    price: None,
+                    category_id: None,
+                    attributes: BTreeMap::new(),
+                    location: None,
+                    created_at: recorded.recorded_at,
+                    active: false,
+                },
+            );
+            return;
+        }
+        let Some(ad) = ads.get_mut(&recorded.stream_id) else {
+            return;
+        };
+        match &recorded.event {
+            ClassifiedAdEvents::TitleChanged(e) => ad.title = Some(e.title.clone()),
+            ClassifiedAdEvents::TextUpdated(e) => ad.text = Some(e.ad_text.clone()),
+            ClassifiedAdEvents::PriceUpdated(e) => {
+                ad.price = Some((e.price, e.currency_code.clone()))
+            }
+            ClassifiedAdEvents::CategoryChanged(e) => {
+                ad.category_id = Some(e.category_id);
+                ad.attributes = e.attributes.clone();
+            }
+            ClassifiedAdEvents::LocationSet(e) => {
+                ad.location = Location::new(e.latitude, e.longitude, &e.postal_area).ok()
+            }
+            ClassifiedAdEvents::Published(_) | ClassifiedAdEvents::Reactivated(_) => {
+                ad.active = true
+            }
+            ClassifiedAdEvents::SentForReview(_)
+            | ClassifiedAdEvents::Rejected(_)
+            | ClassifiedAdEvents::MarkedAsSold(_)
+            | ClassifiedAdEvents::Deactivated(_) => ad.active = false,
+            _ => {}
+        }
+    }
+
+    /// A page of the active ads that match `filter`. Sorting by price needs a
+    /// currency, as amounts in different currencies cannot be compared.
+    pub fn list(
+        &self,
+        filter: &ClassifiedAdFilter,
+        request: &PageRequest,
+    ) -> Result<Page<ClassifiedAdV1Summary>> {
+        if request.sort == SortField::Price && filter.currency.is_none() {
+            return Err(anyhow!("Sorting by price needs a currency"));
+        }
+        let ads: Vec<ListedAd> = self
+            ._ads
+            .read()
+            .unwrap()
+            .values()
+            .filter(|ad| ad.active && filter.matches(ad))
+            .cloned()
+            .collect();
+        let page = paginate(ads, request, |ad, sort| ad.sort_key(sort));
+        Ok(Page {
+            items: page
+                .items
+                .into_iter()
+                .map(ClassifiedAdV1Summary::from)
+                .collect(),
+            next_cursor: page.next_cursor,
+        })
+    }
+}
@@ -0,0 +1,56 @@
+use std::{fs, path::PathBuf};
+
+use anyhow::{Context, Result};
+use chrono::Utc;
+use uuid::Uuid;
+
+pub struct Mail {
+    pub to: String,
+    pub subject: String,
+    pub body: String,
+}
+
+/// Sends mail to users, such as password reset tokens.
+pub trait IMailSender: Send + Sync {
+    fn send(&self, mail: &Mail) -> Result<()>;
+}
+
+/// Stand-in for a real mail service: writes each message as an `.eml` file
+/// into a directory, where it can be read during development.
+pub struct FileMailSender {
+    _dir: PathBuf,
+    _from: String,
+}
+
+impl FileMailSender {
+    pub fn new(dir: impl Into<PathBuf>, from: impl Into<String>) -> Result<Self> {
+        let dir = dir.into();
+        fs::create_dir_all(&dir)
+            .with_context(|| format!("Could not create mail directory {}", dir.display()))?;
+        Ok(Self {
+            _dir: dir,
+            _from: from.into(),
+        })
+    }
+}
+
+impl IMailSender for FileMailSender {
+    fn send(&self, mail: &Mail) -> Result<()> {
+        let now = Utc::now();
+        let content = format!(
+            "From: {}\r\nTo: {}\r\nSubject: {}\r\nDate: {}\r\nContent-Type: text/plain; charset=utf-8\r\n\r\n{}\r\n",
+            self._from,
+            mail.to,
+            mail.subject,
+            now.to_rfc2822(),
+            mail.body
+        );
+        let path = self._dir.join(format!(
+            "{}-{}.eml",
+            now.format("%Y%m%dT%H%M%S"),
+            Uuid::new_v4()
+        ));
+        fs::write(&path, content)
+            .with_context(|| format!("Could not write mail to {}", path.display()))
+    }
+}
@@ -1,39 +1,374 @@
-use std::str::FromStr;
+use std::{str::FromStr, sync::Arc, time::Duration};
 
+use accounts::{
+    AccountV1AccessToken, AccountV1ChangePassword, AccountV1Login, AccountV1Register,
+    AccountV1Registered, AccountV1RequestPasswordReset, AccountV1ResetPassword,
+    Argon2idPasswordHasher, UserAccountApplicationService,
+};
+use anyhow::anyhow;
+use authentication::{JwtAuthentication, JwtAuthenticator, JwtIssuer, Principal};
+use authorization::{ConfiguredRoles, EndpointPolicy, RoleDirectory};
+use blob_store::{content_digest, IBlobStore, LocalFileBlobStore};
+use categories::{
+    attribute_from_json, into_definitions, AttributeCondition, CategoryApplicationService,
+    CategoryTree, CategoryV1Create, CategoryV1Details, CategoryV1Remove, CategoryV1SetAttributes,
+};
 use classified_ad::{
-    ClassifiedAdV1RequestToPublish, ClassifiedAdV1SetTitle, ClassifiedAdV1UpdatePrice,
-    ClassifiedAdV1UpdateText, ClassifiedAdsApplicationService,
-    ClassifiedAdsV1Create,
+    ClassifiedAdV1Approve, ClassifiedAdV1ChangeCategory, ClassifiedAdV1ConvertedPrice,
+    ClassifiedAdV1Deactivate, ClassifiedAdV1Details, ClassifiedAdV1MarkAsSold,
+    ClassifiedAdV1Reactivate, ClassifiedAdV1Reject, ClassifiedAdV1RemovePicture,
+    ClassifiedAdV1RequestToPublish, ClassifiedAdV1ResizePicture, ClassifiedAdV1SetLocation,
+    ClassifiedAdV1SetTitle, ClassifiedAdV1Unwatch, ClassifiedAdV1UpdatePrice,
+    ClassifiedAdV1UpdateText, ClassifiedAdV1UploadPicture, ClassifiedAdV1Watch,
+    ClassifiedAdsApplicationService, ClassifiedAdsV1Create,
+};
+use command_bus::{build_command_bus, dispatch_command};
+use errors::{bad_request, into_http_error};
+use etag::{parse_if_match, to_etag};
+use event_stream::{ad_events, all_ad_events, ClassifiedAdEventLog};
+use exchange_rates::{CachedExchangeRates, PriceConverter, StaticFileExchangeRates};
+use images::{
+    content_type_of, inspect_upload, inspect_upload_with_limit, make_thumbnail,
+    MAX_PROFILE_PHOTO_BYTES,
 };
+use listing::{ActiveClassifiedAds, ClassifiedAdFilter, ClassifiedAdV1Page};
+use mail::FileMailSender;
+use marketplace_domain::{
+    classified_ad::{ClassifiedAdAggregate, ClassifiedAdId},
+    currency_lookup::Iso4217CurrencyLookup,
+    user_account::{EmailAddress, Password},
+    user_profile::Role,
+    ITextModeration, UserId,
+};
+use marketplace_framework::{
+    AggregateRoot, CommandBus, CommandMetadata, CommandMetrics, EntityNotFound,
+    IdempotencyKeyInProgress,
+};
+use nearby::{ClassifiedAdV1NearbyPage, NearbyClassifiedAds, NearbyQuery};
+use notifications::{notifications_socket, NotificationProjection, NotificationStore, Watchlist};
+use pagination::{link_header, PageRequest, SortDirection, SortField};
 
 // use poem::{listener::TcpListener, middleware::AddData, EndpointExt, Route, Server};
 use poem::{
-    listener::TcpListener, middleware::Cors, web::Data, EndpointExt, Result,
-    Route, Server,
+    endpoint::StaticFilesEndpoint, get, http::Method, listener::TcpListener, middleware::Cors,
+    web::Data, EndpointExt, Result, Route, Server,
 };
 use poem_openapi::{
+    param::{Header, Path, Query},
     payload::{Json, PlainText},
-    OpenApi, OpenApiService,
+    ApiResponse, OpenApi, OpenApiService,
+};
+use rust_decimal::Decimal;
+use search::{ClassifiedAdSearch, ClassifiedAdV1SearchResults, SearchQuery};
+use text_moderation::{PurgomalumTextModeration, WordListTextModeration};
+use user_profile::{
+    UserProfileApplicationService, UserProfileV1Details, UserProfileV1GrantRole,
+    UserProfileV1RegisterUser, UserProfileV1RevokeRole, UserProfileV1RoleChange,
+    UserProfileV1UpdateDisplayName, UserProfileV1UpdateFullName, UserProfileV1UploadPhoto,
 };
-use traits::{IApplicationService, IHandleCommand};
 use uuid::Uuid;
+pub mod accounts;
+pub mod authentication;
+pub mod authorization;
+pub mod blob_store;
+pub mod categories;
 pub mod classified_ad;
+pub mod command_bus;
+pub mod errors;
+pub mod etag;
+pub mod event_stream;
+pub mod exchange_rates;
+pub mod images;
+pub mod listing;
+pub mod mail;
+pub mod nearby;
+pub mod notifications;
+pub mod pagination;
+pub mod search;
+pub mod text_moderation;
 pub mod traits;
+pub mod user_profile;
+
+const DEFAULT_EXCHANGE_RATES_FILE: &str =
+    concat!(env!("CARGO_MANIFEST_DIR"), "/exchange_rates.json");
+const EXCHANGE_RATES_REFRESH_AFTER: Duration = Duration::from_secs(60 * 60);
+/// Rates quoted longer ago than this are not used for conversions.
+const EXCHANGE_RATES_MAX_STALENESS: Duration = Duration::from_secs(7 * 24 * 60 * 60);
+
+const TEXT_MODERATION_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Lifetime of the access tokens handed out on login.
+const ACCESS_TOKEN_LIFETIME: Duration = Duration::from_secs(60 * 60);
+
+#[derive(ApiResponse)]
+enum GetClassifiedAdResponse {
+    /// The ad, tagged with its current version
+    #[oai(status = 200)]
+    Ok(Json<ClassifiedAdV1Details>, #[oai(header = "ETag")] String),
+}
+
+#[derive(ApiResponse)]
+enum ListClassifiedAdsResponse {
+    /// A page of ads, with links to the first and next pages
+    #[oai(status = 200)]
+    Ok(Json<ClassifiedAdV1Page>, #[oai(header = "Link")] String),
+}
+
+#[derive(ApiResponse)]
+enum ListNearbyClassifiedAdsResponse {
+    /// A page of ads, nearest first, with links to the first and next pages
+    #[oai(status = 200)]
+    Ok(
+        Json<ClassifiedAdV1NearbyPage>,
+        #[oai(header = "Link")] String,
+    ),
+}
+
+#[derive(ApiResponse)]
+enum SearchClassifiedAdsResponse {
+    /// A page of matching ads, best first, with links to the first and next pages
+    #[oai(status = 200)]
+    Ok(
+        Json<ClassifiedAdV1SearchResults>,
+        #[oai(header = "Link")] String,
+    ),
+}
+
+/// Metadata for a command sent by `principal`, who is checked by the command
+/// policy and the application services.
+fn command_metadata(
+    principal: &Principal,
+    idempotency_key: Option<String>,
+    if_match: Option<String>,
+) -> anyhow::Result<CommandMetadata> {
+    let mut metadata = CommandMetadata::new().with_caller_id(principal.user_id.value().to_string());
+    metadata.insert(principal.clone());
+    if let Some(key) = idempotency_key {
+        metadata = metadata.with_idempotency_key(key);
+    }
+    if let Some(if_match) = if_match {
+        if let Some(versions) = parse_if_match(&if_match)? {
+            metadata = metadata.with_expected_versions(versions);
+        }
+    }
+    Ok(metadata)
+}
 
 struct ClassifiedAdApi;
 #[OpenApi]
 impl ClassifiedAdApi {
+    /// Get a classified ad
+    #[oai(path = "/ad/:id", method = "get")]
+    async fn get(
+        &self,
+        application_service: Data<&ClassifiedAdsApplicationService>,
+        price_converter: Data<&PriceConverter>,
+        id: Path<String>,
+        /// Also show the price converted into this currency
+        currency: Query<Option<String>>,
+    ) -> Result<GetClassifiedAdResponse> {
+        let id = Uuid::from_str(id.as_str()).map_err(bad_request)?;
+        let ad = application_service
+            .load(ClassifiedAdId::new(id))
+            .map_err(into_http_error)?;
+        let mut details = ClassifiedAdV1Details::from(&ad);
+        if let (Some(currency), Some(price)) = (currency.0, ad.price()) {
+            let (converted, rate) = price_converter
+                .convert(&price.money, &currency)
+                .map_err(into_http_error)?;
+            details.converted_price = Some(ClassifiedAdV1ConvertedPrice::new(converted, rate));
+        }
+        Ok(GetClassifiedAdResponse::Ok(
+            Json(details),
+            to_etag(ad.version()),
+        ))
+    }
+    /// Browse active ads a page at a time. Follow `next_cursor`, or the `next`
+    /// link in the `Link` header, for the following page.
+    #[oai(path = "/ads", method = "get")]
+    #[allow(clippy::too_many_arguments)]
+    async fn list(
+        &self,
+        active_ads: Data<&Arc<ActiveClassifiedAds>>,
+        category_tree: Data<&Arc<CategoryTree>>,
+        /// created_at (default), price or title
+        sort: Query<Option<String>>,
+        /// asc, or desc (default)
+        order: Query<Option<String>>,
+        /// Only ads priced in this currency; required to sort by price
+        currency: Query<Option<String>>,
+        /// Only ads in this category or the categories below it
+        category: Query<Option<String>>,
+        /// Conditions on attributes, e.g. `mileage<=100000,fuel=diesel`
+        attributes: Query<Option<String>>,
+        /// Page size, up to 100
+        limit: Query<Option<u64>>,
+        cursor: Query<Option<String>>,
+    ) -> Result<ListClassifiedAdsResponse> {
+        let sort = match &sort.0 {
+            Some(sort) => SortField::from_str(sort).map_err(bad_request)?,
+            None => SortField::CreatedAt,
+        };
+        let direction = match &order.0 {
+            Some(order) => SortDirection::from_str(order).map_err(bad_request)?,
+            None => SortDirection::Desc,
+        };
+        let request = PageRequest::new(
+            sort,
+            direction,
+            limit.0.map(|limit| limit as usize),
+            cursor.0.as_deref(),
+            None,
+        )
+        .map_err(bad_request)?;
+        let categories = match &category.0 {
+            Some(id) => {
+                let id = Uuid::from_str(id).map_err(bad_request)?;
+                Some(category_tree.subtree(id).map_err(into_http_error)?)
+            }
+            None => None,
+        };
+        let filter = ClassifiedAdFilter {
+            currency: currency.0.clone(),
+            categories,
+            attributes: match &attributes.0 {
+                Some(conditions) => {
+                    AttributeCondition::parse_list(conditions).map_err(bad_request)?
+                }
+                None => vec![],
+            },
+        };
+        let page = active_ads
+            .list(&filter, &request)
+            .map_err(into_http_error)?;
+        let mut query = vec![
+            ("sort", sort.to_string()),
+            ("order", direction.as_str().to_string()),
+            ("limit", request.limit.to_string()),
+        ];
+        for (name, value) in [
+            ("currency", currency.0),
+            ("category", category.0),
+            ("attributes", attributes.0),
+        ] {
+            if let Some(value) = value {
+                query.push((name, value));
+            }
+        }
+        let link = link_header("/ads", &query, &page);
+        Ok(ListClassifiedAdsResponse::Ok(
+            Json(ClassifiedAdV1Page::from(page)),
+            link,
+        ))
+    }
+    /// Browse active ads within a distance of a point, nearest first
+    #[oai(path = "/ads/nearby", method = "get")]
+    async fn nearby(
+        &self,
+        nearby_ads: Data<&Arc<NearbyClassifiedAds>>,
+        /// Degrees north of the equator
+        latitude: Query<f64>,
+        /// Degrees east of Greenwich
+        longitude: Query<f64>,
+        /// Up to 500 km
+        radius_km: Query<f64>,
+        /// Page size, up to 100
+        limit: Query<Option<u64>>,
+        cursor: Query<Option<String>>,
+    ) -> Result<ListNearbyClassifiedAdsResponse> {
+        let query = NearbyQuery {
+            latitude: latitude.0,
+            longitude: longitude.0,
+            radius_km: radius_km.0,
+        };
+        let request = PageRequest::new(
+            SortField::Distance,
+            SortDirection::Asc,
+            limit.0.map(|limit| limit as usize),
+            cursor.0.as_deref(),
+            Some(query.scope()),
+        )
+        .map_err(bad_request)?;
+        let page = nearby_ads
+            .nearby(&query, &request)
+            .map_err(into_http_error)?;
+        let params = vec![
+            ("latitude", query.latitude.to_string()),
+            ("longitude", query.longitude.to_string()),
+            ("radius_km", query.radius_km.to_string()),
+            ("limit", request.limit.to_string()),
+        ];
+        let link = link_header("/ads/nearby", &params, &page);
+        Ok(ListNearbyClassifiedAdsResponse::Ok(
+            Json(ClassifiedAdV1NearbyPage::from(page)),
+            link,
+        ))
+    }
+    /// Search active ads by the words in their title and text, best matches first
+    #[oai(path = "/ads/search", method = "get")]
+    #[allow(clippy::too_many_arguments)]
+    async fn search(
+        &self,
+        search: Data<&Arc<ClassifiedAdSearch>>,
+        /// Words to look for; leave empty to list every active ad
+        q: Query<Option<String>>,
+        /// Only ads priced in this currency; required with a price filter
+        currency: Query<Option<String>>,
+        min_price: Query<Option<f64>>,
+        max_price: Query<Option<f64>>,
+        /// Page size, up to 100
+        limit: Query<Option<u64>>,
+        cursor: Query<Option<String>>,
+    ) -> Result<SearchClassifiedAdsResponse> {
+        let query = SearchQuery {
+            text: q.0.unwrap_or_default(),
+            currency: currency.0,
+            min_price: min_price.0,
+            max_price: max_price.0,
+        };
+        let request = PageRequest::new(
+            SortField::Relevance,
+            SortDirection::Desc,
+            limit.0.map(|limit| limit as usize),
+            cursor.0.as_deref(),
+            Some(query.scope()),
+        )
+        .map_err(bad_request)?;
+        let results = search.search(&query, &request).map_err(into_http_error)?;
+        let mut params = vec![("q", query.text.clone())];
+        if let Some(currency) = &query.currency {
+            params.push(("currency", currency.clone()));
+        }
+        if let Some(min_price) = query.min_price {
+            params.push(("min_price", min_price.to_string()));
+        }
+        if let Some(max_price) = query.max_price {
+            params.push(("max_price", max_price.to_string()));
+        }
+        params.push(("limit", request.limit.to_string()));
+        let link = link_header("/ads/search", &params, &results.page);
+        Ok(SearchClassifiedAdsResponse::Ok(
+            Json(ClassifiedAdV1SearchResults::from(results)),
+            link,
+        ))
+    }
     /// Create a classified ad
     #[oai(path = "/ad", method = "post")]
     async fn create(
         &self,
-        application_service: Data<&ClassifiedAdsApplicationService>,
+        command_bus: Data<&Arc<CommandBus>>,
+        principal: Data<&Principal>,
+        #[oai(name = "Idempotency-Key")] idempotency_key: Header<Option<String>>,
         request: Json<ClassifiedAdsV1Create>,
     ) -> Result<PlainText<String>> {
-        let id = Uuid::from_str(request.id.as_str()).unwrap();
-        let owner_id = Uuid::from_str(request.owner_id.as_str()).unwrap();
+        let id = Uuid::from_str(request.id.as_str()).map_err(bad_request)?;
+        let owner_id = Uuid::from_str(request.owner_id.as_str()).map_err(bad_request)?;
         let cmd = marketplace_contracts::classified_ads::v1::Create { id, owner_id };
-        application_service.handle(cmd);
+        let metadata =
+            command_metadata(&principal, idempotency_key.0, None).map_err(bad_request)?;
+        dispatch_command(&command_bus, cmd, metadata)
+            .await
+            .map_err(into_http_error)?;
 
         Ok(PlainText(String::from("Created")))
     }
@@ -41,26 +376,40 @@ impl ClassifiedAdApi {
     #[oai(path = "/ad/title", method = "put")]
     async fn update_title(
         &self,
-        application_service: Data<&ClassifiedAdsApplicationService>,
+        command_bus: Data<&Arc<CommandBus>>,
+        principal: Data<&Principal>,
+        #[oai(name = "Idempotency-Key")] idempotency_key: Header<Option<String>>,
+        #[oai(name = "If-Match")] if_match: Header<Option<String>>,
         request: Json<ClassifiedAdV1SetTitle>,
     ) -> Result<PlainText<String>> {
-        let id = Uuid::from_str(request.id.as_str()).unwrap();
+        let id = Uuid::from_str(request.id.as_str()).map_err(bad_request)?;
         let title = request.title.clone();
         let cmd = marketplace_contracts::classified_ads::v1::SetTitle { id, title };
-        application_service.handle(cmd);
+        let metadata =
+            command_metadata(&principal, idempotency_key.0, if_match.0).map_err(bad_request)?;
+        dispatch_command(&command_bus, cmd, metadata)
+            .await
+            .map_err(into_http_error)?;
         Ok(PlainText(String::from("Updated")))
     }
     /// Update the text of an add
     #[oai(path = "/ad/text", method = "put")]
     async fn update_text(
         &self,
-        application_service: Data<&ClassifiedAdsApplicationService>,
+        command_bus: Data<&Arc<CommandBus>>,
+        principal: Data<&Principal>,
+        #[oai(name = "Idempotency-Key")] idempotency_key: Header<Option<String>>,
+        #[oai(name = "If-Match")] if_match: Header<Option<String>>,
         request: Json<ClassifiedAdV1UpdateText>,
     ) -> Result<PlainText<String>> {
-        let id = Uuid::from_str(request.id.as_str()).unwrap();
+        let id = Uuid::from_str(request.id.as_str()).map_err(bad_request)?;
         let text = request.text.clone();
         let cmd = marketplace_contracts::classified_ads::v1::UpdateText { id, text };
-        application_service.handle(cmd);
+        let metadata =
+            command_metadata(&principal, idempotency_key.0, if_match.0).map_err(bad_request)?;
+        dispatch_command(&command_bus, cmd, metadata)
+            .await
+            .map_err(into_http_error)?;
 
         Ok(PlainText(String::from("Updated")))
     }
@@ -68,32 +417,803 @@ impl ClassifiedAdApi {
     #[oai(path = "/ad/price", method = "put")]
     async fn update_price(
         &self,
-        application_service: Data<&ClassifiedAdsApplicationService>,
+        command_bus: Data<&Arc<CommandBus>>,
+        principal: Data<&Principal>,
+        #[oai(name = "Idempotency-Key")] idempotency_key: Header<Option<String>>,
+        #[oai(name = "If-Match")] if_match: Header<Option<String>>,
         request: Json<ClassifiedAdV1UpdatePrice>,
     ) -> Result<PlainText<String>> {
-        let id = Uuid::from_str(request.id.as_str()).unwrap();
-        let price = request.price;
+        let id = Uuid::from_str(request.id.as_str()).map_err(bad_request)?;
+        let price = Decimal::try_from(request.price).map_err(bad_request)?;
         let currency = request.currency.clone();
         let cmd = marketplace_contracts::classified_ads::v1::UpdatePrice {
             id,
             price,
             currency,
         };
-        application_service.handle(cmd);
+        let metadata =
+            command_metadata(&principal, idempotency_key.0, if_match.0).map_err(bad_request)?;
+        dispatch_command(&command_bus, cmd, metadata)
+            .await
+            .map_err(into_http_error)?;
+        Ok(PlainText(String::from("Updated")))
+    }
+    /// Place the ad in a category, with values for the category's attributes
+    #[oai(path = "/ad/category", method = "put")]
+    async fn change_category(
+        &self,
+        command_bus: Data<&Arc<CommandBus>>,
+        principal: Data<&Principal>,
+        #[oai(name = "Idempotency-Key")] idempotency_key: Header<Option<String>>,
+        #[oai(name = "If-Match")] if_match: Header<Option<String>>,
+        request: Json<ClassifiedAdV1ChangeCategory>,
+    ) -> Result<PlainText<String>> {
+        let id = Uuid::from_str(request.id.as_str()).map_err(bad_request)?;
+        let category_id = Uuid::from_str(request.category_id.as_str()).map_err(bad_request)?;
+        let attributes = request
+            .attributes
+            .iter()
+            .map(|(name, value)| Ok((name.clone(), attribute_from_json(name, value)?)))
+            .collect::<anyhow::Result<_>>()
+            .map_err(bad_request)?;
+        let cmd = marketplace_contracts::classified_ads::v1::ChangeCategory {
+            id,
+            category_id,
+            attributes,
+        };
+        let metadata =
+            command_metadata(&principal, idempotency_key.0, if_match.0).map_err(bad_request)?;
+        dispatch_command(&command_bus, cmd, metadata)
+            .await
+            .map_err(into_http_error)?;
+        Ok(PlainText(String::from("Updated")))
+    }
+    /// Set where the item can be picked up
+    #[oai(path = "/ad/location", method = "put")]
+    async fn set_location(
+        &self,
+        command_bus: Data<&Arc<CommandBus>>,
+        principal: Data<&Principal>,
+        #[oai(name = "Idempotency-Key")] idempotency_key: Header<Option<String>>,
+        #[oai(name = "If-Match")] if_match: Header<Option<String>>,
+        request: Json<ClassifiedAdV1SetLocation>,
+    ) -> Result<PlainText<String>> {
+        let id = Uuid::from_str(request.id.as_str()).map_err(bad_request)?;
+        let cmd = marketplace_contracts::classified_ads::v1::SetLocation {
+            id,
+            latitude: request.latitude,
+            longitude: request.longitude,
+            postal_area: request.postal_area.clone(),
+        };
+        let metadata =
+            command_metadata(&principal, idempotency_key.0, if_match.0).map_err(bad_request)?;
+        dispatch_command(&command_bus, cmd, metadata)
+            .await
+            .map_err(into_http_error)?;
         Ok(PlainText(String::from("Updated")))
     }
     /// Update the price
     #[oai(path = "/ad/publish", method = "put")]
     async fn publish(
         &self,
-        application_service: Data<&ClassifiedAdsApplicationService>,
+        command_bus: Data<&Arc<CommandBus>>,
+        principal: Data<&Principal>,
+        #[oai(name = "Idempotency-Key")] idempotency_key: Header<Option<String>>,
+        #[oai(name = "If-Match")] if_match: Header<Option<String>>,
         request: Json<ClassifiedAdV1RequestToPublish>,
     ) -> Result<PlainText<String>> {
-        let id = Uuid::from_str(request.id.as_str()).unwrap();
+        let id = Uuid::from_str(request.id.as_str()).map_err(bad_request)?;
         let cmd = marketplace_contracts::classified_ads::v1::RequestToPublish { id };
-        application_service.handle(cmd);
+        let metadata =
+            command_metadata(&principal, idempotency_key.0, if_match.0).map_err(bad_request)?;
+        dispatch_command(&command_bus, cmd, metadata)
+            .await
+            .map_err(into_http_error)?;
         Ok(PlainText(String::from("Updated")))
     }
+    /// Watch an ad to be notified when its price drops
+    #[oai(path = "/ad/watch", method = "put")]
+    async fn watch(
+        &self,
+        application_service: Data<&ClassifiedAdsApplicationService>,
+        watchlist: Data<&Arc<Watchlist>>,
+        principal: Data<&Principal>,
+        request: Json<ClassifiedAdV1Watch>,
+    ) -> Result<PlainText<String>> {
+        let id = Uuid::from_str(request.id.as_str()).map_err(bad_request)?;
+        application_service
+            .load(ClassifiedAdId::new(id))
+            .map_err(into_http_error)?;
+        watchlist.watch(id, principal.user_id.value());
+        Ok(PlainText(String::from("Watching")))
+    }
+    /// Stop watching an ad
+    #[oai(path = "/ad/unwatch", method = "put")]
+    async fn unwatch(
+        &self,
+        watchlist: Data<&Arc<Watchlist>>,
+        principal: Data<&Principal>,
+        request: Json<ClassifiedAdV1Unwatch>,
+    ) -> Result<PlainText<String>> {
+        let id = Uuid::from_str(request.id.as_str()).map_err(bad_request)?;
+        watchlist.unwatch(id, principal.user_id.value());
+        Ok(PlainText(String::from("Not watching")))
+    }
+    /// Approve an ad pending review (moderators only)
+    #[oai(path = "/ad/approve", method = "put")]
+    async fn approve(
+        &self,
+        command_bus: Data<&Arc<CommandBus>>,
+        principal: Data<&Principal>,
+        #[oai(name = "Idempotency-Key")] idempotency_key: Header<Option<String>>,
+        #[oai(name = "If-Match")] if_match: Header<Option<String>>,
+        request: Json<ClassifiedAdV1Approve>,
+    ) -> Result<PlainText<String>> {
+        let id = Uuid::from_str(request.id.as_str()).map_err(bad_request)?;
+        let approved_by = principal.user_id.value();
+        let cmd = marketplace_contracts::classified_ads::v1::Approve { id, approved_by };
+        let metadata =
+            command_metadata(&principal, idempotency_key.0, if_match.0).map_err(bad_request)?;
+        dispatch_command(&command_bus, cmd, metadata)
+            .await
+            .map_err(into_http_error)?;
+        Ok(PlainText(String::from("Approved")))
+    }
+    /// Reject an ad pending review (moderators only)
+    #[oai(path = "/ad/reject", method = "put")]
+    async fn reject(
+        &self,
+        command_bus: Data<&Arc<CommandBus>>,
+        principal: Data<&Principal>,
+        #[oai(name = "Idempotency-Key")] idempotency_key: Header<Option<String>>,
+        #[oai(name = "If-Match")] if_match: Header<Option<String>>,
+        request: Json<ClassifiedAdV1Reject>,
+    ) -> Result<PlainText<String>> {
+        let id = Uuid::from_str(request.id.as_str()).map_err(bad_request)?;
+        let rejected_by = principal.user_id.value();
+        let reason = request.reason.clone();
+        let cmd = marketplace_contracts::classified_ads::v1::Reject {
+            id,
+            rejected_by,
+            reason,
+        };
+        let metadata =
+            command_metadata(&principal, idempotency_key.0, if_match.0).map_err(bad_request)?;
+        dispatch_command(&command_bus, cmd, metadata)
+            .await
+            .map_err(into_http_error)?;
+        Ok(PlainText(String::from("Rejected")))
+    }
+    /// Mark an active ad as sold
+    #[oai(path = "/ad/sold", method = "put")]
+    async fn mark_as_sold(
+        &self,
+        command_bus: Data<&Arc<CommandBus>>,
+        principal: Data<&Principal>,
+        #[oai(name = "Idempotency-Key")] idempotency_key: Header<Option<String>>,
+        #[oai(name = "If-Match")] if_match: Header<Option<String>>,
+        request: Json<ClassifiedAdV1MarkAsSold>,
+    ) -> Result<PlainText<String>> {
+        let id = Uuid::from_str(request.id.as_str()).map_err(bad_request)?;
+        let cmd = marketplace_contracts::classified_ads::v1::MarkAsSold { id };
+        let metadata =
+            command_metadata(&principal, idempotency_key.0, if_match.0).map_err(bad_request)?;
+        dispatch_command(&command_bus, cmd, metadata)
+            .await
+            .map_err(into_http_error)?;
+        Ok(PlainText(String::from("Sold")))
+    }
+    /// Withdraw an active or pending ad
+    #[oai(path = "/ad/deactivate", method = "put")]
+    async fn deactivate(
+        &self,
+        command_bus: Data<&Arc<CommandBus>>,
+        principal: Data<&Principal>,
+        #[oai(name = "Idempotency-Key")] idempotency_key: Header<Option<String>>,
+        #[oai(name = "If-Match")] if_match: Header<Option<String>>,
+        request: Json<ClassifiedAdV1Deactivate>,
+    ) -> Result<PlainText<String>> {
+        let id = Uuid::from_str(request.id.as_str()).map_err(bad_request)?;
+        let cmd = marketplace_contracts::classified_ads::v1::Deactivate { id };
+        let metadata =
+            command_metadata(&principal, idempotency_key.0, if_match.0).map_err(bad_request)?;
+        dispatch_command(&command_bus, cmd, metadata)
+            .await
+            .map_err(into_http_error)?;
+        Ok(PlainText(String::from("Deactivated")))
+    }
+    /// Put a withdrawn ad back on the market, sending it for review if it changed
+    #[oai(path = "/ad/reactivate", method = "put")]
+    async fn reactivate(
+        &self,
+        command_bus: Data<&Arc<CommandBus>>,
+        principal: Data<&Principal>,
+        #[oai(name = "Idempotency-Key")] idempotency_key: Header<Option<String>>,
+        #[oai(name = "If-Match")] if_match: Header<Option<String>>,
+        request: Json<ClassifiedAdV1Reactivate>,
+    ) -> Result<PlainText<String>> {
+        let id = Uuid::from_str(request.id.as_str()).map_err(bad_request)?;
+        let cmd = marketplace_contracts::classified_ads::v1::Reactivate { id };
+        let metadata =
+            command_metadata(&principal, idempotency_key.0, if_match.0).map_err(bad_request)?;
+        dispatch_command(&command_bus, cmd, metadata)
+            .await
+            .map_err(into_http_error)?;
+        Ok(PlainText(String::from("Reactivated")))
+    }
+    /// Upload a picture for an ad, returning the id of the new picture
+    #[oai(path = "/ad/picture", method = "post")]
+    async fn upload_picture(
+        &self,
+        command_bus: Data<&Arc<CommandBus>>,
+        principal: Data<&Principal>,
+        blob_store: Data<&Arc<dyn IBlobStore>>,
+        #[oai(name = "Idempotency-Key")] idempotency_key: Header<Option<String>>,
+        #[oai(name = "If-Match")] if_match: Header<Option<String>>,
+        request: ClassifiedAdV1UploadPicture,
+    ) -> Result<PlainText<String>> {
+        let id = Uuid::from_str(request.id.as_str()).map_err(bad_request)?;
+        let picture_id = match &idempotency_key.0 {
+            Some(key) => Uuid::new_v5(&id, key.as_bytes()),
+            None => Uuid::new_v4(),
+        };
+        let content_type = request.file.content_type().map(str::to_string);
+        let content = request.file.into_vec().await.map_err(bad_request)?;
+        let image = inspect_upload(content_type.as_deref(), &content).map_err(bad_request)?;
+        let key = format!(
+            "ads/{id}/{picture_id}-{}.{}",
+            content_digest(&content),
+            image.extension
+        );
+        let url = blob_store.put(&key, &content).map_err(into_http_error)?;
+        let cmd = marketplace_contracts::classified_ads::v1::AddPicture {
+            id,
+            picture_id,
+            url,
+            width: image.width,
+            height: image.height,
+        };
+        let metadata =
+            command_metadata(&principal, idempotency_key.0, if_match.0).map_err(bad_request)?;
+        if let Err(e) = dispatch_command(&command_bus, cmd, metadata).await {
+            // The same bytes under the same key are still being added by
+            // another request, which owns the blob.
+            if !e.is::<IdempotencyKeyInProgress>() {
+                let _ = blob_store.delete(&key);
+            }
+            return Err(into_http_error(e));
+        }
+        Ok(PlainText(picture_id.to_string()))
+    }
+    /// Record the size of a picture as read from its stored file. The size is
+    /// never taken from the caller.
+    #[oai(path = "/ad/picture/resize", method = "put")]
+    #[allow(clippy::too_many_arguments)]
+    async fn resize_picture(
+        &self,
+        command_bus: Data<&Arc<CommandBus>>,
+        principal: Data<&Principal>,
+        application_service: Data<&ClassifiedAdsApplicationService>,
+        blob_store: Data<&Arc<dyn IBlobStore>>,
+        #[oai(name = "Idempotency-Key")] idempotency_key: Header<Option<String>>,
+        #[oai(name = "If-Match")] if_match: Header<Option<String>>,
+        request: Json<ClassifiedAdV1ResizePicture>,
+    ) -> Result<PlainText<String>> {
+        let id = Uuid::from_str(request.id.as_str()).map_err(bad_request)?;
+        let picture_id = Uuid::from_str(request.picture_id.as_str()).map_err(bad_request)?;
+        let ad = application_service
+            .load(ClassifiedAdId::new(id))
+            .map_err(into_http_error)?;
+        let picture = ad
+            .pictures()
+            .into_iter()
+            .find(|p| p.id().value() == picture_id)
+            .ok_or_else(|| {
+                into_http_error(
+                    EntityNotFound {
+                        id: picture_id.to_string(),
+                    }
+                    .into(),
+                )
+            })?;
+        let key = blob_store
+            .key_of(&picture.location())
+            .ok_or_else(|| into_http_error(anyhow!("The picture is not in the blob store")))?;
+        let content = blob_store.get(&key).map_err(into_http_error)?;
+        let image = inspect_upload(content_type_of(&key), &content).map_err(into_http_error)?;
+        let cmd = marketplace_contracts::classified_ads::v1::ResizePicture {
+            id,
+            picture_id,
+            width: image.width,
+            height: image.height,
+        };
+        let metadata =
+            command_metadata(&principal, idempotency_key.0, if_match.0).map_err(bad_request)?;
+        dispatch_command(&command_bus, cmd, metadata)
+            .await
+            .map_err(into_http_error)?;
+        Ok(PlainText(String::from("Updated")))
+    }
+    /// Remove a picture from an ad
+    #[oai(path = "/ad/picture/remove", method = "put")]
+    async fn remove_picture(
+        &self,
+        command_bus: Data<&Arc<CommandBus>>,
+        principal: Data<&Principal>,
+        #[oai(name = "Idempotency-Key")] idempotency_key: Header<Option<String>>,
+        #[oai(name = "If-Match")] if_match: Header<Option<String>>,
+        request: Json<ClassifiedAdV1RemovePicture>,
+    ) -> Result<PlainText<String>> {
+        let id = Uuid::from_str(request.id.as_str()).map_err(bad_request)?;
+        let picture_id = Uuid::from_str(request.picture_id.as_str()).map_err(bad_request)?;
+        let cmd = marketplace_contracts::classified_ads::v1::RemovePicture { id, picture_id };
+        let metadata =
+            command_metadata(&principal, idempotency_key.0, if_match.0).map_err(bad_request)?;
+        dispatch_command(&command_bus, cmd, metadata)
+            .await
+            .map_err(into_http_error)?;
+        Ok(PlainText(String::from("Removed")))
+    }
+}
+
+#[derive(ApiResponse)]
+enum GetUserProfileResponse {
+    /// The profile, tagged with its current version
+    #[oai(status = 200)]
+    Ok(Json<UserProfileV1Details>, #[oai(header = "ETag")] String),
+}
+
+struct UserProfileApi;
+#[OpenApi]
+impl UserProfileApi {
+    /// Get a user profile
+    #[oai(path = "/profile/:id", method = "get")]
+    async fn get_profile(
+        &self,
+        application_service: Data<&UserProfileApplicationService>,
+        id: Path<String>,
+    ) -> Result<GetUserProfileResponse> {
+        let id = Uuid::from_str(id.as_str()).map_err(bad_request)?;
+        let profile = application_service
+            .load(UserId::new(id))
+            .map_err(into_http_error)?;
+        Ok(GetUserProfileResponse::Ok(
+            Json(UserProfileV1Details::from(&profile)),
+            to_etag(profile.version()),
+        ))
+    }
+    /// Register a profile for the signed in user
+    #[oai(path = "/profile", method = "post")]
+    async fn register(
+        &self,
+        command_bus: Data<&Arc<CommandBus>>,
+        principal: Data<&Principal>,
+        #[oai(name = "Idempotency-Key")] idempotency_key: Header<Option<String>>,
+        request: Json<UserProfileV1RegisterUser>,
+    ) -> Result<PlainText<String>> {
+        let user_id = principal.user_id.value();
+        let cmd = marketplace_contracts::user_profiles::v1::RegisterUser {
+            user_id,
+            full_name: request.full_name.clone(),
+            display_name: request.display_name.clone(),
+        };
+        let metadata =
+            command_metadata(&principal, idempotency_key.0, None).map_err(bad_request)?;
+        dispatch_command(&command_bus, cmd, metadata)
+            .await
+            .map_err(into_http_error)?;
+        Ok(PlainText(String::from("Registered")))
+    }
+    /// Update the full name on the signed in user's profile
+    #[oai(path = "/profile/full-name", method = "put")]
+    async fn update_full_name(
+        &self,
+        command_bus: Data<&Arc<CommandBus>>,
+        principal: Data<&Principal>,
+        #[oai(name = "Idempotency-Key")] idempotency_key: Header<Option<String>>,
+        #[oai(name = "If-Match")] if_match: Header<Option<String>>,
+        request: Json<UserProfileV1UpdateFullName>,
+    ) -> Result<PlainText<String>> {
+        let user_id = principal.user_id.value();
+        let cmd = marketplace_contracts::user_profiles::v1::UpdateFullName {
+            user_id,
+            full_name: request.full_name.clone(),
+        };
+        let metadata =
+            command_metadata(&principal, idempotency_key.0, if_match.0).map_err(bad_request)?;
+        dispatch_command(&command_bus, cmd, metadata)
+            .await
+            .map_err(into_http_error)?;
+        Ok(PlainText(String::from("Updated")))
+    }
+    /// Update the display name on the signed in user's profile
+    #[oai(path = "/profile/display-name", method = "put")]
+    async fn update_display_name(
+        &self,
+        command_bus: Data<&Arc<CommandBus>>,
+        principal: Data<&Principal>,
+        #[oai(name = "Idempotency-Key")] idempotency_key: Header<Option<String>>,
+        #[oai(name = "If-Match")] if_match: Header<Option<String>>,
+        request: Json<UserProfileV1UpdateDisplayName>,
+    ) -> Result<PlainText<String>> {
+        let user_id = principal.user_id.value();
+        let cmd = marketplace_contracts::user_profiles::v1::UpdateDisplayName {
+            user_id,
+            display_name: request.display_name.clone(),
+        };
+        let metadata =
+            command_metadata(&principal, idempotency_key.0, if_match.0).map_err(bad_request)?;
+        dispatch_command(&command_bus, cmd, metadata)
+            .await
+            .map_err(into_http_error)?;
+        Ok(PlainText(String::from("Updated")))
+    }
+    /// Grant a role to a user (admins only)
+    #[oai(path = "/profile/roles/grant", method = "put")]
+    async fn grant_role(
+        &self,
+        command_bus: Data<&Arc<CommandBus>>,
+        principal: Data<&Principal>,
+        #[oai(name = "Idempotency-Key")] idempotency_key: Header<Option<String>>,
+        #[oai(name = "If-Match")] if_match: Header<Option<String>>,
+        request: Json<UserProfileV1GrantRole>,
+    ) -> Result<PlainText<String>> {
+        let user_id = Uuid::from_str(request.user_id.as_str()).map_err(bad_request)?;
+        let cmd = marketplace_contracts::user_profiles::v1::GrantRole {
+            user_id,
+            role: request.role.clone(),
+            granted_by: principal.user_id.value(),
+        };
+        let metadata =
+            command_metadata(&principal, idempotency_key.0, if_match.0).map_err(bad_request)?;
+        dispatch_command(&command_bus, cmd, metadata)
+            .await
+            .map_err(into_http_error)?;
+        Ok(PlainText(String::from("Granted")))
+    }
+    /// Revoke a role from a user (admins only)
+    #[oai(path = "/profile/roles/revoke", method = "put")]
+    async fn revoke_role(
+        &self,
+        command_bus: Data<&Arc<CommandBus>>,
+        principal: Data<&Principal>,
+        #[oai(name = "Idempotency-Key")] idempotency_key: Header<Option<String>>,
+        #[oai(name = "If-Match")] if_match: Header<Option<String>>,
+        request: Json<UserProfileV1RevokeRole>,
+    ) -> Result<PlainText<String>> {
+        let user_id = Uuid::from_str(request.user_id.as_str()).map_err(bad_request)?;
+        let cmd = marketplace_contracts::user_profiles::v1::RevokeRole {
+            user_id,
+            role: request.role.clone(),
+            revoked_by: principal.user_id.value(),
+        };
+        let metadata =
+            command_metadata(&principal, idempotency_key.0, if_match.0).map_err(bad_request)?;
+        dispatch_command(&command_bus, cmd, metadata)
+            .await
+            .map_err(into_http_error)?;
+        Ok(PlainText(String::from("Revoked")))
+    }
+    /// Role grants and revocations on a profile, oldest first (admins only)
+    #[oai(path = "/profile/:id/roles/history", method = "get")]
+    async fn role_history(
+        &self,
+        application_service: Data<&UserProfileApplicationService>,
+        id: Path<String>,
+    ) -> Result<Json<Vec<UserProfileV1RoleChange>>> {
+        let id = Uuid::from_str(id.as_str()).map_err(bad_request)?;
+        let profile = application_service
+            .load(UserId::new(id))
+            .map_err(into_http_error)?;
+        Ok(Json(UserProfileV1RoleChange::history(&profile)))
+    }
+    /// Upload a new photo for the signed in user's profile. A thumbnail is generated
+    /// and stored next to it.
+    #[oai(path = "/profile/photo", method = "post")]
+    async fn upload_photo(
+        &self,
+        command_bus: Data<&Arc<CommandBus>>,
+        principal: Data<&Principal>,
+        blob_store: Data<&Arc<dyn IBlobStore>>,
+        #[oai(name = "Idempotency-Key")] idempotency_key: Header<Option<String>>,
+        #[oai(name = "If-Match")] if_match: Header<Option<String>>,
+        request: UserProfileV1UploadPhoto,
+    ) -> Result<PlainText<String>> {
+        let user_id = principal.user_id.value();
+        let photo_id = match &idempotency_key.0 {
+            Some(key) => Uuid::new_v5(&user_id, key.as_bytes()),
+            None => Uuid::new_v4(),
+        };
+        let content_type = request.file.content_type().map(str::to_string);
+        let content = request.file.into_vec().await.map_err(bad_request)?;
+        let image =
+            inspect_upload_with_limit(content_type.as_deref(), &content, MAX_PROFILE_PHOTO_BYTES)
+                .map_err(bad_request)?;
+        let (content, thumbnail) = tokio::task::spawn_blocking(move || {
+            make_thumbnail(&content, image.format).map(|thumbnail| (content, thumbnail))
+        })
+        .await
+        .map_err(bad_request)?
+        .map_err(bad_request)?;
+
+        let digest = content_digest(&content);
+        let key = format!("profiles/{user_id}/{photo_id}-{digest}.{}", image.extension);
+        let thumbnail_key = format!(
+            "profiles/{user_id}/{photo_id}-{digest}-thumbnail.{}",
+            image.extension
+        );
+        let photo_url = blob_store.put(&key, &content).map_err(into_http_error)?;
+        let thumbnail_url = match blob_store.put(&thumbnail_key, &thumbnail) {
+            Ok(url) => url,
+            Err(e) => {
+                let _ = blob_store.delete(&key);
+                return Err(into_http_error(e));
+            }
+        };
+        let cmd = marketplace_contracts::user_profiles::v1::UpdateProfilePhoto {
+            user_id,
+            photo_url,
+            thumbnail_url,
+        };
+        let metadata =
+            command_metadata(&principal, idempotency_key.0, if_match.0).map_err(bad_request)?;
+        if let Err(e) = dispatch_command(&command_bus, cmd, metadata).await {
+            if !e.is::<IdempotencyKeyInProgress>() {
+                let _ = blob_store.delete(&key);
+                let _ = blob_store.delete(&thumbnail_key);
+            }
+            return Err(into_http_error(e));
+        }
+        Ok(PlainText(photo_id.to_string()))
+    }
+}
+
+struct CategoryApi;
+#[OpenApi]
+impl CategoryApi {
+    /// Every category, each after its parent
+    #[oai(path = "/categories", method = "get")]
+    async fn list_categories(
+        &self,
+        category_tree: Data<&Arc<CategoryTree>>,
+    ) -> Json<Vec<CategoryV1Details>> {
+        Json(category_tree.list())
+    }
+    /// Get a category with every attribute ads in it can have
+    #[oai(path = "/category/:id", method = "get")]
+    async fn get_category(
+        &self,
+        category_tree: Data<&Arc<CategoryTree>>,
+        id: Path<String>,
+    ) -> Result<Json<CategoryV1Details>> {
+        let id = Uuid::from_str(id.as_str()).map_err(bad_request)?;
+        let details = category_tree.details(id).map_err(into_http_error)?;
+        Ok(Json(details))
+    }
+    /// Add a category (admins only)
+    #[oai(path = "/category", method = "post")]
+    async fn create_category(
+        &self,
+        command_bus: Data<&Arc<CommandBus>>,
+        principal: Data<&Principal>,
+        #[oai(name = "Idempotency-Key")] idempotency_key: Header<Option<String>>,
+        request: Json<CategoryV1Create>,
+    ) -> Result<PlainText<String>> {
+        let request = request.0;
+        let id = Uuid::from_str(request.id.as_str()).map_err(bad_request)?;
+        let parent_id = request
+            .parent_id
+            .map(|id| Uuid::from_str(&id))
+            .transpose()
+            .map_err(bad_request)?;
+        let cmd = marketplace_contracts::categories::v1::CreateCategory {
+            id,
+            parent_id,
+            name: request.name,
+            attributes: into_definitions(request.attributes).map_err(bad_request)?,
+        };
+        let metadata =
+            command_metadata(&principal, idempotency_key.0, None).map_err(bad_request)?;
+        dispatch_command(&command_bus, cmd, metadata)
+            .await
+            .map_err(into_http_error)?;
+        Ok(PlainText(String::from("Created")))
+    }
+    /// Replace the attributes of a category (admins only)
+    #[oai(path = "/category/attributes", method = "put")]
+    async fn set_category_attributes(
+        &self,
+        command_bus: Data<&Arc<CommandBus>>,
+        principal: Data<&Principal>,
+        #[oai(name = "Idempotency-Key")] idempotency_key: Header<Option<String>>,
+        request: Json<CategoryV1SetAttributes>,
+    ) -> Result<PlainText<String>> {
+        let request = request.0;
+        let cmd = marketplace_contracts::categories::v1::SetCategoryAttributes {
+            id: Uuid::from_str(request.id.as_str()).map_err(bad_request)?,
+            attributes: into_definitions(request.attributes).map_err(bad_request)?,
+        };
+        let metadata =
+            command_metadata(&principal, idempotency_key.0, None).map_err(bad_request)?;
+        dispatch_command(&command_bus, cmd, metadata)
+            .await
+            .map_err(into_http_error)?;
+        Ok(PlainText(String::from("Updated")))
+    }
+    /// Remove a category with no categories below it and no ads in it (admins only)
+    #[oai(path = "/category/remove", method = "put")]
+    async fn remove_category(
+        &self,
+        command_bus: Data<&Arc<CommandBus>>,
+        principal: Data<&Principal>,
+        #[oai(name = "Idempotency-Key")] idempotency_key: Header<Option<String>>,
+        request: Json<CategoryV1Remove>,
+    ) -> Result<PlainText<String>> {
+        let cmd = marketplace_contracts::categories::v1::RemoveCategory {
+            id: Uuid::from_str(request.id.as_str()).map_err(bad_request)?,
+        };
+        let metadata =
+            command_metadata(&principal, idempotency_key.0, None).map_err(bad_request)?;
+        dispatch_command(&command_bus, cmd, metadata)
+            .await
+            .map_err(into_http_error)?;
+        Ok(PlainText(String::from("Removed")))
+    }
+}
+
+struct AccountApi;
+#[OpenApi]
+impl AccountApi {
+    /// Create an account and its user profile
+    #[oai(path = "/account/register", method = "post")]
+    async fn register(
+        &self,
+        command_bus: Data<&Arc<CommandBus>>,
+        accounts: Data<&UserAccountApplicationService>,
+        request: Json<AccountV1Register>,
+    ) -> Result<Json<AccountV1Registered>> {
+        // Checked and the address held up front, so a bad email or password or
+        // a taken address does not leave a profile behind
+        let user_id = Uuid::new_v4();
+        EmailAddress::new(&request.email).map_err(bad_request)?;
+        Password::new(&request.password).map_err(bad_request)?;
+        let _reservation = accounts
+            .reserve_email(&request.email, user_id)
+            .map_err(into_http_error)?;
+        // The new user sends the commands setting up their own account
+        let principal = Principal {
+            user_id: UserId::new(user_id),
+            roles: vec![Role::User],
+        };
+        let profile = marketplace_contracts::user_profiles::v1::RegisterUser {
+            user_id,
+            full_name: request.full_name.clone(),
+            display_name: request.display_name.clone(),
+        };
+        let metadata = command_metadata(&principal, None, None).map_err(bad_request)?;
+        dispatch_command(&command_bus, profile, metadata)
+            .await
+            .map_err(into_http_error)?;
+        let account = marketplace_contracts::accounts::v1::RegisterAccount {
+            user_id,
+            email: request.email.clone(),
+            password: request.password.clone(),
+        };
+        let metadata = command_metadata(&principal, None, None).map_err(bad_request)?;
+        dispatch_command(&command_bus, account, metadata)
+            .await
+            .map_err(into_http_error)?;
+        Ok(Json(AccountV1Registered {
+            user_id: user_id.to_string(),
+        }))
+    }
+    /// Exchange an email address and password for an access token
+    #[oai(path = "/account/login", method = "post")]
+    async fn login(
+        &self,
+        accounts: Data<&UserAccountApplicationService>,
+        token_issuer: Data<&Arc<JwtIssuer>>,
+        request: Json<AccountV1Login>,
+    ) -> Result<Json<AccountV1AccessToken>> {
+        let accounts = accounts.clone();
+        let user_id =
+            tokio::task::spawn_blocking(move || accounts.login(&request.email, &request.password))
+                .await
+                .map_err(bad_request)?
+                .map_err(into_http_error)?;
+        let access_token = token_issuer.issue(&user_id).map_err(into_http_error)?;
+        Ok(Json(AccountV1AccessToken {
+            access_token,
+            token_type: String::from("Bearer"),
+            expires_in: token_issuer.lifetime().as_secs(),
+        }))
+    }
+    /// Change the password of the signed in user
+    #[oai(path = "/account/password", method = "put")]
+    async fn change_password(
+        &self,
+        command_bus: Data<&Arc<CommandBus>>,
+        principal: Data<&Principal>,
+        request: Json<AccountV1ChangePassword>,
+    ) -> Result<PlainText<String>> {
+        let cmd = marketplace_contracts::accounts::v1::ChangePassword {
+            user_id: principal.user_id.value(),
+            current_password: request.current_password.clone(),
+            new_password: request.new_password.clone(),
+        };
+        let metadata = command_metadata(&principal, None, None).map_err(bad_request)?;
+        dispatch_command(&command_bus, cmd, metadata)
+            .await
+            .map_err(into_http_error)?;
+        Ok(PlainText(String::from("Password changed")))
+    }
+    /// Mail a password reset token to the address, if an account uses it
+    #[oai(path = "/account/password-reset", method = "post")]
+    async fn request_password_reset(
+        &self,
+        command_bus: Data<&Arc<CommandBus>>,
+        request: Json<AccountV1RequestPasswordReset>,
+    ) -> Result<PlainText<String>> {
+        let cmd = marketplace_contracts::accounts::v1::RequestPasswordReset {
+            email: request.email.clone(),
+        };
+        dispatch_command(&command_bus, cmd, CommandMetadata::new())
+            .await
+            .map_err(into_http_error)?;
+        Ok(PlainText(String::from(
+            "If an account uses this address, a reset token has been sent to it",
+        )))
+    }
+    /// Choose a new password with a token from a password reset mail
+    #[oai(path = "/account/password-reset", method = "put")]
+    async fn reset_password(
+        &self,
+        command_bus: Data<&Arc<CommandBus>>,
+        request: Json<AccountV1ResetPassword>,
+    ) -> Result<PlainText<String>> {
+        let cmd = marketplace_contracts::accounts::v1::ResetPassword {
+            email: request.email.clone(),
+            token: request.token.clone(),
+            new_password: request.new_password.clone(),
+        };
+        dispatch_command(&command_bus, cmd, CommandMetadata::new())
+            .await
+            .map_err(into_http_error)?;
+        Ok(PlainText(String::from("Password changed")))
+    }
+}
+
+/// Roles required by endpoints beyond being signed in. Checked again for the
+/// commands they send by the command bus.
+fn endpoint_policy() -> EndpointPolicy {
+    EndpointPolicy::new()
+        .require(Method::PUT, "/ad/approve", Role::Moderator)
+        .require(Method::PUT, "/ad/reject", Role::Moderator)
+        .require(Method::PUT, "/profile/roles/grant", Role::Admin)
+        .require(Method::PUT, "/profile/roles/revoke", Role::Admin)
+        .require(Method::GET, "/profile/:id/roles/history", Role::Admin)
+        .require(Method::POST, "/category", Role::Admin)
+        .require(Method::PUT, "/category/attributes", Role::Admin)
+        .require(Method::PUT, "/category/remove", Role::Admin)
+        .require(Method::GET, "/ws", Role::User)
+}
+
+fn render_metrics(metrics: &CommandMetrics) -> String {
+    let mut lines: Vec<String> = metrics
+        .snapshot()
+        .into_iter()
+        .map(|(command, stats)| {
+            format!(
+                "{command} dispatched={} succeeded={} failed={} total_ms={}",
+                stats.dispatched,
+                stats.succeeded,
+                stats.failed,
+                stats.total_duration.as_millis()
+            )
+        })
+        .collect();
+    lines.sort();
+    lines.join("\n")
 }
 
 #[tokio::main]
@@ -102,18 +1222,119 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
         std::env::set_var("RUST_LOG", "poem=debug");
     }
     tracing_subscriber::fmt::init();
-    let classified_ads_application_service = ClassifiedAdsApplicationService::new();
+    // An ISO 4217 table in `code,minor_units,in_use` format can replace the bundled one
+    let currency_lookup = match std::env::var("MARKETPLACE_CURRENCIES_FILE") {
+        Ok(path) => Iso4217CurrencyLookup::from_file(path)?,
+        Err(_) => Iso4217CurrencyLookup::bundled(),
+    };
+    let currency_lookup = Arc::new(currency_lookup);
+    // Use a PurgoMalum style service when configured, otherwise the bundled word list
+    let text_moderation: Arc<dyn ITextModeration + Send + Sync> =
+        match std::env::var("MARKETPLACE_TEXT_MODERATION_URL") {
+            Ok(url) => Arc::new(PurgomalumTextModeration::new(url, TEXT_MODERATION_TIMEOUT)),
+            Err(_) => Arc::new(WordListTextModeration::bundled()),
+        };
+    let ad_event_log = Arc::new(ClassifiedAdEventLog::new());
+    let category_tree = Arc::new(CategoryTree::new());
+    tokio::spawn(category_tree.clone().run(ad_event_log.clone()));
+    let notification_store = Arc::new(NotificationStore::new());
+    let watchlist = Arc::new(Watchlist::new());
+    tokio::spawn(
+        NotificationProjection::new(notification_store.clone(), watchlist.clone())
+            .run(ad_event_log.clone()),
+    );
+    let ad_search = Arc::new(ClassifiedAdSearch::new()?);
+    tokio::spawn(ad_search.clone().run(ad_event_log.clone()));
+    let active_ads = Arc::new(ActiveClassifiedAds::new());
+    tokio::spawn(active_ads.clone().run(ad_event_log.clone()));
+    let nearby_ads = Arc::new(NearbyClassifiedAds::new());
+    tokio::spawn(nearby_ads.clone().run(ad_event_log.clone()));
+    let classified_ads_application_service = ClassifiedAdsApplicationService::new(
+        currency_lookup.clone(),
+        text_moderation.clone(),
+        category_tree.clone(),
+        ad_event_log.clone(),
+    );
+    let rates_file = std::env::var("MARKETPLACE_EXCHANGE_RATES_FILE")
+        .unwrap_or_else(|_| String::from(DEFAULT_EXCHANGE_RATES_FILE));
+    let exchange_rates = CachedExchangeRates::new(
+        Arc::new(StaticFileExchangeRates::new(rates_file)),
+        EXCHANGE_RATES_REFRESH_AFTER,
+        EXCHANGE_RATES_MAX_STALENESS,
+    );
+    let price_converter = PriceConverter::new(currency_lookup, Arc::new(exchange_rates));
+    let user_profile_application_service = UserProfileApplicationService::new(text_moderation);
+    // Mail is written to files until a real mail service is plugged in
+    let mail_dir = std::env::var("MARKETPLACE_MAIL_DIR").unwrap_or_else(|_| String::from("mail"));
+    let mail_from = std::env::var("MARKETPLACE_MAIL_FROM")
+        .unwrap_or_else(|_| String::from("no-reply@marketplace.local"));
+    let user_account_application_service = UserAccountApplicationService::new(
+        Arc::new(Argon2idPasswordHasher::new()),
+        Arc::new(FileMailSender::new(mail_dir, mail_from)?),
+    );
+    let token_issuer = Arc::new(JwtIssuer::from_env(ACCESS_TOKEN_LIFETIME)?);
+    let metrics = CommandMetrics::new();
+    let command_bus = Arc::new(build_command_bus(
+        classified_ads_application_service.clone(),
+        user_profile_application_service.clone(),
+        user_account_application_service.clone(),
+        CategoryApplicationService::new(category_tree.clone()),
+        metrics.clone(),
+    ));
+    let roles = RoleDirectory::new(
+        ConfiguredRoles::from_env()?,
+        user_profile_application_service.clone(),
+    );
+
+    let blob_dir = std::env::var("MARKETPLACE_BLOB_DIR").unwrap_or_else(|_| String::from("blobs"));
+    let blob_store = LocalFileBlobStore::new(blob_dir, "http://localhost:8000/blobs")?;
+    let blob_root = blob_store.root();
+    let blob_store: Arc<dyn IBlobStore> = Arc::new(blob_store);
 
-    let api_service = OpenApiService::new(ClassifiedAdApi, "Classified Ads", "1.0.0")
-        .server("http://localhost:8000");
+    let api_service = OpenApiService::new(
+        (ClassifiedAdApi, UserProfileApi, CategoryApi, AccountApi),
+        "Classified Ads",
+        "1.0.0",
+    )
+    .server("http://localhost:8000");
     let ui = api_service.swagger_ui();
     let spec = api_service.spec();
     let route = Route::new()
         .nest("/", api_service)
         .nest("/ui", ui)
         .at("/spec", poem::endpoint::make_sync(move |_| spec.clone()))
+        .nest("/blobs", StaticFilesEndpoint::new(blob_root))
+        .at("/ad/:id/events", get(ad_events))
+        .at("/ads/events", get(all_ad_events))
+        .at("/ws", get(notifications_socket))
+        .at(
+            "/metrics",
+            poem::endpoint::make_sync(move |_| render_metrics(&metrics)),
+        )
+        .with(endpoint_policy())
+        .with(
+            JwtAuthentication::new(JwtAuthenticator::from_env()?, Arc::new(roles))
+                .allow_anonymous(Method::POST, "/account/register")
+                .allow_anonymous(Method::POST, "/account/login")
+                .allow_anonymous(Method::POST, "/account/password-reset")
+                .allow_anonymous(Method::PUT, "/account/password-reset")
+                .allow_query_token(Method::GET, "/ws"),
+        )
         .with(Cors::new())
-        .data(classified_ads_application_service);
+        .data(command_bus)
+        .data(ad_event_log)
+        .data(notification_store)
+        .data(watchlist)
+        .data(ad_search)
+        .data(active_ads)
+        .data(nearby_ads)
+        .data(category_tree)
+        .data(classified_ads_application_service)
+        .data(user_profile_application_service)
+        .data(user_account_application_service)
+        .data(token_issuer)
+        .data(price_converter)
+        .data(blob_store);
 
     // let app = Route::new().nest("/ad", ad::route().with(AddData::new(classified_ads_api)));
     Server::new(TcpListener::bind("127.0.0.1:8000"))
@@ -0,0 +1,449 @@
+use std::{
+    collections::{HashMap, HashSet},
+    f64::consts::PI,
+    sync::{Arc, RwLock},
+};
+
+use anyhow::{anyhow, Result};
+use futures_util::StreamExt;
+use marketplace_domain::{
+    classified_ad_events::ClassifiedAdEvents,
+    location::{check_point, distance_km, Location, EARTH_RADIUS_KM},
+};
+use poem_openapi::Object;
+use rust_decimal::{
+    prelude::{FromPrimitive, ToPrimitive},
+    Decimal,
+};
+use uuid::Uuid;
+
+use crate::{
+    classified_ad::ClassifiedAdV1Location,
+    event_stream::{ClassifiedAdEventLog, RecordedEvent},
+    pagination::{paginate, Page, PageRequest, SortKey},
+};
+
+/// Side of a grid cell. Cells are narrower towards the poles, where meridians meet.
+const GRID_CELL_DEGREES: f64 = 0.5;
+const LATITUDE_CELLS: i32 = (180.0 / GRID_CELL_DEGREES) as i32;
+const LONGITUDE_CELLS: i32 = (360.0 / GRID_CELL_DEGREES) as i32;
+const KM_PER_DEGREE_LATITUDE: f64 = EARTH_RADIUS_KM * PI / 180.0;
+pub const MAX_RADIUS_KM: f64 = 500.0;
+
+// ================================================================================
+// Query
+// ================================================================================
+
+/// A circle on the earth's surface to find ads in.
+pub struct NearbyQuery {
+    pub latitude: f64,
+    pub longitude: f64,
+    pub radius_km: f64,
+}
+
+impl NearbyQuery {
+    /// The point and radius, which distances in a cursor are measured from.
+    pub fn scope(&self) -> String {
+        format!("{},{},{}", self.latitude, self.longitude, self.radius_km)
+    }
+
+    fn validate(&self) -> Result<()> {
+        check_point(self.latitude, self.longitude)?;
+        if !(self.radius_km > 0.0 && self.radius_km <= MAX_RADIUS_KM) {
+            return Err(anyhow!(
+                "The radius must be above 0 and at most {} km",
+                MAX_RADIUS_KM
+            ));
+        }
+        Ok(())
+    }
+}
+
+#[derive(Object)]
+pub struct ClassifiedAdV1NearbyAd {
+    pub id: String,
+    pub owner_id: String,
+    pub title: Option<String>,
+    pub price: Option<f64>,
+    pub currency: Option<String>,
+    pub location: ClassifiedAdV1Location,
+    /// Straight-line distance from the point searched around, to the metre
+    pub distance_km: f64,
+}
+
+#[derive(Object)]
+pub struct ClassifiedAdV1NearbyPage {
+    /// Nearest first
+    pub items: Vec<ClassifiedAdV1NearbyAd>,
+    /// Pass as `cursor`, with the same point and radius, to get the next page;
+    /// missing on the last page
+    pub next_cursor: Option<String>,
+}
+
+impl From<Page<ClassifiedAdV1NearbyAd>> for ClassifiedAdV1NearbyPage {
+    fn from(page: Page<ClassifiedAdV1NearbyAd>) -> Self {
+        Self {
+            items: page.items,
+            next_cursor: page.next_cursor,
+        }
+    }
+}
+
+// ================================================================================
+// Index
+// ================================================================================
+
+type Cell = (i32, i32);
+
+fn cell_of(latitude: f64, longitude: f64) -> Cell {
+    let row = ((latitude + 90.0) / GRID_CELL_DEGREES).floor() as i32;
+    let column = ((longitude + 180.0) / GRID_CELL_DEGREES).floor() as i32;
+    (
+        row.clamp(0, LATITUDE_CELLS - 1),
+        column.rem_euclid(LONGITUDE_CELLS),
+    )
+}
+
+/// Every cell holding a point within `radius_km` of the query's point. The box
+/// around the circle wraps across the antimeridian and, near a pole, takes in
+/// every longitude.
+fn cells_around(query: &NearbyQuery) -> Vec<Cell> {
+    let degrees_latitude = query.radius_km / KM_PER_DEGREE_LATITUDE;
+    let south = query.latitude - degrees_latitude;
+    let north = query.latitude + degrees_latitude;
+    let rows = cell_of(south.max(-90.0), 0.0).0..=cell_of(north.min(90.0), 0.0).0;
+    let widest = south.abs().max(north.abs()).to_radians().cos();
+    let columns: Vec<i32> = match degrees_latitude / widest {
+        degrees if south > -90.0 && north < 90.0 && degrees < 180.0 => {
+            let west = cell_of(0.0, query.longitude - degrees).1;
+            let east = cell_of(0.0, query.longitude + degrees).1;
+            let count = (east - west).rem_euclid(LONGITUDE_CELLS) + 1;
+            (0..count)
+                .map(|i| (west + i).rem_euclid(LONGITUDE_CELLS))
+                .collect()
+        }
+        _ => (0..LONGITUDE_CELLS).collect(),
+    };
+    rows.flat_map(|row| columns.iter().map(move |column| (row, *column)))
+        .collect()
+}
+
+struct PlacedAd {
+    id: Uuid,
+    owner_id: Uuid,
+    title: Option<String>,
+    price: Option<(Decimal, String)>,
+    location: Option<Location>,
+    active: bool,
+}
+
+impl PlacedAd {
+    /// The cell the ad is indexed in; only active ads with a location are.
+    fn cell(&self) -> Option<Cell> {
+        let location = self.location.as_ref().filter(|_| self.active)?;
+        Some(cell_of(location.latitude(), location.longitude()))
+    }
+}
+
+#[derive(Default)]
+struct GeoIndex {
+    ads: HashMap<Uuid, PlacedAd>,
+    cells: HashMap<Cell, HashSet<Uuid>>,
+}
+
+/// Read model of active ads by where they are, on a grid of latitude and
+/// longitude cells, kept up to date from the ad event log.
+#[derive(Default)]
+pub struct NearbyClassifiedAds {
+    _index: RwLock<GeoIndex>,
+}
+
+impl NearbyClassifiedAds {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Follows the event log from the start for as long as it is open.
+    pub async fn run(self: Arc<Self>, log: Arc<ClassifiedAdEventLog>) {
+        let mut events = Box::pin(log.subscribe_all(0));
+        while let Some(event) = events.next().await {
+            self.when(&event);
+        }
+    }
+
+    fn when(&self, recorded: &RecordedEvent) {
+        let mut index = self._index.write().unwrap();
+        let GeoIndex { ads, cells } = &mut *index;
+        if let ClassifiedAdEvents::Created(e) = &recorded.event {
+            ads.insert(
+                e.id,
+                PlacedAd {
+                    id: e.id,
+                    owner_id: e.owner_id,
+                    title: None,
+                    price: None,
+                    location: None,
+                    active: false,
+                },
+            );
+            return;
+        }
+        let Some(ad) = ads.get_mut(&recorded.stream_id) else {
+            return;
+        };
+        let previous = ad.cell();
+        match &recorded.event {
+            ClassifiedAdEvents::TitleChanged(e) => ad.title = Some(e.title.clone()),
+            ClassifiedAdEvents::PriceUpdated(e) => {
+                ad.price = Some((e.price, e.currency_code.clone()))
+            }
+            ClassifiedAdEvents::LocationSet(e) => {
+                ad.location = Location::new(e.latitude, e.longitude, &e.postal_area).ok()
+            }
+            ClassifiedAdEvents::Published(_) | ClassifiedAdEvents::Reactivated(_) => {
+                ad.active = true
+            }
+            ClassifiedAdEvents::SentForReview(_)
+            | ClassifiedAdEvents::Rejected(_)
+            | ClassifiedAdEvents::MarkedAsSold(_)
+            | ClassifiedAdEvents::Deactivated(_) => ad.active = false,
+            _ => return,
+        }
+        let current = ad.cell();
+        if previous == current {
+            return;
+        }
+        if let Some(cell) = previous {
+            if let Some(ids) = cells.get_mut(&cell) {
+                ids.remove(&ad.id);
+                if ids.is_empty() {
+                    cells.remove(&cell);
+                }
+            }
+        }
+        if let Some(cell) = current {
+            cells.entry(cell).or_default().insert(ad.id);
+        }
+    }
+
+    /// A page of the active ads within the query's radius, nearest first. The
+    /// request has to be sorted by distance.
+    pub fn nearby(
+        &self,
+        query: &NearbyQuery,
+        request: &PageRequest,
+    ) -> Result<Page<ClassifiedAdV1NearbyAd>> {
+        query.validate()?;
+        let index = self._index.read().unwrap();
+        let found = cells_around(query)
+            .into_iter()
+            .filter_map(|cell| index.cells.get(&cell))
+            .flatten()
+            .filter_map(|id| index.ads.get(id))
+            .filter_map(|ad| {
+                let location = ad.location.as_ref()?;
+                let distance = distance_km(
+                    query.latitude,
+                    query.longitude,
+                    location.latitude(),
+                    location.longitude(),
+                );
+                (distance <= query.radius_km).then_some((ad, location, distance))
+            });
+        let page = paginate(found, request, |(ad, _, distance), _| {
+            let key = Decimal::from_f64(*distance).unwrap_or_default();
+            (SortKey::Number(key), ad.id)
+        });
+        Ok(Page {
+            items: page
+                .items
+                .into_iter()
+                .map(|(ad, location, distance)| ClassifiedAdV1NearbyAd {
+                    id: ad.id.to_string(),
+                    owner_id: ad.owner_id.to_string(),
+                    title: ad.title.clone(),
+                    price: ad.price.as_ref().and_then(|p| p.0.to_f64()),
+                    currency: ad.price.as_ref().map(|p| p.1.clone()),
+                    location: ClassifiedAdV1Location::from(location),
+                    distance_km: (distance * 1000.0).round() / 1000.0,
+                })
+                .collect(),
+            next_cursor: page.next_cursor,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::Utc;
+    use marketplace_domain::classified_ad_events::{
+        ClassifiedAdCreated, ClassifiedAdLocationSet, ClassifiedAdPublished,
+    };
+
+    use super::*;
+    use crate::pagination::{SortDirection, SortField};
+
+    fn record(stream_id: Uuid, event: ClassifiedAdEvents) -> RecordedEvent {
+        RecordedEvent {
+            global_position: 0,
+            stream_id,
+            stream_position: 0,
+            event_type: "",
+            data: serde_json::Value::Null,
+            recorded_at: Utc::now(),
+            owner_id: Uuid::nil(),
+            published: true,
+            event,
+        }
+    }
+
+    /// Indexes an active ad at each point, returning the ads' ids and points.
+    fn index_ads(points: &[(f64, f64)]) -> (NearbyClassifiedAds, Vec<(Uuid, f64, f64)>) {
+        let nearby = NearbyClassifiedAds::new();
+        let ads: Vec<(Uuid, f64, f64)> = points
+            .iter()
+            .map(|(latitude, longitude)| (Uuid::new_v4(), *latitude, *longitude))
+            .collect();
+        for (id, latitude, longitude) in &ads {
+            let id = *id;
+            let events: [ClassifiedAdEvents; 3] = [
+                ClassifiedAdCreated {
+                    id,
+                    owner_id: Uuid::new_v4(),
+                }
+                .into(),
+                ClassifiedAdLocationSet {
+                    id,
+                    latitude: *latitude,
+                    longitude: *longitude,
+                    postal_area: String::from("Somewhere"),
+                }
+                .into(),
+                ClassifiedAdPublished {
+                    id,
+                    approved_by: Uuid::new_v4(),
+                }
+                .into(),
+            ];
+            for event in events {
+                nearby.when(&record(id, event));
+            }
+        }
+        (nearby, ads)
+    }
+
+    /// The point `distance_km` from the start along `bearing` degrees east of north.
+    fn destination(latitude: f64, longitude: f64, bearing: f64, distance_km: f64) -> (f64, f64) {
+        let (lat1, lon1) = (latitude.to_radians(), longitude.to_radians());
+        let (bearing, angle) = (bearing.to_radians(), distance_km / EARTH_RADIUS_KM);
+        let lat2 = (lat1.sin() * angle.cos() + lat1.cos() * angle.sin() * bearing.cos()).asin();
+        let lon2 = lon1
+            + (bearing.sin() * angle.sin() * lat1.cos())
+                .atan2(angle.cos() - lat1.sin() * lat2.sin());
+        let longitude = (lon2.to_degrees() + 540.0).rem_euclid(360.0) - 180.0;
+        (lat2.to_degrees(), longitude)
+    }
+
+    /// Every id `nearby` returns for the query, following cursors to the last page.
+    fn found(nearby: &NearbyClassifiedAds, query: &NearbyQuery) -> HashSet<Uuid> {
+        let mut found = HashSet::new();
+        let mut cursor = None;
+        loop {
+            let request = PageRequest::new(
+                SortField::Distance,
+                SortDirection::Asc,
+                Some(100),
+                cursor.as_deref(),
+                Some(query.scope()),
+            )
+            .unwrap();
+            let page = nearby.nearby(query, &request).unwrap();
+            found.extend(page.items.iter().map(|ad| Uuid::parse_str(&ad.id).unwrap()));
+            match page.next_cursor {
+                Some(next) => cursor = Some(next),
+                None => return found,
+            }
+        }
+    }
+
+    /// Places ads all around the query's point, inside and just outside its
+    /// radius, and checks exactly those within the radius are found.
+    fn assert_finds_every_ad_within(query: NearbyQuery, extra_points: &[(f64, f64)]) {
+        let mut points = extra_points.to_vec();
+        for bearing in (0..360).step_by(10) {
+            for fraction in [0.0, 0.25, 0.5, 0.75, 0.99, 1.01, 1.5] {
+                points.push(destination(
+                    query.latitude,
+                    query.longitude,
+                    f64::from(bearing),
+                    query.radius_km * fraction,
+                ));
+            }
+        }
+        let (nearby, ads) = index_ads(&points);
+        let within: HashSet<Uuid> = ads
+            .iter()
+            .filter(|(_, latitude, longitude)| {
+                distance_km(query.latitude, query.longitude, *latitude, *longitude)
+                    <= query.radius_km
+            })
+            .map(|(id, _, _)| *id)
+            .collect();
+        assert!(!within.is_empty());
+        assert_eq!(found(&nearby, &query), within);
+    }
+
+    #[test]
+    fn finds_ads_across_the_antimeridian() {
+        assert_finds_every_ad_within(
+            NearbyQuery {
+                latitude: 10.0,
+                longitude: 179.9,
+                radius_km: 100.0,
+            },
+            &[(10.0, -179.9), (10.0, 180.0), (10.0, -180.0)],
+        );
+        assert_finds_every_ad_within(
+            NearbyQuery {
+                latitude: -30.0,
+                longitude: -179.95,
+                radius_km: 50.0,
+            },
+            &[(-30.0, 179.95)],
+        );
+    }
+
+    #[test]
+    fn finds_ads_across_a_pole() {
+        assert_finds_every_ad_within(
+            NearbyQuery {
+                latitude: 89.8,
+                longitude: 45.0,
+                radius_km: 100.0,
+            },
+            &[(90.0, 0.0), (89.5, -135.0), (89.5, 100.0)],
+        );
+        assert_finds_every_ad_within(
+            NearbyQuery {
+                latitude: -89.9,
+                longitude: 0.0,
+                radius_km: 30.0,
+            },
+            &[(-90.0, 0.0), (-89.9, 180.0)],
+        );
+    }
+
+    #[test]
+    fn finds_ads_around_a_point_on_a_cell_edge() {
+        assert_eq!(cell_of(40.0, 20.0), cell_of(40.0 + 1e-9, 20.0 + 1e-9));
+        assert_eq!(cell_of(90.0, 180.0), (LATITUDE_CELLS - 1, 0));
+        assert_finds_every_ad_within(
+            NearbyQuery {
+                latitude: 40.0,
+                longitude: 20.0,
+                radius_km: 10.0,
+            },
+            &[(40.0, 20.0), (40.0, 19.5), (39.5, 20.0), (40.5, 20.5)],
+        );
+    }
+}
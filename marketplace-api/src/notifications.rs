@@ -0,0 +1,384 @@
+use std::{
+    collections::{HashMap, HashSet, VecDeque},
+    sync::{Arc, Mutex, RwLock},
+    time::Duration,
+};
+
+use chrono::{DateTime, Utc};
+use futures_util::{stream, SinkExt, Stream, StreamExt};
+use marketplace_domain::classified_ad_events::ClassifiedAdEvents;
+use poem::{
+    handler,
+    web::{
+        websocket::{CloseCode, Message, WebSocket},
+        Data, Query,
+    },
+    IntoResponse,
+};
+use rust_decimal::Decimal;
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use tokio::{
+    sync::broadcast::{self, error::RecvError},
+    time::{interval_at, timeout, Instant},
+};
+use uuid::Uuid;
+
+use crate::{
+    authentication::Principal,
+    event_stream::{ClassifiedAdEventLog, RecordedEvent},
+};
+
+/// Notifications kept for each user, so a reconnecting client can catch up.
+const NOTIFICATIONS_KEPT_PER_USER: usize = 100;
+/// Notifications a slow socket can fall behind by before it has to catch up from the store.
+const LIVE_NOTIFICATIONS_CAPACITY: usize = 256;
+/// A client that does not take a message within this long is disconnected.
+const SEND_TIMEOUT: Duration = Duration::from_secs(10);
+const PING_INTERVAL: Duration = Duration::from_secs(30);
+
+// ================================================================================
+// Notifications
+// ================================================================================
+
+/// Something a user is told about. Ids increase across all users, so a client
+/// resumes from the last id it saw.
+#[derive(Serialize)]
+pub struct Notification {
+    pub id: u64,
+    #[serde(skip)]
+    pub user_id: Uuid,
+    #[serde(rename = "type")]
+    pub kind: &'static str,
+    pub ad_id: Uuid,
+    pub message: String,
+    pub data: serde_json::Value,
+    pub created_at: DateTime<Utc>,
+}
+
+struct StoredNotifications {
+    last_id: u64,
+    by_user: HashMap<Uuid, VecDeque<Arc<Notification>>>,
+}
+
+/// In-memory store of each user's latest notifications, which connected sockets
+/// are told about.
+pub struct NotificationStore {
+    _notifications: RwLock<StoredNotifications>,
+    _live: broadcast::Sender<Arc<Notification>>,
+}
+
+impl NotificationStore {
+    pub fn new() -> Self {
+        let (live, _) = broadcast::channel(LIVE_NOTIFICATIONS_CAPACITY);
+        Self {
+            _notifications: RwLock::new(StoredNotifications {
+                last_id: 0,
+                by_user: HashMap::new(),
+            }),
+            _live: live,
+        }
+    }
+
+    fn push(
+        &self,
+        user_id: Uuid,
+        kind: &'static str,
+        ad_id: Uuid,
+        message: String,
+        data: serde_json::Value,
+    ) {
+        let mut stored = self._notifications.write().unwrap();
+        stored.last_id += 1;
+        let notification = Arc::new(Notification {
+            id: stored.last_id,
+            user_id,
+            kind,
+            ad_id,
+            message,
+            data,
+            created_at: Utc::now(),
+        });
+        let kept = stored.by_user.entry(user_id).or_default();
+        kept.push_back(notification.clone());
+        if kept.len() > NOTIFICATIONS_KEPT_PER_USER {
+            kept.pop_front();
+        }
+        // Nobody connected is fine
+        let _ = self._live.send(notification);
+    }
+
+    /// The kept notifications of a user with an id above `after`, oldest first.
+    pub fn read(&self, user_id: Uuid, after: u64) -> VecDeque<Arc<Notification>> {
+        self._notifications
+            .read()
+            .unwrap()
+            .by_user
+            .get(&user_id)
+            .into_iter()
+            .flatten()
+            .filter(|notification| notification.id > after)
+            .cloned()
+            .collect()
+    }
+
+    /// Notifications of a user after id `after`, followed by new ones as they are
+    /// made. A subscriber that falls too far behind is caught up from the store.
+    pub fn subscribe(
+        self: Arc<Self>,
+        user_id: Uuid,
+        after: u64,
+    ) -> impl Stream<Item = Arc<Notification>> {
+        // Subscribe before reading the store so nothing made in between is missed
+        let live = self._live.subscribe();
+        let backlog = self.read(user_id, after);
+        let state = (self, after, backlog, live);
+        stream::unfold(
+            state,
+            move |(store, mut after, mut backlog, mut live)| async move {
+                loop {
+                    let next = match backlog.pop_front() {
+                        Some(notification) => notification,
+                        None => match live.recv().await {
+                            Ok(notification) => notification,
+                            Err(RecvError::Lagged(_)) => {
+                                backlog = store.read(user_id, after);
+                                continue;
+                            }
+                            Err(RecvError::Closed) => return None,
+                        },
+                    };
+                    if next.user_id == user_id && next.id > after {
+                        after = next.id;
+                        return Some((next, (store, after, backlog, live)));
+                    }
+                }
+            },
+        )
+    }
+}
+
+impl Default for NotificationStore {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+// ================================================================================
+// Watchlist
+// ================================================================================
+
+/// Ads users watch to hear about price drops.
+#[derive(Default)]
+pub struct Watchlist {
+    _watchers: RwLock<HashMap<Uuid, HashSet<Uuid>>>,
+}
+
+impl Watchlist {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn watch(&self, ad_id: Uuid, user_id: Uuid) {
+        self._watchers
+            .write()
+            .unwrap()
+            .entry(ad_id)
+            .or_default()
+            .insert(user_id);
+    }
+
+    pub fn unwatch(&self, ad_id: Uuid, user_id: Uuid) {
+        let mut watchers = self._watchers.write().unwrap();
+        if let Some(users) = watchers.get_mut(&ad_id) {
+            users.remove(&user_id);
+            if users.is_empty() {
+                watchers.remove(&ad_id);
+            }
+        }
+    }
+
+    pub fn watchers(&self, ad_id: Uuid) -> Vec<Uuid> {
+        self._watchers
+            .read()
+            .unwrap()
+            .get(&ad_id)
+            .map(|users| users.iter().copied().collect())
+            .unwrap_or_default()
+    }
+}
+
+// ================================================================================
+// Projection
+// ================================================================================
+
+/// What the projection remembers of an ad to word its notifications.
+#[derive(Default)]
+struct AdSummary {
+    owner_id: Option<Uuid>,
+    title: Option<String>,
+    price: Option<(Decimal, String)>,
+    /// Published and not since taken down; only then can watchers see its price.
+    active: bool,
+}
+
+impl AdSummary {
+    fn name(&self) -> String {
+        match &self.title {
+            Some(title) => format!("\"{}\"", title),
+            None => String::from("Your ad"),
+        }
+    }
+}
+
+/// Turns committed ad events into notifications: owners hear when their ad is
+/// approved or rejected, and watchers when the price of an active ad drops.
+pub struct NotificationProjection {
+    _store: Arc<NotificationStore>,
+    _watchlist: Arc<Watchlist>,
+    _ads: Mutex<HashMap<Uuid, AdSummary>>,
+}
+
+impl NotificationProjection {
+    pub fn new(store: Arc<NotificationStore>, watchlist: Arc<Watchlist>) -> Self {
+        Self {
+            _store: store,
+            _watchlist: watchlist,
+            _ads: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Follows the event log from the start for as long as it is open.
+    pub async fn run(self, log: Arc<ClassifiedAdEventLog>) {
+        let mut events = Box::pin(log.subscribe_all(0));
+        while let Some(event) = events.next().await {
+            self.when(&event);
+        }
+    }
+
+    fn when(&self, recorded: &RecordedEvent) {
+        let mut ads = self._ads.lock().unwrap();
+        let ad = ads.entry(recorded.stream_id).or_default();
+        match &recorded.event {
+            ClassifiedAdEvents::Created(e) => ad.owner_id = Some(e.owner_id),
+            ClassifiedAdEvents::TitleChanged(e) => ad.title = Some(e.title.clone()),
+            ClassifiedAdEvents::Published(e) => {
+                ad.active = true;
+                if let Some(owner_id) = ad.owner_id {
+                    self._store.push(
+                        owner_id,
+                        "AdApproved",
+                        e.id,
+                        format!("{} was approved and is now published", ad.name()),
+                        json!({ "approved_by": e.approved_by }),
+                    );
+                }
+            }
+            ClassifiedAdEvents::Rejected(e) => {
+                ad.active = false;
+                if let Some(owner_id) = ad.owner_id {
+                    self._store.push(
+                        owner_id,
+                        "AdRejected",
+                        e.id,
+                        format!("{} was rejected: {}", ad.name(), e.reason),
+                        json!({ "rejected_by": e.rejected_by, "reason": e.reason }),
+                    );
+                }
+            }
+            ClassifiedAdEvents::PriceUpdated(e) => {
+                let new_price = (e.price, e.currency_code.clone());
+                // Prices in another currency are not compared
+                if let Some((old_price, _)) = ad.price.as_ref().filter(|(old, currency)| {
+                    ad.active && *currency == e.currency_code && e.price < *old
+                }) {
+                    for user_id in self._watchlist.watchers(e.id) {
+                        if Some(user_id) == ad.owner_id {
+                            continue;
+                        }
+                        self._store.push(
+                            user_id,
+                            "PriceDropped",
+                            e.id,
+                            format!(
+                                "{} dropped in price from {} to {} {}",
+                                ad.name(),
+                                old_price,
+                                e.price,
+                                e.currency_code
+                            ),
+                            json!({
+                                "old_price": old_price,
+                                "new_price": e.price,
+                                "currency": e.currency_code,
+                            }),
+                        );
+                    }
+                }
+                ad.price = Some(new_price);
+            }
+            ClassifiedAdEvents::Reactivated(_) => ad.active = true,
+            ClassifiedAdEvents::SentForReview(_)
+            | ClassifiedAdEvents::MarkedAsSold(_)
+            | ClassifiedAdEvents::Deactivated(_) => ad.active = false,
+            _ => {}
+        }
+    }
+}
+
+// ================================================================================
+// Endpoints
+// ================================================================================
+
+#[derive(Deserialize)]
+pub struct ResumeQuery {
+    /// Id of the last notification a reconnecting client saw.
+    last_id: Option<u64>,
+}
+
+/// `GET /ws`: the caller's notifications over a WebSocket, as JSON text messages.
+/// Clients that cannot keep up are disconnected and resume with `?last_id=`.
+#[handler]
+pub async fn notifications_socket(
+    ws: WebSocket,
+    Query(query): Query<ResumeQuery>,
+    principal: Data<&Principal>,
+    store: Data<&Arc<NotificationStore>>,
+) -> impl IntoResponse {
+    let notifications = store
+        .clone()
+        .subscribe(principal.user_id.value(), query.last_id.unwrap_or(0));
+    ws.on_upgrade(move |socket| async move {
+        let (mut sink, mut incoming) = socket.split();
+        let mut notifications = Box::pin(notifications);
+        let mut ping = interval_at(Instant::now() + PING_INTERVAL, PING_INTERVAL);
+        loop {
+            let message = tokio::select! {
+                notification = notifications.next() => match notification {
+                    Some(notification) => {
+                        Message::text(serde_json::to_string(notification.as_ref()).unwrap_or_default())
+                    }
+                    None => break,
+                },
+                received = incoming.next() => match received {
+                    Some(Ok(Message::Close(_))) | Some(Err(_)) | None => break,
+                    // Notifications only go one way
+                    Some(Ok(_)) => continue,
+                },
+                _ = ping.tick() => Message::ping(vec![]),
+            };
+            // Waiting on the client holds back reading further notifications, which
+            // queue up in the store until it catches up or is disconnected
+            match timeout(SEND_TIMEOUT, sink.send(message)).await {
+                Ok(Ok(())) => {}
+                Ok(Err(_)) => break,
+                Err(_) => {
+                    let close =
+                        Message::close_with(CloseCode::Again, "Too slow to take notifications");
+                    let _ = timeout(SEND_TIMEOUT, sink.send(close)).await;
+                    break;
+                }
+            }
+        }
+    })
+}
@@ -0,0 +1,389 @@
+use std::{cmp::Ordering, fmt, str::FromStr};
+
+use anyhow::{anyhow, Result};
+use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine};
+use chrono::{DateTime, Utc};
+use rust_decimal::Decimal;
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+
+pub const DEFAULT_PAGE_SIZE: usize = 20;
+pub const MAX_PAGE_SIZE: usize = 100;
+
+// ================================================================================
+// Sorting
+// ================================================================================
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum SortField {
+    CreatedAt,
+    Price,
+    Title,
+    /// From the point of a nearby search; not a choice for other listings.
+    Distance,
+    /// How well a search matched; not a choice for other listings.
+    Relevance,
+}
+
+impl SortField {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            SortField::CreatedAt => "created_at",
+            SortField::Price => "price",
+            SortField::Title => "title",
+            SortField::Distance => "distance",
+            SortField::Relevance => "relevance",
+        }
+    }
+}
+
+impl FromStr for SortField {
+    type Err = anyhow::Error;
+
+    fn from_str(value: &str) -> Result<Self> {
+        match value {
+            "created_at" => Ok(SortField::CreatedAt),
+            "price" => Ok(SortField::Price),
+            "title" => Ok(SortField::Title),
+            _ => Err(anyhow!(
+                "Cannot sort by {}; use created_at, price or title",
+                value
+            )),
+        }
+    }
+}
+
+impl fmt::Display for SortField {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum SortDirection {
+    Asc,
+    Desc,
+}
+
+impl SortDirection {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            SortDirection::Asc => "asc",
+            SortDirection::Desc => "desc",
+        }
+    }
+}
+
+impl FromStr for SortDirection {
+    type Err = anyhow::Error;
+
+    fn from_str(value: &str) -> Result<Self> {
+        match value {
+            "asc" => Ok(SortDirection::Asc),
+            "desc" => Ok(SortDirection::Desc),
+            _ => Err(anyhow!("Sort order must be asc or desc, not {}", value)),
+        }
+    }
+}
+
+/// Value an item is sorted on. Items sorted together all have the same kind of key.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub enum SortKey {
+    Time(DateTime<Utc>),
+    Number(Decimal),
+    Text(String),
+}
+
+impl SortKey {
+    fn compare(&self, other: &SortKey) -> Ordering {
+        match (self, other) {
+            (SortKey::Time(a), SortKey::Time(b)) => a.cmp(b),
+            (SortKey::Number(a), SortKey::Number(b)) => a.cmp(b),
+            (SortKey::Text(a), SortKey::Text(b)) => a.cmp(b),
+            _ => Ordering::Equal,
+        }
+    }
+}
+
+// ================================================================================
+// Cursors
+// ================================================================================
+
+/// Where a page ends: the sort key and id of its last item. Ids break ties, so
+/// items with equal keys are neither skipped nor repeated. Listings whose keys
+/// depend on their query, like distances from a point, also record the query.
+#[derive(Serialize, Deserialize)]
+struct Cursor {
+    sort: SortField,
+    direction: SortDirection,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    scope: Option<String>,
+    key: SortKey,
+    id: Uuid,
+}
+
+impl Cursor {
+    /// Cursors are opaque to clients: base64 of the position, which may change shape.
+    fn encode(&self) -> String {
+        URL_SAFE_NO_PAD.encode(serde_json::to_vec(self).unwrap_or_default())
+    }
+
+    fn decode(value: &str) -> Result<Self> {
+        URL_SAFE_NO_PAD
+            .decode(value)
+            .ok()
+            .and_then(|bytes| serde_json::from_slice(&bytes).ok())
+            .ok_or_else(|| anyhow!("The cursor is not valid"))
+    }
+}
+
+/// Which page of a sorted listing a client asked for.
+pub struct PageRequest {
+    pub sort: SortField,
+    pub direction: SortDirection,
+    pub limit: usize,
+    _scope: Option<String>,
+    _after: Option<Cursor>,
+}
+
+impl PageRequest {
+    /// A cursor has to come from a page with the same sort order and scope.
+    pub fn new(
+        sort: SortField,
+        direction: SortDirection,
+        limit: Option<usize>,
+        cursor: Option<&str>,
+        scope: Option<String>,
+    ) -> Result<Self> {
+        let limit = limit.unwrap_or(DEFAULT_PAGE_SIZE);
+        if !(1..=MAX_PAGE_SIZE).contains(&limit) {
+            return Err(anyhow!(
+                "The page size must be between 1 and {}",
+                MAX_PAGE_SIZE
+            ));
+        }
+        let after = cursor.map(Cursor::decode).transpose()?;
+        if let Some(after) = &after {
+            if after.sort != sort || after.direction != direction {
+                return Err(anyhow!(
+                    "The cursor is for a listing sorted by {} {}",
+                    after.sort,
+                    after.direction.as_str()
+                ));
+            }
+            if after.scope != scope {
+                return Err(anyhow!(
+                    "The cursor is for a different query; start again without it"
+                ));
+            }
+        }
+        Ok(Self {
+            sort,
+            direction,
+            limit,
+            _scope: scope,
+            _after: after,
+        })
+    }
+
+    fn compare(&self, a: &(SortKey, Uuid), b: &(SortKey, Uuid)) -> Ordering {
+        let ordering = a.0.compare(&b.0).then(a.1.cmp(&b.1));
+        match self.direction {
+            SortDirection::Asc => ordering,
+            SortDirection::Desc => ordering.reverse(),
+        }
+    }
+}
+
+pub struct Page<T> {
+    pub items: Vec<T>,
+    /// Cursor to the following page; none on the last page.
+    pub next_cursor: Option<String>,
+}
+
+/// Sorts `items` by the key `key_of` gives for the requested field, and takes the
+/// page after the request's cursor.
+pub fn paginate<T>(
+    items: impl IntoIterator<Item = T>,
+    request: &PageRequest,
+    key_of: impl Fn(&T, SortField) -> (SortKey, Uuid),
+) -> Page<T> {
+    let mut keyed: Vec<((SortKey, Uuid), T)> = items
+        .into_iter()
+        .map(|item| (key_of(&item, request.sort), item))
+        .filter(|(key, _)| {
+            request._after.as_ref().is_none_or(|after| {
+                request.compare(key, &(after.key.clone(), after.id)) == Ordering::Greater
+            })
+        })
+        .collect();
+    keyed.sort_by(|a, b| request.compare(&a.0, &b.0));
+    let has_more = keyed.len() > request.limit;
+    keyed.truncate(request.limit);
+    let next_cursor = match (has_more, keyed.last()) {
+        (true, Some(((key, id), _))) => Some(
+            Cursor {
+                sort: request.sort,
+                direction: request.direction,
+                scope: request._scope.clone(),
+                key: key.clone(),
+                id: *id,
+            }
+            .encode(),
+        ),
+        _ => None,
+    };
+    Page {
+        items: keyed.into_iter().map(|(_, item)| item).collect(),
+        next_cursor,
+    }
+}
+
+/// `Link` header with the `first` page and, unless this is the last page, the
+/// `next` one. `query` holds the other parameters of the listing, kept on both.
+pub fn link_header<T>(path: &str, query: &[(&str, String)], page: &Page<T>) -> String {
+    let url = |cursor: Option<&String>| {
+        let mut params: Vec<(&str, &str)> = query.iter().map(|(k, v)| (*k, v.as_str())).collect();
+        if let Some(cursor) = cursor {
+            params.push(("cursor", cursor));
+        }
+        match serde_urlencoded::to_string(&params) {
+            Ok(encoded) if !encoded.is_empty() => format!("{}?{}", path, encoded),
+            _ => path.to_string(),
+        }
+    };
+    let mut links = vec![format!("<{}>; rel=\"first\"", url(None))];
+    if let Some(cursor) = &page.next_cursor {
+        links.push(format!("<{}>; rel=\"next\"", url(Some(cursor))));
+    }
+    links.join(", ")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    type Item = (u32, Uuid);
+
+    fn request(
+        direction: SortDirection,
+        limit: usize,
+        cursor: Option<&str>,
+        scope: Option<&str>,
+    ) -> Result<PageRequest> {
+        PageRequest::new(
+            SortField::Price,
+            direction,
+            Some(limit),
+            cursor,
+            scope.map(str::to_string),
+        )
+    }
+
+    fn key_of(item: &Item, _: SortField) -> (SortKey, Uuid) {
+        (SortKey::Number(Decimal::from(item.0)), item.1)
+    }
+
+    /// Every item, a page of `limit` at a time, and how many pages it took.
+    fn page_through(items: &[Item], direction: SortDirection, limit: usize) -> (Vec<Item>, usize) {
+        let mut seen = vec![];
+        let mut pages = 0;
+        let mut cursor: Option<String> = None;
+        loop {
+            let request = request(direction, limit, cursor.as_deref(), Some("q")).unwrap();
+            let page = paginate(items.iter().copied(), &request, key_of);
+            assert!(page.items.len() <= limit);
+            seen.extend(page.items);
+            pages += 1;
+            match page.next_cursor {
+                Some(next) => cursor = Some(next),
+                None => return (seen, pages),
+            }
+        }
+    }
+
+    fn cursor_after(item: Item, direction: SortDirection, scope: Option<&str>) -> String {
+        Cursor {
+            sort: SortField::Price,
+            direction,
+            scope: scope.map(str::to_string),
+            key: key_of(&item, SortField::Price).0,
+            id: item.1,
+        }
+        .encode()
+    }
+
+    #[test]
+    fn cursors_round_trip() {
+        let id = Uuid::new_v4();
+        let encoded = Cursor {
+            sort: SortField::Distance,
+            direction: SortDirection::Desc,
+            scope: Some(String::from("52.5,13.4,10")),
+            key: SortKey::Number(Decimal::new(1234, 2)),
+            id,
+        }
+        .encode();
+        let decoded = Cursor::decode(&encoded).unwrap();
+        assert_eq!(decoded.sort, SortField::Distance);
+        assert_eq!(decoded.direction, SortDirection::Desc);
+        assert_eq!(decoded.scope.as_deref(), Some("52.5,13.4,10"));
+        assert_eq!(decoded.key, SortKey::Number(Decimal::new(1234, 2)));
+        assert_eq!(decoded.id, id);
+        assert!(Cursor::decode("not a cursor").is_err());
+    }
+
+    #[test]
+    fn ties_on_the_sort_key_are_broken_by_id() {
+        let items: Vec<Item> = [5, 1, 5, 3, 5, 1, 5]
+            .into_iter()
+            .map(|price| (price, Uuid::new_v4()))
+            .collect();
+        let mut ascending = items.clone();
+        ascending.sort();
+        for limit in 1..=items.len() {
+            let (seen, _) = page_through(&items, SortDirection::Asc, limit);
+            assert_eq!(seen, ascending);
+            let (seen, _) = page_through(&items, SortDirection::Desc, limit);
+            assert_eq!(seen, ascending.iter().rev().copied().collect::<Vec<_>>());
+        }
+    }
+
+    #[test]
+    fn a_cursor_from_another_sort_or_scope_is_rejected() {
+        let item = (5, Uuid::new_v4());
+        let cursor = cursor_after(item, SortDirection::Asc, Some("car"));
+        assert!(request(SortDirection::Asc, 10, Some(&cursor), Some("car")).is_ok());
+        assert!(request(SortDirection::Desc, 10, Some(&cursor), Some("car")).is_err());
+        assert!(request(SortDirection::Asc, 10, Some(&cursor), Some("bike")).is_err());
+        assert!(request(SortDirection::Asc, 10, Some(&cursor), None).is_err());
+        let other_field = PageRequest::new(
+            SortField::Title,
+            SortDirection::Asc,
+            None,
+            Some(&cursor),
+            Some(String::from("car")),
+        );
+        assert!(other_field.is_err());
+        let unscoped = cursor_after(item, SortDirection::Asc, None);
+        assert!(request(SortDirection::Asc, 10, Some(&unscoped), Some("car")).is_err());
+    }
+
+    #[test]
+    fn the_last_page_has_no_next_cursor() {
+        let items: Vec<Item> = (0..6).map(|price| (price, Uuid::new_v4())).collect();
+        // A last page that is exactly full still has no cursor to an empty page
+        assert_eq!(page_through(&items, SortDirection::Asc, 3).1, 2);
+        assert_eq!(page_through(&items, SortDirection::Asc, 4).1, 2);
+        assert_eq!(page_through(&items, SortDirection::Asc, 6).1, 1);
+        assert_eq!(page_through(&items, SortDirection::Asc, 10).1, 1);
+        assert_eq!(page_through(&[], SortDirection::Asc, 10).1, 1);
+        let last = cursor_after(items[5], SortDirection::Asc, Some("q"));
+        let request = request(SortDirection::Asc, 10, Some(&last), Some("q")).unwrap();
+        let page = paginate(items.iter().copied(), &request, key_of);
+        assert!(page.items.is_empty());
+        assert!(page.next_cursor.is_none());
+    }
+}
@@ -0,0 +1,333 @@
+use std::{
+    collections::{hash_map::Entry, HashMap},
+    ops::Bound,
+    sync::{Arc, Mutex},
+};
+
+use anyhow::{anyhow, Result};
+use futures_util::StreamExt;
+use marketplace_domain::classified_ad_events::ClassifiedAdEvents;
+use poem_openapi::Object;
+use rust_decimal::{
+    prelude::{FromPrimitive, ToPrimitive},
+    Decimal,
+};
+use tantivy::{
+    collector::{Count, TopDocs},
+    columnar::StrColumn,
+    doc,
+    query::{AllQuery, BooleanQuery, Occur, Query, QueryParser, RangeQuery, TermQuery},
+    schema::{Field, IndexRecordOption, Schema, Value, FAST, INDEXED, STORED, STRING, TEXT},
+    snippet::SnippetGenerator,
+    DocAddress, Index, IndexReader, IndexWriter, ReloadPolicy, Searcher, TantivyDocument, Term,
+};
+use uuid::Uuid;
+
+use crate::{
+    event_stream::{ClassifiedAdEventLog, RecordedEvent},
+    pagination::{paginate, Page, PageRequest, SortKey},
+};
+
+/// Memory the index writer buffers documents in; the least tantivy accepts.
+const WRITER_MEMORY_BYTES: usize = 15_000_000;
+const TITLE_BOOST: f32 = 2.0;
+const HIGHLIGHT_MAX_CHARS: usize = 150;
+/// How far into the results a search can page; collecting deeper gets expensive.
+pub const MAX_SEARCH_RESULTS: usize = 10_000;
+const ID_FIELD: &str = "id";
+
+// ================================================================================
+// Query
+// ================================================================================
+
+pub struct SearchQuery {
+    /// Words to look for in titles and texts; every ad matches when empty.
+    pub text: String,
+    /// Only ads priced in this currency.
+    pub currency: Option<String>,
+    pub min_price: Option<f64>,
+    pub max_price: Option<f64>,
+}
+
+impl SearchQuery {
+    /// The words searched for, which the scores in a cursor were computed against.
+    pub fn scope(&self) -> String {
+        self.text.clone()
+    }
+
+    fn validate(&self) -> Result<()> {
+        // Amounts in different currencies cannot be compared
+        if (self.min_price.is_some() || self.max_price.is_some()) && self.currency.is_none() {
+            return Err(anyhow!("A price filter needs a currency"));
+        }
+        if let (Some(min), Some(max)) = (self.min_price, self.max_price) {
+            if min > max {
+                return Err(anyhow!("The minimum price is above the maximum"));
+            }
+        }
+        Ok(())
+    }
+}
+
+#[derive(Object)]
+pub struct ClassifiedAdV1SearchHit {
+    pub id: String,
+    pub title: Option<String>,
+    pub text: Option<String>,
+    pub price: Option<f64>,
+    pub currency: Option<String>,
+    pub score: f32,
+    /// Part of the title with matching words in `<b>` tags
+    pub title_highlight: Option<String>,
+    /// Part of the text with matching words in `<b>` tags
+    pub text_highlight: Option<String>,
+}
+
+#[derive(Object)]
+pub struct ClassifiedAdV1SearchResults {
+    /// Number of ads matching, across all pages; only the best 10 000 can be paged through
+    pub total: u64,
+    /// Best matches first
+    pub hits: Vec<ClassifiedAdV1SearchHit>,
+    /// Pass as `cursor`, with the same search, to get the next page; missing on
+    /// the last page
+    pub next_cursor: Option<String>,
+}
+
+/// A page of search hits, and how many ads matched in all.
+pub struct SearchPage {
+    pub total: u64,
+    pub page: Page<ClassifiedAdV1SearchHit>,
+}
+
+impl From<SearchPage> for ClassifiedAdV1SearchResults {
+    fn from(results: SearchPage) -> Self {
+        Self {
+            total: results.total,
+            hits: results.page.items,
+            next_cursor: results.page.next_cursor,
+        }
+    }
+}
+
+// ================================================================================
+// Index
+// ================================================================================
+
+struct SearchFields {
+    id: Field,
+    title: Field,
+    text: Field,
+    price: Field,
+    currency: Field,
+}
+
+/// What the index keeps of an ad to rebuild its document when it changes.
+#[derive(Default)]
+struct SearchableAd {
+    title: Option<String>,
+    text: Option<String>,
+    price: Option<(Decimal, String)>,
+    active: bool,
+}
+
+/// Full-text search over active ads, kept up to date from the ad event log.
+pub struct ClassifiedAdSearch {
+    _index: Index,
+    _fields: SearchFields,
+    _writer: Mutex<IndexWriter>,
+    _reader: IndexReader,
+    _ads: Mutex<HashMap<Uuid, SearchableAd>>,
+}
+
+impl ClassifiedAdSearch {
+    /// An empty index held in memory.
+    pub fn new() -> Result<Self> {
+        let mut schema = Schema::builder();
+        let fields = SearchFields {
+            id: schema.add_text_field(ID_FIELD, STRING | STORED | FAST),
+            title: schema.add_text_field("title", TEXT | STORED),
+            text: schema.add_text_field("text", TEXT | STORED),
+            price: schema.add_f64_field("price", INDEXED | FAST | STORED),
+            currency: schema.add_text_field("currency", STRING | STORED),
+        };
+        let index = Index::create_in_ram(schema.build());
+        let writer = index.writer(WRITER_MEMORY_BYTES)?;
+        let reader = index
+            .reader_builder()
+            .reload_policy(ReloadPolicy::Manual)
+            .try_into()?;
+        Ok(Self {
+            _index: index,
+            _fields: fields,
+            _writer: Mutex::new(writer),
+            _reader: reader,
+            _ads: Mutex::new(HashMap::new()),
+        })
+    }
+
+    /// Follows the event log from the start for as long as it is open.
+    pub async fn run(self: Arc<Self>, log: Arc<ClassifiedAdEventLog>) {
+        let mut events = Box::pin(log.subscribe_all(0));
+        while let Some(event) = events.next().await {
+            if let Err(e) = self.when(&event) {
+                tracing::error!("Could not index event {}: {}", event.global_position, e);
+            }
+        }
+    }
+
+    fn when(&self, recorded: &RecordedEvent) -> Result<()> {
+        let mut ads = self._ads.lock().unwrap();
+        let ad = ads.entry(recorded.stream_id).or_default();
+        let was_active = ad.active;
+        match &recorded.event {
+            ClassifiedAdEvents::TitleChanged(e) => ad.title = Some(e.title.clone()),
+            ClassifiedAdEvents::TextUpdated(e) => ad.text = Some(e.ad_text.clone()),
+            ClassifiedAdEvents::PriceUpdated(e) => {
+                ad.price = Some((e.price, e.currency_code.clone()))
+            }
+            ClassifiedAdEvents::Published(_) | ClassifiedAdEvents::Reactivated(_) => {
+                ad.active = true
+            }
+            ClassifiedAdEvents::SentForReview(_)
+            | ClassifiedAdEvents::Rejected(_)
+            | ClassifiedAdEvents::MarkedAsSold(_)
+            | ClassifiedAdEvents::Deactivated(_) => ad.active = false,
+            _ => return Ok(()),
+        }
+        if !was_active && !ad.active {
+            return Ok(());
+        }
+        let fields = &self._fields;
+        let mut writer = self._writer.lock().unwrap();
+        let id = recorded.stream_id.to_string();
+        writer.delete_term(Term::from_field_text(fields.id, &id));
+        if ad.active {
+            let mut document = doc!(fields.id => id);
+            if let Some(title) = &ad.title {
+                document.add_text(fields.title, title);
+            }
+            if let Some(text) = &ad.text {
+                document.add_text(fields.text, text);
+            }
+            if let Some((price, currency)) = &ad.price {
+                document.add_f64(fields.price, price.to_f64().unwrap_or_default());
+                document.add_text(fields.currency, currency);
+            }
+            writer.add_document(document)?;
+        }
+        writer.commit()?;
+        self._reader.reload()?;
+        Ok(())
+    }
+
+    /// A page of the active ads matching `query`, best first and then by id. Words
+    /// are matched in titles, which count for more, and texts; query syntax the
+    /// parser does not understand is searched for as plain words. The request has
+    /// to be sorted by relevance.
+    pub fn search(&self, query: &SearchQuery, request: &PageRequest) -> Result<SearchPage> {
+        query.validate()?;
+        let fields = &self._fields;
+        let searcher = self._reader.searcher();
+        let text_query: Box<dyn Query> = match query.text.trim() {
+            "" => Box::new(AllQuery),
+            text => {
+                let mut parser =
+                    QueryParser::for_index(&self._index, vec![fields.title, fields.text]);
+                parser.set_field_boost(fields.title, TITLE_BOOST);
+                parser.parse_query_lenient(text).0
+            }
+        };
+        let mut clauses = vec![(Occur::Must, text_query.box_clone())];
+        if let Some(currency) = &query.currency {
+            let term = Term::from_field_text(fields.currency, &currency.to_uppercase());
+            clauses.push((
+                Occur::Must,
+                Box::new(TermQuery::new(term, IndexRecordOption::Basic)),
+            ));
+        }
+        if query.min_price.is_some() || query.max_price.is_some() {
+            clauses.push((
+                Occur::Must,
+                Box::new(RangeQuery::new_f64_bounds(
+                    String::from("price"),
+                    query.min_price.map_or(Bound::Unbounded, Bound::Included),
+                    query.max_price.map_or(Bound::Unbounded, Bound::Included),
+                )),
+            ));
+        }
+        let filtered = BooleanQuery::new(clauses);
+        let (top, total) =
+            searcher.search(&filtered, &(TopDocs::with_limit(MAX_SEARCH_RESULTS), Count))?;
+        let found = ids_of(&searcher, top)?;
+        let page = paginate(found, request, |(score, _, id), _| {
+            let key = Decimal::from_f32(*score).unwrap_or_default();
+            (SortKey::Number(key), *id)
+        });
+
+        let mut title_snippets = SnippetGenerator::create(&searcher, &*text_query, fields.title)?;
+        title_snippets.set_max_num_chars(HIGHLIGHT_MAX_CHARS);
+        let mut text_snippets = SnippetGenerator::create(&searcher, &*text_query, fields.text)?;
+        text_snippets.set_max_num_chars(HIGHLIGHT_MAX_CHARS);
+        let mut hits = vec![];
+        for (score, address, _) in page.items {
+            let document: TantivyDocument = searcher.doc(address)?;
+            let text_of = |field: Field| {
+                document
+                    .get_first(field)
+                    .and_then(|value| value.as_str())
+                    .map(String::from)
+            };
+            let highlight = |snippets: &SnippetGenerator| {
+                let snippet = snippets.snippet_from_doc(&document);
+                (!snippet.is_empty()).then(|| snippet.to_html())
+            };
+            hits.push(ClassifiedAdV1SearchHit {
+                id: text_of(fields.id).unwrap_or_default(),
+                title: text_of(fields.title),
+                text: text_of(fields.text),
+                price: document.get_first(fields.price).and_then(|v| v.as_f64()),
+                currency: text_of(fields.currency),
+                score,
+                title_highlight: highlight(&title_snippets),
+                text_highlight: highlight(&text_snippets),
+            });
+        }
+        Ok(SearchPage {
+            total: total as u64,
+            page: Page {
+                items: hits,
+                next_cursor: page.next_cursor,
+            },
+        })
+    }
+}
+
+/// Reads the ad id of each hit from the id fast field, so ties in score can be
+/// broken without loading the documents.
+fn ids_of(
+    searcher: &Searcher,
+    hits: Vec<(f32, DocAddress)>,
+) -> Result<Vec<(f32, DocAddress, Uuid)>> {
+    let mut columns: HashMap<u32, Option<StrColumn>> = HashMap::new();
+    let mut id = String::new();
+    let mut found = Vec::with_capacity(hits.len());
+    for (score, address) in hits {
+        let column = match columns.entry(address.segment_ord) {
+            Entry::Occupied(entry) => entry.into_mut(),
+            Entry::Vacant(entry) => entry.insert(
+                searcher
+                    .segment_reader(address.segment_ord)
+                    .fast_fields()
+                    .str(ID_FIELD)?,
+            ),
+        };
+        let no_id = || anyhow!("Search hit {:?} has no id", address);
+        let column = column.as_ref().ok_or_else(no_id)?;
+        let ord = column.term_ords(address.doc_id).next().ok_or_else(no_id)?;
+        id.clear();
+        column.ord_to_str(ord, &mut id)?;
+        found.push((score, address, Uuid::parse_str(&id)?));
+    }
+    Ok(found)
+}
@@ -0,0 +1,141 @@
+use std::{collections::HashSet, fmt, fs, path::Path, time::Duration};
+
+use anyhow::{anyhow, Context, Result};
+use marketplace_domain::ITextModeration;
+
+/// Word list shipped with the api, one word per line.
+pub const BUNDLED_WORD_LIST: &str = include_str!("../profanity.txt");
+
+/// Raised when the text moderation service cannot give an answer.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TextModerationUnavailable {
+    pub reason: String,
+}
+
+impl fmt::Display for TextModerationUnavailable {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Text moderation is unavailable: {}", self.reason)
+    }
+}
+
+impl std::error::Error for TextModerationUnavailable {}
+
+// ================================================================================
+// Word list
+// ================================================================================
+
+/// Flags text containing any word from a list. Words are compared after
+/// lowercasing and undoing common substitutions, so "Sh1t" matches "shit".
+#[derive(Clone)]
+pub struct WordListTextModeration {
+    _words: HashSet<String>,
+}
+
+impl WordListTextModeration {
+    pub fn new(words: impl IntoIterator<Item = String>) -> Self {
+        Self {
+            _words: words.into_iter().map(|w| normalise_word(&w)).collect(),
+        }
+    }
+
+    pub fn bundled() -> Self {
+        Self::from_list(BUNDLED_WORD_LIST)
+    }
+
+    pub fn from_file(path: impl AsRef<Path>) -> Result<Self> {
+        let path = path.as_ref();
+        let content = fs::read_to_string(path)
+            .with_context(|| format!("Could not read word list from {}", path.display()))?;
+        Ok(Self::from_list(&content))
+    }
+
+    /// One word per line; blank lines and `#` comments are skipped.
+    pub fn from_list(content: &str) -> Self {
+        Self::new(
+            content
+                .lines()
+                .map(str::trim)
+                .filter(|line| !line.is_empty() && !line.starts_with('#'))
+                .map(String::from),
+        )
+    }
+}
+
+impl Default for WordListTextModeration {
+    fn default() -> Self {
+        Self::bundled()
+    }
+}
+
+impl ITextModeration for WordListTextModeration {
+    fn contains_profanity(&self, text: &str) -> Result<bool> {
+        Ok(text
+            .split(|c: char| c.is_whitespace() || matches!(c, ',' | '.' | '?' | '_' | '-'))
+            .map(normalise_word)
+            .any(|word| self._words.contains(&word)))
+    }
+}
+
+fn normalise_word(word: &str) -> String {
+    word.to_lowercase()
+        .chars()
+        .map(|c| match c {
+            '0' => 'o',
+            '1' | '!' => 'i',
+            '3' => 'e',
+            '4' | '@' => 'a',
+            '5' | '$' => 's',
+            '7' => 't',
+            c => c,
+        })
+        .filter(|c| c.is_alphanumeric())
+        .collect()
+}
+
+// ================================================================================
+// HTTP service
+// ================================================================================
+
+/// Asks a PurgoMalum style service, answering `GET /service/containsprofanity?text=`
+/// with `true` or `false`.
+pub struct PurgomalumTextModeration {
+    _base_url: String,
+    _agent: ureq::Agent,
+}
+
+impl PurgomalumTextModeration {
+    pub fn new(base_url: impl Into<String>, timeout: Duration) -> Self {
+        let agent = ureq::Agent::config_builder()
+            .timeout_global(Some(timeout))
+            .build()
+            .into();
+        Self {
+            _base_url: base_url.into().trim_end_matches('/').to_string(),
+            _agent: agent,
+        }
+    }
+}
+
+impl ITextModeration for PurgomalumTextModeration {
+    fn contains_profanity(&self, text: &str) -> Result<bool> {
+        let unavailable = |reason: String| TextModerationUnavailable { reason };
+        let url = format!("{}/service/containsprofanity", self._base_url);
+        let body = self
+            ._agent
+            .get(&url)
+            .query("text", text)
+            .call()
+            .map_err(|e| unavailable(e.to_string()))?
+            .body_mut()
+            .read_to_string()
+            .map_err(|e| unavailable(e.to_string()))?;
+        match body.trim() {
+            "true" => Ok(true),
+            "false" => Ok(false),
+            other => Err(anyhow!(unavailable(format!(
+                "unexpected answer {:?}",
+                other
+            )))),
+        }
+    }
+}
@@ -1,5 +1,5 @@
 use anyhow::Result;
-
+use marketplace_framework::CommandMetadata;
 
 pub trait IHandleCommand {
     type Command;
@@ -9,14 +9,16 @@ pub trait IHandleCommand {
 pub trait IEntityStore: Sync + Send {
     type Entity;
     /// Loads an entity by id
-    fn load(&self, id: String) -> Self::Entity;
+    fn load(&self, id: String) -> Result<Self::Entity>;
     /// Check if entity with a given id already exists
     fn exists(&self, id: String) -> bool;
-    /// Persists an entity
-    fn save(&mut self, entity: Self::Entity) -> Result<()>;
+    /// Persists an entity. When `expected_version` is given, fails with a
+    /// `ConcurrencyConflict` if the stored entity has moved on since it was loaded.
+    /// Without one the entity is new, and saving fails if its id is already stored.
+    fn save(&mut self, entity: Self::Entity, expected_version: Option<i64>) -> Result<()>;
 }
 
 pub trait IApplicationService {
     type Command;
-    fn handle(&self, command: impl Into<Self::Command>) -> Result<()>;
+    fn handle(&self, command: impl Into<Self::Command>, metadata: &CommandMetadata) -> Result<()>;
 }
@@ -0,0 +1,299 @@
+use std::{
+    collections::HashMap,
+    str::FromStr,
+    sync::{Arc, Mutex},
+};
+
+use anyhow::{anyhow, Result};
+use chrono::{DateTime, Utc};
+use marketplace_contracts::user_profiles::v1;
+use marketplace_domain::{
+    user_profile::{DisplayName, FullName, Role, UserEvents, UserProfile, UserProfileAggregate},
+    ITextModeration, UserId,
+};
+use marketplace_framework::{
+    AggregateRoot, CommandMetadata, ConcurrencyConflict, EntityNotFound, ICommandHandler,
+};
+use poem_openapi::{types::multipart::Upload, Multipart, Object};
+
+use crate::{
+    text_moderation::WordListTextModeration,
+    traits::{IApplicationService, IEntityStore},
+};
+
+pub struct UserProfileStore {
+    _store: HashMap<String, UserProfile>,
+}
+
+impl UserProfileStore {
+    pub fn new() -> Self {
+        Self {
+            _store: HashMap::new(),
+        }
+    }
+}
+
+impl Default for UserProfileStore {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl IEntityStore for UserProfileStore {
+    type Entity = UserProfile;
+
+    fn save(&mut self, profile: UserProfile, expected_version: Option<i64>) -> Result<()> {
+        let id = profile.id().value().to_string();
+        match (expected_version, self._store.get(&id)) {
+            (None, Some(_)) => return Err(anyhow!("User profile with this ID already exists")),
+            (Some(expected_version), Some(stored)) if stored.version() != expected_version => {
+                return Err(ConcurrencyConflict {
+                    expected_version,
+                    actual_version: stored.version(),
+                }
+                .into());
+            }
+            _ => {}
+        }
+        self._store.insert(id, profile);
+        Ok(())
+    }
+
+    fn exists(&self, id: String) -> bool {
+        self._store.contains_key(&id)
+    }
+
+    fn load(&self, id: String) -> Result<UserProfile> {
+        match self._store.get(&id) {
+            Some(profile) => Ok(profile.clone()),
+            None => Err(EntityNotFound { id }.into()),
+        }
+    }
+}
+
+#[derive(Clone)]
+pub struct UserProfileApplicationService {
+    _repository: Arc<Mutex<dyn IEntityStore<Entity = UserProfile>>>,
+    _text_moderation: Arc<dyn ITextModeration + Send + Sync>,
+}
+
+impl UserProfileApplicationService {
+    pub fn new(text_moderation: Arc<dyn ITextModeration + Send + Sync>) -> Self {
+        Self {
+            _repository: Arc::new(Mutex::new(UserProfileStore::new())),
+            _text_moderation: text_moderation,
+        }
+    }
+}
+
+impl Default for UserProfileApplicationService {
+    fn default() -> Self {
+        Self::new(Arc::new(WordListTextModeration::bundled()))
+    }
+}
+
+impl UserProfileApplicationService {
+    /// Loads the current state of a profile for queries.
+    pub fn load(&self, id: UserId) -> Result<UserProfile> {
+        self._repository
+            .lock()
+            .unwrap()
+            .load(id.value().to_string())
+    }
+
+    fn handle_register(&self, cmd: v1::RegisterUser) -> Result<()> {
+        if self
+            ._repository
+            .lock()
+            .unwrap()
+            .exists(cmd.user_id.to_string())
+        {
+            return Err(anyhow!("User profile with this ID already exists"));
+        }
+        let mut profile = UserProfile::new_empty();
+        profile.create_new_profile(
+            UserId::new(cmd.user_id),
+            FullName::new(&cmd.full_name)?,
+            DisplayName::new(&cmd.display_name)?,
+            self._text_moderation.clone(),
+        )?;
+        self._repository.lock().unwrap().save(profile, None)?;
+        Ok(())
+    }
+
+    fn handle_update<Cmd>(
+        &self,
+        id: UserId,
+        cmd: Cmd,
+        metadata: &CommandMetadata,
+        operation: fn(cmd: Cmd, p: &mut UserProfile) -> Result<()>,
+    ) -> Result<()> {
+        let mut profile = self.load(id)?;
+        let loaded_version = profile.version();
+        metadata.check_version(loaded_version)?;
+        operation(cmd, &mut profile)?;
+        self._repository
+            .lock()
+            .unwrap()
+            .save(profile, Some(loaded_version))?;
+        Ok(())
+    }
+}
+
+impl IApplicationService for UserProfileApplicationService {
+    type Command = v1::Commands;
+    fn handle(&self, command: impl Into<Self::Command>, metadata: &CommandMetadata) -> Result<()> {
+        match command.into() {
+            v1::Commands::RegisterUser(cmd) => self.handle_register(cmd)?,
+            v1::Commands::UpdateFullName(cmd) => {
+                self.handle_update(UserId::new(cmd.user_id), cmd, metadata, |cmd, p| {
+                    p.update_full_name(UserId::new(cmd.user_id), FullName::new(&cmd.full_name)?)
+                })?
+            }
+            v1::Commands::UpdateDisplayName(cmd) => {
+                let moderation = self._text_moderation.clone();
+                self.handle_update(
+                    UserId::new(cmd.user_id),
+                    (cmd, moderation),
+                    metadata,
+                    |(cmd, moderation), p| {
+                        p.update_display_name(
+                            UserId::new(cmd.user_id),
+                            DisplayName::new(&cmd.display_name)?,
+                            moderation,
+                        )
+                    },
+                )?
+            }
+            v1::Commands::UpdateProfilePhoto(cmd) => {
+                self.handle_update(UserId::new(cmd.user_id), cmd, metadata, |cmd, p| {
+                    p.update_profile_photo(
+                        UserId::new(cmd.user_id),
+                        cmd.photo_url,
+                        cmd.thumbnail_url,
+                    )
+                })?
+            }
+            v1::Commands::GrantRole(cmd) => {
+                self.handle_update(UserId::new(cmd.user_id), cmd, metadata, |cmd, p| {
+                    p.grant_role(
+                        UserId::new(cmd.user_id),
+                        Role::from_str(&cmd.role)?,
+                        UserId::new(cmd.granted_by),
+                    )
+                })?
+            }
+            v1::Commands::RevokeRole(cmd) => {
+                self.handle_update(UserId::new(cmd.user_id), cmd, metadata, |cmd, p| {
+                    p.revoke_role(
+                        UserId::new(cmd.user_id),
+                        Role::from_str(&cmd.role)?,
+                        UserId::new(cmd.revoked_by),
+                    )
+                })?
+            }
+        };
+        Ok(())
+    }
+}
+
+/// Lets the application service be registered on the command bus for each of its commands.
+impl<C: Into<v1::Commands>> ICommandHandler<C> for UserProfileApplicationService {
+    fn handle(&self, command: C, metadata: &CommandMetadata) -> Result<()> {
+        IApplicationService::handle(self, command, metadata)
+    }
+}
+
+#[derive(Object)]
+pub struct UserProfileV1RegisterUser {
+    pub full_name: String,
+    pub display_name: String,
+}
+#[derive(Object)]
+pub struct UserProfileV1UpdateFullName {
+    pub full_name: String,
+}
+#[derive(Object)]
+pub struct UserProfileV1UpdateDisplayName {
+    pub display_name: String,
+}
+#[derive(Object)]
+pub struct UserProfileV1GrantRole {
+    pub user_id: String,
+    /// `moderator` or `admin`
+    pub role: String,
+}
+#[derive(Object)]
+pub struct UserProfileV1RevokeRole {
+    pub user_id: String,
+    /// `moderator` or `admin`
+    pub role: String,
+}
+#[derive(Multipart)]
+pub struct UserProfileV1UploadPhoto {
+    /// PNG or JPEG image
+    pub file: Upload,
+}
+
+/// Current state of a user profile
+#[derive(Object)]
+pub struct UserProfileV1Details {
+    pub user_id: String,
+    pub full_name: String,
+    pub display_name: String,
+    pub photo_url: Option<String>,
+    pub thumbnail_url: Option<String>,
+    /// Roles granted on top of `user`
+    pub roles: Vec<String>,
+}
+
+impl From<&UserProfile> for UserProfileV1Details {
+    fn from(profile: &UserProfile) -> Self {
+        Self {
+            user_id: profile.id().value().to_string(),
+            full_name: profile.full_name().value(),
+            display_name: profile.display_name().value(),
+            photo_url: profile.photo_url(),
+            thumbnail_url: profile.thumbnail_url(),
+            roles: profile
+                .roles()
+                .iter()
+                .map(|role| role.to_string())
+                .collect(),
+        }
+    }
+}
+
+/// A role grant or revocation on a profile
+#[derive(Object)]
+pub struct UserProfileV1RoleChange {
+    /// `granted` or `revoked`
+    pub change: String,
+    pub role: String,
+    pub changed_by: String,
+    pub changed_at: DateTime<Utc>,
+}
+
+impl UserProfileV1RoleChange {
+    pub fn history(profile: &UserProfile) -> Vec<Self> {
+        profile
+            .role_history()
+            .into_iter()
+            .filter_map(|event| match event {
+                UserEvents::RoleGranted(e) => Some(Self {
+                    change: String::from("granted"),
+                    role: e.role.to_string(),
+                    changed_by: e.granted_by.value().to_string(),
+                    changed_at: e.granted_at.into(),
+                }),
+                UserEvents::RoleRevoked(e) => Some(Self {
+                    change: String::from("revoked"),
+                    role: e.role.to_string(),
+                    changed_by: e.revoked_by.value().to_string(),
+                    changed_at: e.revoked_at.into(),
+                }),
+                _ => None,
+            })
+            .collect()
+    }
+}
@@ -1,9 +1,12 @@
 pub mod classified_ads {
     pub mod v1 {
-        use serde_derive::Deserialize;
+        use std::collections::BTreeMap;
+
+        use rust_decimal::Decimal;
+        use serde_derive::{Deserialize, Serialize};
         use uuid::Uuid;
 
-        #[derive(Deserialize)]
+        #[derive(Clone, Serialize, Deserialize)]
         pub struct Create {
             pub id: Uuid,
             pub owner_id: Uuid,
@@ -13,7 +16,7 @@ pub mod classified_ads {
                 Commands::Create(cmd)
             }
         }
-        #[derive(Deserialize)]
+        #[derive(Clone, Serialize, Deserialize)]
         pub struct SetTitle {
             pub id: Uuid,
             pub title: String,
@@ -23,7 +26,7 @@ pub mod classified_ads {
                 Commands::SetTitle(cmd)
             }
         }
-        #[derive(Deserialize)]
+        #[derive(Clone, Serialize, Deserialize)]
         pub struct UpdateText {
             pub id: Uuid,
             pub text: String,
@@ -33,10 +36,10 @@ pub mod classified_ads {
                 Commands::UpdateText(cmd)
             }
         }
-        #[derive(Deserialize)]
+        #[derive(Clone, Serialize, Deserialize)]
         pub struct UpdatePrice {
             pub id: Uuid,
-            pub price: f64,
+            pub price: Decimal,
             pub currency: String,
         }
         impl From<UpdatePrice> for Commands {
@@ -44,7 +47,36 @@ pub mod classified_ads {
                 Commands::UpdatePrice(cmd)
             }
         }
-        #[derive(Deserialize)]
+        #[derive(Clone, Serialize, Deserialize)]
+        pub enum AttributeValue {
+            Number(Decimal),
+            Text(String),
+            Boolean(bool),
+        }
+        #[derive(Clone, Serialize, Deserialize)]
+        pub struct ChangeCategory {
+            pub id: Uuid,
+            pub category_id: Uuid,
+            pub attributes: BTreeMap<String, AttributeValue>,
+        }
+        impl From<ChangeCategory> for Commands {
+            fn from(cmd: ChangeCategory) -> Self {
+                Commands::ChangeCategory(cmd)
+            }
+        }
+        #[derive(Clone, Serialize, Deserialize)]
+        pub struct SetLocation {
+            pub id: Uuid,
+            pub latitude: f64,
+            pub longitude: f64,
+            pub postal_area: String,
+        }
+        impl From<SetLocation> for Commands {
+            fn from(cmd: SetLocation) -> Self {
+                Commands::SetLocation(cmd)
+            }
+        }
+        #[derive(Clone, Serialize, Deserialize)]
         pub struct RequestToPublish {
             pub id: Uuid,
         }
@@ -53,14 +85,319 @@ pub mod classified_ads {
                 Commands::RequestToPublish(cmd)
             }
         }
+        #[derive(Clone, Serialize, Deserialize)]
+        pub struct Approve {
+            pub id: Uuid,
+            pub approved_by: Uuid,
+        }
+        impl From<Approve> for Commands {
+            fn from(cmd: Approve) -> Self {
+                Commands::Approve(cmd)
+            }
+        }
+        #[derive(Clone, Serialize, Deserialize)]
+        pub struct Reject {
+            pub id: Uuid,
+            pub rejected_by: Uuid,
+            pub reason: String,
+        }
+        impl From<Reject> for Commands {
+            fn from(cmd: Reject) -> Self {
+                Commands::Reject(cmd)
+            }
+        }
+        #[derive(Clone, Serialize, Deserialize)]
+        pub struct MarkAsSold {
+            pub id: Uuid,
+        }
+        impl From<MarkAsSold> for Commands {
+            fn from(cmd: MarkAsSold) -> Self {
+                Commands::MarkAsSold(cmd)
+            }
+        }
+        #[derive(Clone, Serialize, Deserialize)]
+        pub struct Deactivate {
+            pub id: Uuid,
+        }
+        impl From<Deactivate> for Commands {
+            fn from(cmd: Deactivate) -> Self {
+                Commands::Deactivate(cmd)
+            }
+        }
+        #[derive(Clone, Serialize, Deserialize)]
+        pub struct Reactivate {
+            pub id: Uuid,
+        }
+        impl From<Reactivate> for Commands {
+            fn from(cmd: Reactivate) -> Self {
+                Commands::Reactivate(cmd)
+            }
+        }
+        #[derive(Clone, Serialize, Deserialize)]
+        pub struct AddPicture {
+            pub id: Uuid,
+            pub picture_id: Uuid,
+            pub url: String,
+            pub width: u32,
+            pub height: u32,
+        }
+        impl From<AddPicture> for Commands {
+            fn from(cmd: AddPicture) -> Self {
+                Commands::AddPicture(cmd)
+            }
+        }
+        #[derive(Clone, Serialize, Deserialize)]
+        pub struct ResizePicture {
+            pub id: Uuid,
+            pub picture_id: Uuid,
+            pub width: u32,
+            pub height: u32,
+        }
+        impl From<ResizePicture> for Commands {
+            fn from(cmd: ResizePicture) -> Self {
+                Commands::ResizePicture(cmd)
+            }
+        }
+        #[derive(Clone, Serialize, Deserialize)]
+        pub struct RemovePicture {
+            pub id: Uuid,
+            pub picture_id: Uuid,
+        }
+        impl From<RemovePicture> for Commands {
+            fn from(cmd: RemovePicture) -> Self {
+                Commands::RemovePicture(cmd)
+            }
+        }
 
-        #[derive(Deserialize)]
+        #[derive(Clone, Serialize, Deserialize)]
         pub enum Commands {
             Create(Create),
             SetTitle(SetTitle),
             UpdateText(UpdateText),
             UpdatePrice(UpdatePrice),
+            ChangeCategory(ChangeCategory),
+            SetLocation(SetLocation),
             RequestToPublish(RequestToPublish),
+            Approve(Approve),
+            Reject(Reject),
+            MarkAsSold(MarkAsSold),
+            Deactivate(Deactivate),
+            Reactivate(Reactivate),
+            AddPicture(AddPicture),
+            ResizePicture(ResizePicture),
+            RemovePicture(RemovePicture),
+        }
+    }
+}
+
+pub mod user_profiles {
+    pub mod v1 {
+        use serde_derive::{Deserialize, Serialize};
+        use uuid::Uuid;
+
+        #[derive(Clone, Serialize, Deserialize)]
+        pub struct RegisterUser {
+            pub user_id: Uuid,
+            pub full_name: String,
+            pub display_name: String,
+        }
+        impl From<RegisterUser> for Commands {
+            fn from(cmd: RegisterUser) -> Self {
+                Commands::RegisterUser(cmd)
+            }
+        }
+        #[derive(Clone, Serialize, Deserialize)]
+        pub struct UpdateFullName {
+            pub user_id: Uuid,
+            pub full_name: String,
+        }
+        impl From<UpdateFullName> for Commands {
+            fn from(cmd: UpdateFullName) -> Self {
+                Commands::UpdateFullName(cmd)
+            }
+        }
+        #[derive(Clone, Serialize, Deserialize)]
+        pub struct UpdateDisplayName {
+            pub user_id: Uuid,
+            pub display_name: String,
+        }
+        impl From<UpdateDisplayName> for Commands {
+            fn from(cmd: UpdateDisplayName) -> Self {
+                Commands::UpdateDisplayName(cmd)
+            }
+        }
+        /// Records a photo that was uploaded to the blob store, with its thumbnail.
+        #[derive(Clone, Serialize, Deserialize)]
+        pub struct UpdateProfilePhoto {
+            pub user_id: Uuid,
+            pub photo_url: String,
+            pub thumbnail_url: String,
+        }
+        impl From<UpdateProfilePhoto> for Commands {
+            fn from(cmd: UpdateProfilePhoto) -> Self {
+                Commands::UpdateProfilePhoto(cmd)
+            }
+        }
+
+        #[derive(Clone, Serialize, Deserialize)]
+        pub struct GrantRole {
+            pub user_id: Uuid,
+            pub role: String,
+            pub granted_by: Uuid,
+        }
+        impl From<GrantRole> for Commands {
+            fn from(cmd: GrantRole) -> Self {
+                Commands::GrantRole(cmd)
+            }
+        }
+        #[derive(Clone, Serialize, Deserialize)]
+        pub struct RevokeRole {
+            pub user_id: Uuid,
+            pub role: String,
+            pub revoked_by: Uuid,
+        }
+        impl From<RevokeRole> for Commands {
+            fn from(cmd: RevokeRole) -> Self {
+                Commands::RevokeRole(cmd)
+            }
+        }
+
+        #[derive(Clone, Serialize, Deserialize)]
+        pub enum Commands {
+            RegisterUser(RegisterUser),
+            UpdateFullName(UpdateFullName),
+            UpdateDisplayName(UpdateDisplayName),
+            UpdateProfilePhoto(UpdateProfilePhoto),
+            GrantRole(GrantRole),
+            RevokeRole(RevokeRole),
+        }
+    }
+}
+
+pub mod accounts {
+    pub mod v1 {
+        use serde_derive::{Deserialize, Serialize};
+        use uuid::Uuid;
+
+        #[derive(Clone, Serialize, Deserialize)]
+        pub struct RegisterAccount {
+            pub user_id: Uuid,
+            pub email: String,
+            pub password: String,
+        }
+        impl From<RegisterAccount> for Commands {
+            fn from(cmd: RegisterAccount) -> Self {
+                Commands::RegisterAccount(cmd)
+            }
+        }
+        #[derive(Clone, Serialize, Deserialize)]
+        pub struct ChangePassword {
+            pub user_id: Uuid,
+            pub current_password: String,
+            pub new_password: String,
+        }
+        impl From<ChangePassword> for Commands {
+            fn from(cmd: ChangePassword) -> Self {
+                Commands::ChangePassword(cmd)
+            }
+        }
+        /// Mails a reset token to the address if an account uses it.
+        #[derive(Clone, Serialize, Deserialize)]
+        pub struct RequestPasswordReset {
+            pub email: String,
+        }
+        impl From<RequestPasswordReset> for Commands {
+            fn from(cmd: RequestPasswordReset) -> Self {
+                Commands::RequestPasswordReset(cmd)
+            }
+        }
+        #[derive(Clone, Serialize, Deserialize)]
+        pub struct ResetPassword {
+            pub email: String,
+            pub token: String,
+            pub new_password: String,
+        }
+        impl From<ResetPassword> for Commands {
+            fn from(cmd: ResetPassword) -> Self {
+                Commands::ResetPassword(cmd)
+            }
+        }
+
+        #[derive(Clone, Serialize, Deserialize)]
+        pub enum Commands {
+            RegisterAccount(RegisterAccount),
+            ChangePassword(ChangePassword),
+            RequestPasswordReset(RequestPasswordReset),
+            ResetPassword(ResetPassword),
+        }
+    }
+}
+
+pub mod categories {
+    pub mod v1 {
+        use rust_decimal::Decimal;
+        use serde_derive::{Deserialize, Serialize};
+        use uuid::Uuid;
+
+        #[derive(Clone, Serialize, Deserialize)]
+        pub enum AttributeType {
+            Number {
+                min: Option<Decimal>,
+                max: Option<Decimal>,
+                unit: Option<String>,
+            },
+            Text {
+                max_length: usize,
+            },
+            Choice {
+                options: Vec<String>,
+            },
+            Boolean,
+        }
+        #[derive(Clone, Serialize, Deserialize)]
+        pub struct AttributeDefinition {
+            pub name: String,
+            pub kind: AttributeType,
+            pub required: bool,
+        }
+
+        #[derive(Clone, Serialize, Deserialize)]
+        pub struct CreateCategory {
+            pub id: Uuid,
+            pub parent_id: Option<Uuid>,
+            pub name: String,
+            pub attributes: Vec<AttributeDefinition>,
+        }
+        impl From<CreateCategory> for Commands {
+            fn from(cmd: CreateCategory) -> Self {
+                Commands::CreateCategory(cmd)
+            }
+        }
+        #[derive(Clone, Serialize, Deserialize)]
+        pub struct SetCategoryAttributes {
+            pub id: Uuid,
+            pub attributes: Vec<AttributeDefinition>,
+        }
+        impl From<SetCategoryAttributes> for Commands {
+            fn from(cmd: SetCategoryAttributes) -> Self {
+                Commands::SetCategoryAttributes(cmd)
+            }
+        }
+        #[derive(Clone, Serialize, Deserialize)]
+        pub struct RemoveCategory {
+            pub id: Uuid,
+        }
+        impl From<RemoveCategory> for Commands {
+            fn from(cmd: RemoveCategory) -> Self {
+                Commands::RemoveCategory(cmd)
+            }
+        }
+
+        #[derive(Clone, Serialize, Deserialize)]
+        pub enum Commands {
+            CreateCategory(CreateCategory),
+            SetCategoryAttributes(SetCategoryAttributes),
+            RemoveCategory(RemoveCategory),
         }
     }
 }
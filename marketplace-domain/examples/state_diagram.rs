@@ -0,0 +1,11 @@
+//! Prints the classified ad state diagram generated from the transition table.
+//!
+//! `cargo run -p marketplace-domain --example state_diagram -- [mermaid|dot]`
+use marketplace_domain::classified_ad_state::{to_dot, to_mermaid};
+
+fn main() {
+    match std::env::args().nth(1).as_deref() {
+        Some("dot") => print!("{}", to_dot()),
+        _ => print!("{}", to_mermaid()),
+    }
+}
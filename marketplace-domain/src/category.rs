@@ -0,0 +1,225 @@
+use std::{collections::BTreeMap, fmt};
+
+use anyhow::{anyhow, Result};
+use rust_decimal::Decimal;
+use uuid::Uuid;
+
+// ================================================================================
+// Value Objects
+// ================================================================================
+
+#[derive(Debug, Clone, Copy, Hash, PartialEq, Eq)]
+pub struct CategoryId {
+    _value: Uuid,
+}
+
+impl CategoryId {
+    pub fn new(value: Uuid) -> Self {
+        Self { _value: value }
+    }
+
+    pub fn value(&self) -> Uuid {
+        self._value
+    }
+}
+
+/// The kind of value an attribute takes, with its limits.
+#[derive(Debug, Clone, PartialEq)]
+pub enum AttributeType {
+    Number {
+        min: Option<Decimal>,
+        max: Option<Decimal>,
+        /// Shown next to the value, e.g. "km"
+        unit: Option<String>,
+    },
+    Text {
+        max_length: usize,
+    },
+    /// Text that has to be one of the options.
+    Choice {
+        options: Vec<String>,
+    },
+    Boolean,
+}
+
+/// An attribute ads in a category can have, such as the mileage of a car.
+#[derive(Debug, Clone, PartialEq)]
+pub struct AttributeDefinition {
+    pub name: String,
+    pub kind: AttributeType,
+    pub required: bool,
+}
+
+impl AttributeDefinition {
+    pub fn new(name: &str, kind: AttributeType, required: bool) -> Result<Self> {
+        let name = name.trim();
+        let valid = !name.is_empty()
+            && name
+                .chars()
+                .all(|c| c.is_ascii_lowercase() || c.is_ascii_digit() || c == '_');
+        if !valid {
+            return Err(anyhow!(
+                "Attribute name {:?} must be lowercase letters, digits and underscores",
+                name
+            ));
+        }
+        match &kind {
+            AttributeType::Number {
+                min: Some(min),
+                max: Some(max),
+                ..
+            } if min > max => {
+                return Err(anyhow!(
+                    "Attribute {} has a minimum above its maximum",
+                    name
+                ))
+            }
+            AttributeType::Text { max_length: 0 } => {
+                return Err(anyhow!("Attribute {} must allow some text", name))
+            }
+            AttributeType::Choice { options } if options.is_empty() => {
+                return Err(anyhow!("Attribute {} needs at least one option", name))
+            }
+            _ => {}
+        }
+        Ok(Self {
+            name: name.to_string(),
+            kind,
+            required,
+        })
+    }
+
+    fn check(&self, value: &AttributeValue) -> Result<()> {
+        match (&self.kind, value) {
+            (AttributeType::Number { min, max, .. }, AttributeValue::Number(number)) => {
+                if min.is_some_and(|min| *number < min) || max.is_some_and(|max| *number > max) {
+                    return Err(anyhow!(
+                        "{} must be between {} and {}",
+                        self.name,
+                        min.map_or(String::from("-∞"), |min| min.to_string()),
+                        max.map_or(String::from("∞"), |max| max.to_string())
+                    ));
+                }
+            }
+            (AttributeType::Text { max_length }, AttributeValue::Text(text)) => {
+                if text.chars().count() > *max_length {
+                    return Err(anyhow!(
+                        "{} cannot be longer than {} characters",
+                        self.name,
+                        max_length
+                    ));
+                }
+            }
+            (AttributeType::Choice { options }, AttributeValue::Text(text)) => {
+                if !options.contains(text) {
+                    return Err(anyhow!(
+                        "{} must be one of {}",
+                        self.name,
+                        options.join(", ")
+                    ));
+                }
+            }
+            (AttributeType::Boolean, AttributeValue::Boolean(_)) => {}
+            (kind, _) => {
+                return Err(anyhow!("{} must be a {}", self.name, kind.type_name()));
+            }
+        }
+        Ok(())
+    }
+}
+
+impl AttributeType {
+    pub fn type_name(&self) -> &'static str {
+        match self {
+            AttributeType::Number { .. } => "number",
+            AttributeType::Text { .. } => "text",
+            AttributeType::Choice { .. } => "choice",
+            AttributeType::Boolean => "boolean",
+        }
+    }
+}
+
+/// Every attribute an ad in a category can have, including those of the
+/// categories above it.
+#[derive(Debug, Clone)]
+pub struct CategorySchema {
+    _category_id: CategoryId,
+    _attributes: Vec<AttributeDefinition>,
+}
+
+impl CategorySchema {
+    pub fn new(category_id: CategoryId, attributes: Vec<AttributeDefinition>) -> Result<Self> {
+        for (i, attribute) in attributes.iter().enumerate() {
+            if attributes[..i].iter().any(|a| a.name == attribute.name) {
+                return Err(anyhow!("Attribute {} is defined twice", attribute.name));
+            }
+        }
+        Ok(Self {
+            _category_id: category_id,
+            _attributes: attributes,
+        })
+    }
+
+    pub fn category_id(&self) -> CategoryId {
+        self._category_id
+    }
+
+    pub fn attributes(&self) -> &[AttributeDefinition] {
+        &self._attributes
+    }
+
+    pub fn attribute(&self, name: &str) -> Option<&AttributeDefinition> {
+        self._attributes.iter().find(|a| a.name == name)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum AttributeValue {
+    Number(Decimal),
+    Text(String),
+    Boolean(bool),
+}
+
+impl fmt::Display for AttributeValue {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            AttributeValue::Number(number) => write!(f, "{}", number),
+            AttributeValue::Text(text) => f.write_str(text),
+            AttributeValue::Boolean(value) => write!(f, "{}", value),
+        }
+    }
+}
+
+/// The attribute values of an ad, checked against the schema of its category.
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct AdAttributes {
+    _values: BTreeMap<String, AttributeValue>,
+}
+
+impl AdAttributes {
+    pub fn new(values: BTreeMap<String, AttributeValue>, schema: &CategorySchema) -> Result<Self> {
+        for (name, value) in &values {
+            let definition = schema
+                .attribute(name)
+                .ok_or_else(|| anyhow!("The category has no attribute {}", name))?;
+            definition.check(value)?;
+        }
+        if let Some(missing) = schema
+            .attributes()
+            .iter()
+            .find(|a| a.required && !values.contains_key(&a.name))
+        {
+            return Err(anyhow!("{} is required in this category", missing.name));
+        }
+        Ok(Self { _values: values })
+    }
+
+    /// Restores attributes that were already checked when their event was raised.
+    pub(crate) fn from_event(values: BTreeMap<String, AttributeValue>) -> Self {
+        Self { _values: values }
+    }
+
+    pub fn values(&self) -> &BTreeMap<String, AttributeValue> {
+        &self._values
+    }
+}
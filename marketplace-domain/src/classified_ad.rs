@@ -1,9 +1,15 @@
+use std::{str::FromStr, sync::Arc};
+
 use anyhow::{anyhow, Result};
 use marketplace_framework::AggregateRoot;
 use uuid::Uuid;
 
 use crate::{
-    classified_ad_events::*, CurrencyCode, CurrencyDetails, ICurrencyLookup, Price, UserId,
+    category::{AdAttributes, CategoryId},
+    classified_ad_events::*,
+    location::Location,
+    picture::{Picture, PictureId, PictureSize},
+    CurrencyCode, ICurrencyLookup, ITextModeration, Price, UserId,
 };
 // ================================================================================
 // Value Objects
@@ -30,13 +36,21 @@ pub struct ClassifiedAdTitle {
 }
 
 impl ClassifiedAdTitle {
-    pub fn new(title: String) -> Result<Self> {
+    pub fn new(title: String, moderation: impl ITextModeration) -> Result<Self> {
         if title.len() > 100 {
             return Err(anyhow!("Title cannot be longer than 100 characters"));
         }
+        if moderation.contains_profanity(&title)? {
+            return Err(anyhow!("Title contains offensive language"));
+        }
         Ok(Self { _value: title })
     }
 
+    /// Restores a title that was already checked when its event was raised.
+    fn from_event(title: String) -> Self {
+        Self { _value: title }
+    }
+
     pub fn value(&self) -> String {
         self._value.to_owned()
     }
@@ -47,21 +61,24 @@ pub struct ClassifiedAdText {
 }
 
 impl ClassifiedAdText {
-    pub fn new(text: String) -> Self {
+    pub fn new(text: String, moderation: impl ITextModeration) -> Result<Self> {
+        if moderation.contains_profanity(&text)? {
+            return Err(anyhow!("Text contains offensive language"));
+        }
+        Ok(Self { _value: text })
+    }
+
+    /// Restores a text that was already checked when its event was raised.
+    fn from_event(text: String) -> Self {
         Self { _value: text }
     }
+
     pub fn value(&self) -> String {
         self._value.to_owned()
     }
 }
 
-#[derive(Debug, PartialEq, Clone)]
-pub enum ClassifiedAdState {
-    PendingReview,
-    Active,
-    InActive,
-    MarkedAsSold,
-}
+pub use crate::classified_ad_state::{ClassifiedAdCommand, ClassifiedAdState, IllegalTransition};
 
 // ================================================================================
 // Events
@@ -80,44 +97,142 @@ pub trait ClassifiedAdAggregate:
         let event = ClassifiedAdPriceUpdated {
             id: self.id()?.value(),
             price: price.money.amount,
+            currency_code: price.money.currency_code.to_string(),
         };
 
-        self.apply(event)?;
-
-        Ok(())
+        self.update_content(event.into())
     }
 
     /// Set the classified ad's  text.
-    fn set_text(&mut self, text: String) -> Result<()> {
+    fn set_text(&mut self, text: ClassifiedAdText) -> Result<()> {
         let event = ClassifiedAdTextUpdated {
             id: self.id()?.value(),
-            ad_text: text,
+            ad_text: text.value(),
         };
-        self.apply(event)?;
 
-        Ok(())
+        self.update_content(event.into())
     }
 
     /// Set the classified ad's  title.
-    fn set_title(&mut self, title: String) -> Result<()> {
+    fn set_title(&mut self, title: ClassifiedAdTitle) -> Result<()> {
         let event = ClassifiedAdTitleChanged {
             id: self.id()?.value(),
-            title,
+            title: title.value(),
         };
+
+        self.update_content(event.into())
+    }
+
+    /// Place the ad in a category, with attributes already checked against its schema.
+    fn change_category(&mut self, category_id: CategoryId, attributes: AdAttributes) -> Result<()> {
+        let event = ClassifiedAdCategoryChanged {
+            id: self.id()?.value(),
+            category_id: category_id.value(),
+            attributes: attributes.values().clone(),
+        };
+
+        self.update_content(event.into())
+    }
+
+    /// Set where the item can be picked up.
+    fn set_location(&mut self, location: Location) -> Result<()> {
+        let event = ClassifiedAdLocationSet {
+            id: self.id()?.value(),
+            latitude: location.latitude(),
+            longitude: location.longitude(),
+            postal_area: location.postal_area().to_string(),
+        };
+
+        self.update_content(event.into())
+    }
+
+    /// Apply a change to the title, text or price. Changing a live ad sends it
+    /// back for review.
+    fn update_content(&mut self, event: ClassifiedAdEvents) -> Result<()> {
+        let next = self
+            .state()
+            .transition(ClassifiedAdCommand::UpdateContent)?;
         self.apply(event)?;
+        if self.state() != next {
+            self.apply(ClassifiedAdSentForReview {
+                id: self.id()?.value(),
+            })?;
+        }
 
         Ok(())
     }
 
+    fn add_picture(&mut self, picture_id: PictureId, url: String, size: PictureSize) -> Result<()> {
+        if self.picture(picture_id).is_some() {
+            return Err(anyhow!("Picture {} was already added", picture_id.value()));
+        }
+        let order = self
+            .pictures()
+            .iter()
+            .map(|p| p.order() + 1)
+            .max()
+            .unwrap_or(0);
+        let event = PictureAddedToClassifiedAd {
+            id: self.id()?.value(),
+            picture_id: picture_id.value(),
+            url,
+            width: size.width(),
+            height: size.height(),
+            order,
+        };
+
+        self.update_content(event.into())
+    }
+
+    fn resize_picture(&mut self, picture_id: PictureId, size: PictureSize) -> Result<()> {
+        if self.picture(picture_id).is_none() {
+            return Err(anyhow!("Cannot resize a picture that is not on the ad"));
+        }
+        let event = ClassifiedAdPictureResized {
+            id: self.id()?.value(),
+            picture_id: picture_id.value(),
+            width: size.width(),
+            height: size.height(),
+        };
+
+        self.update_content(event.into())
+    }
+
+    fn remove_picture(&mut self, picture_id: PictureId) -> Result<()> {
+        if self.picture(picture_id).is_none() {
+            return Err(anyhow!("Cannot remove a picture that is not on the ad"));
+        }
+        let event = PictureRemovedFromClassifiedAd {
+            id: self.id()?.value(),
+            picture_id: picture_id.value(),
+        };
+
+        self.update_content(event.into())
+    }
+
+    fn picture(&self, picture_id: PictureId) -> Option<Picture> {
+        self.pictures().into_iter().find(|p| p.id() == picture_id)
+    }
+
     fn title(&self) -> Option<ClassifiedAdTitle>;
     fn text(&self) -> Option<ClassifiedAdText>;
+    fn pictures(&self) -> Vec<Picture>;
     fn price(&self) -> Option<Price>;
+    fn category(&self) -> Option<CategoryId>;
+    fn attributes(&self) -> AdAttributes;
+    fn location(&self) -> Option<Location>;
+    fn owner_id(&self) -> Option<UserId>;
+    fn state(&self) -> ClassifiedAdState;
+    fn was_approved(&self) -> bool;
+    fn changed_since_approval(&self) -> bool;
 
     fn request_to_publish(&mut self) -> Result<()> {
-        if self.title() == None {
+        self.state()
+            .transition(ClassifiedAdCommand::RequestToPublish)?;
+        if self.title().is_none() {
             return Err(anyhow!("Title cannot be empty"));
         }
-        if self.text() == None {
+        if self.text().is_none() {
             return Err(anyhow!("Text cannot be empty"));
         }
         let invalid_price = match self.price() {
@@ -127,6 +242,11 @@ pub trait ClassifiedAdAggregate:
         if invalid_price {
             return Err(anyhow!("Price cannot be 0"));
         }
+        if !self.pictures().iter().any(Picture::has_correct_size) {
+            return Err(anyhow!(
+                "At least one picture of the minimum resolution is required"
+            ));
+        }
 
         let event = ClassifiedAdSentForReview {
             id: self.id()?.value(),
@@ -136,10 +256,77 @@ pub trait ClassifiedAdAggregate:
         Ok(())
     }
 
-    // /// Get a reference to the classified ad's  state.
-    // pub fn state(&self) -> ClassifiedAdState {
-    //     self._state.clone()
-    // }
+    /// Approve an ad that is pending review, making it active.
+    fn approve(&mut self, approved_by: UserId) -> Result<()> {
+        self.state().transition(ClassifiedAdCommand::Approve)?;
+        let event = ClassifiedAdPublished {
+            id: self.id()?.value(),
+            approved_by: approved_by.value(),
+        };
+
+        self.apply(event)?;
+        Ok(())
+    }
+
+    /// Reject an ad that is pending review, sending it back to its owner.
+    fn reject(&mut self, rejected_by: UserId, reason: String) -> Result<()> {
+        self.state().transition(ClassifiedAdCommand::Reject)?;
+        if reason.trim().is_empty() {
+            return Err(anyhow!("A reason must be given when rejecting an ad"));
+        }
+        let event = ClassifiedAdRejected {
+            id: self.id()?.value(),
+            rejected_by: rejected_by.value(),
+            reason,
+        };
+
+        self.apply(event)?;
+        Ok(())
+    }
+
+    fn mark_as_sold(&mut self) -> Result<()> {
+        self.state().transition(ClassifiedAdCommand::MarkAsSold)?;
+        let event = ClassifiedAdMarkedAsSold {
+            id: self.id()?.value(),
+        };
+
+        self.apply(event)?;
+        Ok(())
+    }
+
+    /// Withdraw an ad that is live or waiting for review.
+    fn deactivate(&mut self) -> Result<()> {
+        self.state().transition(ClassifiedAdCommand::Deactivate)?;
+        let event = ClassifiedAdDeactivated {
+            id: self.id()?.value(),
+        };
+
+        self.apply(event)?;
+        Ok(())
+    }
+
+    /// Put a withdrawn ad back on the market. Ads whose content changed since
+    /// they were approved have to go through review again.
+    fn reactivate(&mut self) -> Result<()> {
+        self.state().transition(ClassifiedAdCommand::Reactivate)?;
+        if !self.was_approved() {
+            return Err(IllegalTransition {
+                from: self.state(),
+                command: ClassifiedAdCommand::Reactivate,
+                reason: Some("it was never approved, request to publish it instead"),
+            }
+            .into());
+        }
+        if self.changed_since_approval() {
+            return self.request_to_publish();
+        }
+        let event = ClassifiedAdReactivated {
+            id: self.id()?.value(),
+        };
+
+        self.apply(event)?;
+        Ok(())
+    }
 }
 
 #[derive(Clone)]
@@ -149,13 +336,24 @@ pub struct ClassifiedAd {
     _text: Option<ClassifiedAdText>,
     _title: Option<ClassifiedAdTitle>,
     _price: Option<Price>,
+    _category: Option<CategoryId>,
+    _attributes: AdAttributes,
+    _location: Option<Location>,
+    _pictures: Vec<Picture>,
     _state: ClassifiedAdState,
+    _changed_since_approval: bool,
     _changes: Vec<ClassifiedAdEvents>,
+    _version: i64,
+    _currency_lookup: Arc<dyn ICurrencyLookup + Send + Sync>,
 
     pub uuid: Option<ClassifiedAdId>,
 }
 impl ClassifiedAd {
-    pub fn new(classified_ad_id: ClassifiedAdId, owner_id: UserId) -> Self {
+    pub fn new(
+        classified_ad_id: ClassifiedAdId,
+        owner_id: UserId,
+        currency_lookup: Arc<dyn ICurrencyLookup + Send + Sync>,
+    ) -> Self {
         Self {
             uuid: Some(classified_ad_id),
             _owner_id: Some(owner_id),
@@ -163,16 +361,32 @@ impl ClassifiedAd {
             _text: None,
             _title: None,
             _price: None,
+            _category: None,
+            _attributes: AdAttributes::default(),
+            _location: None,
+            _pictures: vec![],
             _state: ClassifiedAdState::InActive,
+            _changed_since_approval: false,
             _changes: vec![],
+            _version: 0,
+            _currency_lookup: currency_lookup,
         }
     }
+
+    /// Events applied since the ad was created, oldest first.
+    pub fn changes(&self) -> &[ClassifiedAdEvents] {
+        &self._changes
+    }
 }
 
 impl AggregateRoot for ClassifiedAd {
     type Id = ClassifiedAdId;
     type Event = ClassifiedAdEvents;
 
+    fn version(&self) -> i64 {
+        self._version
+    }
+
     fn ensure_valid_state(&self) -> Result<()> {
         let valid = self.uuid.is_some()
             && self._owner_id.is_some()
@@ -182,12 +396,14 @@ impl AggregateRoot for ClassifiedAd {
                         && self._text.is_some()
                         && self._price.is_some()
                         && !self._price.ok_or(anyhow!("No price"))?.is_zero()
+                        && self._pictures.iter().any(Picture::has_correct_size)
                 }
                 ClassifiedAdState::Active => {
                     self._title.is_some()
                         && self._text.is_some()
                         && self._price.is_some()
                         && !self._price.ok_or(anyhow!("No price"))?.is_zero()
+                        && self._pictures.iter().any(Picture::has_correct_size)
                         && self._approved_by.is_some()
                 }
                 _ => true,
@@ -206,21 +422,75 @@ impl AggregateRoot for ClassifiedAd {
                 self._state = ClassifiedAdState::InActive;
             }
             ClassifiedAdEvents::TextUpdated(e) => {
-                self._text = Some(ClassifiedAdText::new(e.ad_text))
+                self._text = Some(ClassifiedAdText::from_event(e.ad_text));
+                self._changed_since_approval = true;
             }
             ClassifiedAdEvents::TitleChanged(e) => {
-                self._title = Some(ClassifiedAdTitle::new(e.title)?)
+                self._title = Some(ClassifiedAdTitle::from_event(e.title));
+                self._changed_since_approval = true;
             }
             ClassifiedAdEvents::PriceUpdated(e) => {
-                self._price = Some(Price::from_decimal(e.price, None, FakeCurrencyLookup)?)
+                let currency_code = CurrencyCode::from_str(&e.currency_code)?;
+                self._price = Some(Price::from_decimal(
+                    e.price,
+                    Some(currency_code),
+                    self._currency_lookup.clone(),
+                )?);
+                self._changed_since_approval = true;
+            }
+            ClassifiedAdEvents::CategoryChanged(e) => {
+                self._category = Some(CategoryId::new(e.category_id));
+                self._attributes = AdAttributes::from_event(e.attributes);
+                self._changed_since_approval = true;
+            }
+            ClassifiedAdEvents::LocationSet(e) => {
+                self._location = Some(Location::from_event(e.latitude, e.longitude, e.postal_area));
+                self._changed_since_approval = true;
             }
             ClassifiedAdEvents::SentForReview(_e) => self._state = ClassifiedAdState::PendingReview,
+            ClassifiedAdEvents::Published(e) => {
+                self._approved_by = Some(UserId::new(e.approved_by));
+                self._changed_since_approval = false;
+                self._state = ClassifiedAdState::Active;
+            }
+            ClassifiedAdEvents::Rejected(_e) => {
+                self._approved_by = None;
+                self._state = ClassifiedAdState::InActive;
+            }
+            ClassifiedAdEvents::MarkedAsSold(_e) => self._state = ClassifiedAdState::MarkedAsSold,
+            ClassifiedAdEvents::Deactivated(_e) => self._state = ClassifiedAdState::InActive,
+            ClassifiedAdEvents::Reactivated(_e) => self._state = ClassifiedAdState::Active,
+            ClassifiedAdEvents::PictureAdded(e) => {
+                self._pictures.push(Picture::new(
+                    PictureId::new(e.picture_id),
+                    PictureSize::new(e.width, e.height)?,
+                    e.url,
+                    e.order,
+                ));
+                self._changed_since_approval = true;
+            }
+            ClassifiedAdEvents::PictureResized(e) => {
+                let size = PictureSize::new(e.width, e.height)?;
+                if let Some(picture) = self
+                    ._pictures
+                    .iter_mut()
+                    .find(|p| p.id().value() == e.picture_id)
+                {
+                    picture.resize(size);
+                }
+                self._changed_since_approval = true;
+            }
+            ClassifiedAdEvents::PictureRemoved(e) => {
+                self._pictures.retain(|p| p.id().value() != e.picture_id);
+                self._changed_since_approval = true;
+            }
         };
         Ok(())
     }
 
     fn store_changes(&mut self, event: Self::Event) -> Result<()> {
         self._changes.push(event);
+        self._version += 1;
         Ok(())
     }
 }
@@ -238,32 +508,39 @@ impl ClassifiedAdAggregate for ClassifiedAd {
         self._text.clone()
     }
 
+    fn pictures(&self) -> Vec<Picture> {
+        self._pictures.clone()
+    }
+
     fn price(&self) -> Option<Price> {
         self._price
     }
-}
 
-pub struct FakeCurrencyLookup;
+    fn category(&self) -> Option<CategoryId> {
+        self._category
+    }
 
-const CURRENCIES: &[CurrencyDetails] = &[CurrencyDetails {
-    currency_code: CurrencyCode::EUR,
-    in_use: true,
-    decimal_places: 2,
-}];
+    fn attributes(&self) -> AdAttributes {
+        self._attributes.clone()
+    }
 
-impl ICurrencyLookup for FakeCurrencyLookup {
-    fn find_currency(&self, currency_code: CurrencyCode) -> Result<CurrencyDetails> {
-        let currency = CURRENCIES
-            .iter()
-            .find(|&c| c.currency_code == currency_code)
-            .cloned();
-
-        match currency {
-            Some(currency_details) => Ok(currency_details),
-            None => Err(anyhow!(
-                "Could not find currency with code {:?}",
-                currency_code
-            )),
-        }
+    fn location(&self) -> Option<Location> {
+        self._location.clone()
+    }
+
+    fn owner_id(&self) -> Option<UserId> {
+        self._owner_id.clone()
+    }
+
+    fn state(&self) -> ClassifiedAdState {
+        self._state
+    }
+
+    fn was_approved(&self) -> bool {
+        self._approved_by.is_some()
+    }
+
+    fn changed_since_approval(&self) -> bool {
+        self._changed_since_approval
     }
 }
@@ -1,12 +1,27 @@
+use std::collections::BTreeMap;
+
+use rust_decimal::Decimal;
 use uuid::Uuid;
 
+use crate::category::AttributeValue;
+
 #[derive(Clone)]
 pub enum ClassifiedAdEvents {
     Created(ClassifiedAdCreated),
     TextUpdated(ClassifiedAdTextUpdated),
     TitleChanged(ClassifiedAdTitleChanged),
     PriceUpdated(ClassifiedAdPriceUpdated),
+    CategoryChanged(ClassifiedAdCategoryChanged),
+    LocationSet(ClassifiedAdLocationSet),
     SentForReview(ClassifiedAdSentForReview),
+    Published(ClassifiedAdPublished),
+    Rejected(ClassifiedAdRejected),
+    MarkedAsSold(ClassifiedAdMarkedAsSold),
+    Deactivated(ClassifiedAdDeactivated),
+    Reactivated(ClassifiedAdReactivated),
+    PictureAdded(PictureAddedToClassifiedAd),
+    PictureResized(ClassifiedAdPictureResized),
+    PictureRemoved(PictureRemovedFromClassifiedAd),
 }
 
 #[derive(Clone)]
@@ -45,13 +60,42 @@ impl From<ClassifiedAdTextUpdated> for ClassifiedAdEvents {
 #[derive(Clone)]
 pub struct ClassifiedAdPriceUpdated {
     pub id: Uuid,
-    pub price: f64,
+    pub price: Decimal,
+    /// ISO 4217 code, e.g. "AUD"
+    pub currency_code: String,
 }
 impl From<ClassifiedAdPriceUpdated> for ClassifiedAdEvents {
     fn from(e: ClassifiedAdPriceUpdated) -> Self {
         ClassifiedAdEvents::PriceUpdated(e)
     }
 }
+/// The ad was placed in a category, with attribute values that fit its schema.
+#[derive(Clone)]
+pub struct ClassifiedAdCategoryChanged {
+    pub id: Uuid,
+    pub category_id: Uuid,
+    pub attributes: BTreeMap<String, AttributeValue>,
+}
+impl From<ClassifiedAdCategoryChanged> for ClassifiedAdEvents {
+    fn from(e: ClassifiedAdCategoryChanged) -> Self {
+        ClassifiedAdEvents::CategoryChanged(e)
+    }
+}
+#[derive(Clone)]
+pub struct ClassifiedAdLocationSet {
+    pub id: Uuid,
+    /// Degrees north of the equator
+    pub latitude: f64,
+    /// Degrees east of Greenwich
+    pub longitude: f64,
+    pub postal_area: String,
+}
+impl From<ClassifiedAdLocationSet> for ClassifiedAdEvents {
+    fn from(e: ClassifiedAdLocationSet) -> Self {
+        ClassifiedAdEvents::LocationSet(e)
+    }
+}
+
 #[derive(Clone)]
 pub struct ClassifiedAdSentForReview {
     pub id: Uuid,
@@ -61,3 +105,95 @@ impl From<ClassifiedAdSentForReview> for ClassifiedAdEvents {
         ClassifiedAdEvents::SentForReview(e)
     }
 }
+
+#[derive(Clone)]
+pub struct ClassifiedAdPublished {
+    pub id: Uuid,
+    pub approved_by: Uuid,
+}
+impl From<ClassifiedAdPublished> for ClassifiedAdEvents {
+    fn from(e: ClassifiedAdPublished) -> Self {
+        ClassifiedAdEvents::Published(e)
+    }
+}
+
+#[derive(Clone)]
+pub struct ClassifiedAdRejected {
+    pub id: Uuid,
+    pub rejected_by: Uuid,
+    pub reason: String,
+}
+impl From<ClassifiedAdRejected> for ClassifiedAdEvents {
+    fn from(e: ClassifiedAdRejected) -> Self {
+        ClassifiedAdEvents::Rejected(e)
+    }
+}
+
+#[derive(Clone)]
+pub struct ClassifiedAdMarkedAsSold {
+    pub id: Uuid,
+}
+impl From<ClassifiedAdMarkedAsSold> for ClassifiedAdEvents {
+    fn from(e: ClassifiedAdMarkedAsSold) -> Self {
+        ClassifiedAdEvents::MarkedAsSold(e)
+    }
+}
+
+#[derive(Clone)]
+pub struct ClassifiedAdDeactivated {
+    pub id: Uuid,
+}
+impl From<ClassifiedAdDeactivated> for ClassifiedAdEvents {
+    fn from(e: ClassifiedAdDeactivated) -> Self {
+        ClassifiedAdEvents::Deactivated(e)
+    }
+}
+
+#[derive(Clone)]
+pub struct ClassifiedAdReactivated {
+    pub id: Uuid,
+}
+impl From<ClassifiedAdReactivated> for ClassifiedAdEvents {
+    fn from(e: ClassifiedAdReactivated) -> Self {
+        ClassifiedAdEvents::Reactivated(e)
+    }
+}
+
+#[derive(Clone)]
+pub struct PictureAddedToClassifiedAd {
+    pub id: Uuid,
+    pub picture_id: Uuid,
+    pub url: String,
+    pub width: u32,
+    pub height: u32,
+    pub order: u32,
+}
+impl From<PictureAddedToClassifiedAd> for ClassifiedAdEvents {
+    fn from(e: PictureAddedToClassifiedAd) -> Self {
+        ClassifiedAdEvents::PictureAdded(e)
+    }
+}
+
+#[derive(Clone)]
+pub struct ClassifiedAdPictureResized {
+    pub id: Uuid,
+    pub picture_id: Uuid,
+    pub width: u32,
+    pub height: u32,
+}
+impl From<ClassifiedAdPictureResized> for ClassifiedAdEvents {
+    fn from(e: ClassifiedAdPictureResized) -> Self {
+        ClassifiedAdEvents::PictureResized(e)
+    }
+}
+
+#[derive(Clone)]
+pub struct PictureRemovedFromClassifiedAd {
+    pub id: Uuid,
+    pub picture_id: Uuid,
+}
+impl From<PictureRemovedFromClassifiedAd> for ClassifiedAdEvents {
+    fn from(e: PictureRemovedFromClassifiedAd) -> Self {
+        ClassifiedAdEvents::PictureRemoved(e)
+    }
+}
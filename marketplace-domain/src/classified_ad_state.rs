@@ -0,0 +1,145 @@
+use std::fmt;
+
+#[derive(Debug, PartialEq, Eq, Clone, Copy, Hash)]
+pub enum ClassifiedAdState {
+    PendingReview,
+    Active,
+    InActive,
+    MarkedAsSold,
+}
+
+/// Behaviours of the classified ad that move it between states.
+#[derive(Debug, PartialEq, Eq, Clone, Copy, Hash)]
+pub enum ClassifiedAdCommand {
+    /// Changing the title, text, price, category, location or pictures
+    UpdateContent,
+    RequestToPublish,
+    Approve,
+    Reject,
+    MarkAsSold,
+    Deactivate,
+    Reactivate,
+}
+
+/// Every allowed `(from, command, to)` transition. Anything not listed is illegal.
+pub const TRANSITIONS: &[(ClassifiedAdState, ClassifiedAdCommand, ClassifiedAdState)] = &[
+    (
+        ClassifiedAdState::InActive,
+        ClassifiedAdCommand::UpdateContent,
+        ClassifiedAdState::InActive,
+    ),
+    (
+        ClassifiedAdState::InActive,
+        ClassifiedAdCommand::RequestToPublish,
+        ClassifiedAdState::PendingReview,
+    ),
+    (
+        ClassifiedAdState::InActive,
+        ClassifiedAdCommand::Reactivate,
+        ClassifiedAdState::Active,
+    ),
+    (
+        ClassifiedAdState::PendingReview,
+        ClassifiedAdCommand::UpdateContent,
+        ClassifiedAdState::PendingReview,
+    ),
+    (
+        ClassifiedAdState::PendingReview,
+        ClassifiedAdCommand::Approve,
+        ClassifiedAdState::Active,
+    ),
+    (
+        ClassifiedAdState::PendingReview,
+        ClassifiedAdCommand::Reject,
+        ClassifiedAdState::InActive,
+    ),
+    (
+        ClassifiedAdState::PendingReview,
+        ClassifiedAdCommand::Deactivate,
+        ClassifiedAdState::InActive,
+    ),
+    (
+        ClassifiedAdState::Active,
+        ClassifiedAdCommand::UpdateContent,
+        ClassifiedAdState::PendingReview,
+    ),
+    (
+        ClassifiedAdState::Active,
+        ClassifiedAdCommand::MarkAsSold,
+        ClassifiedAdState::MarkedAsSold,
+    ),
+    (
+        ClassifiedAdState::Active,
+        ClassifiedAdCommand::Deactivate,
+        ClassifiedAdState::InActive,
+    ),
+];
+
+impl ClassifiedAdState {
+    /// The state the ad ends up in when `command` is handled in this state.
+    pub fn transition(
+        self,
+        command: ClassifiedAdCommand,
+    ) -> Result<ClassifiedAdState, IllegalTransition> {
+        TRANSITIONS
+            .iter()
+            .find(|(from, c, _)| *from == self && *c == command)
+            .map(|(_, _, to)| *to)
+            .ok_or(IllegalTransition {
+                from: self,
+                command,
+                reason: None,
+            })
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct IllegalTransition {
+    pub from: ClassifiedAdState,
+    pub command: ClassifiedAdCommand,
+    /// Why the transition is not allowed, when the table alone does not say.
+    pub reason: Option<&'static str>,
+}
+
+impl fmt::Display for IllegalTransition {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "Cannot {:?} a classified ad in state {:?}",
+            self.command, self.from
+        )?;
+        match self.reason {
+            Some(reason) => write!(f, ": {}", reason),
+            None => Ok(()),
+        }
+    }
+}
+
+impl std::error::Error for IllegalTransition {}
+
+// ================================================================================
+// Diagrams
+// ================================================================================
+
+/// Renders the transition table as a Mermaid state diagram.
+pub fn to_mermaid() -> String {
+    let mut diagram = String::from("stateDiagram-v2\n    [*] --> InActive\n");
+    for (from, command, to) in TRANSITIONS {
+        diagram.push_str(&format!("    {from:?} --> {to:?}: {command:?}\n"));
+    }
+    diagram.push_str("    MarkedAsSold --> [*]\n");
+    diagram
+}
+
+/// Renders the transition table as a Graphviz DOT digraph.
+pub fn to_dot() -> String {
+    let mut diagram = String::from("digraph ClassifiedAdState {\n    start [shape=point];\n");
+    diagram.push_str("    start -> InActive;\n");
+    for (from, command, to) in TRANSITIONS {
+        diagram.push_str(&format!(
+            "    {from:?} -> {to:?} [label=\"{command:?}\"];\n"
+        ));
+    }
+    diagram.push_str("}\n");
+    diagram
+}
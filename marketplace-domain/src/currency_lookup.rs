@@ -0,0 +1,95 @@
+use std::{collections::HashMap, fs, path::Path, str::FromStr};
+
+use anyhow::{anyhow, Context, Result};
+
+use crate::{CurrencyCode, CurrencyDetails, ICurrencyLookup};
+
+/// ISO 4217 list shipped with the crate, as `code,minor_units,in_use` lines.
+const BUNDLED_ISO_4217: &str = include_str!("../data/iso4217.csv");
+
+/// Currency lookup backed by an ISO 4217 table, either the bundled one or a
+/// file in the same format.
+#[derive(Clone)]
+pub struct Iso4217CurrencyLookup {
+    _currencies: HashMap<CurrencyCode, CurrencyDetails>,
+}
+
+impl Iso4217CurrencyLookup {
+    pub fn bundled() -> Self {
+        Self::from_csv(BUNDLED_ISO_4217).expect("Bundled ISO 4217 table is invalid")
+    }
+
+    pub fn from_file(path: impl AsRef<Path>) -> Result<Self> {
+        let path = path.as_ref();
+        let content = fs::read_to_string(path)
+            .with_context(|| format!("Could not read currencies from {}", path.display()))?;
+        Self::from_csv(&content)
+    }
+
+    /// Parses `code,minor_units,in_use` lines. Blank lines, `#` comments and
+    /// the header are skipped. Minor units of `N.A.` are read as no decimal
+    /// places, and the currency is never in use.
+    pub fn from_csv(content: &str) -> Result<Self> {
+        let mut currencies = HashMap::new();
+        for (index, line) in content.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') || line.starts_with("code,") {
+                continue;
+            }
+            let details = parse_line(line).with_context(|| format!("Line {}", index + 1))?;
+            currencies.insert(details.currency_code, details);
+        }
+        Ok(Self {
+            _currencies: currencies,
+        })
+    }
+
+    pub fn currencies(&self) -> impl Iterator<Item = &CurrencyDetails> {
+        self._currencies.values()
+    }
+}
+
+impl Default for Iso4217CurrencyLookup {
+    fn default() -> Self {
+        Self::bundled()
+    }
+}
+
+impl ICurrencyLookup for Iso4217CurrencyLookup {
+    fn find_currency(&self, currency_code: CurrencyCode) -> Result<CurrencyDetails> {
+        self._currencies
+            .get(&currency_code)
+            .cloned()
+            .ok_or_else(|| anyhow!("Could not find currency with code {}", currency_code))
+    }
+}
+
+/// Minor units of codes that have none, such as gold (XAU) or the testing code XTS.
+const NO_MINOR_UNIT: &str = "N.A.";
+
+fn parse_line(line: &str) -> Result<CurrencyDetails> {
+    let fields: Vec<&str> = line.split(',').map(str::trim).collect();
+    match fields.as_slice() {
+        [code, minor_units, in_use] => {
+            let in_use: bool = in_use
+                .parse()
+                .map_err(|_| anyhow!("Invalid in_use flag {}", in_use))?;
+            // Nothing can be priced in a code without a minor unit
+            let (decimal_places, in_use) = match *minor_units {
+                NO_MINOR_UNIT => (0, false),
+                _ => (
+                    minor_units
+                        .parse()
+                        .map_err(|_| anyhow!("Invalid minor units {}", minor_units))?,
+                    in_use,
+                ),
+            };
+            Ok(CurrencyDetails {
+                currency_code: CurrencyCode::from_str(code)?,
+                in_use,
+                decimal_places,
+            })
+        }
+        _ => Err(anyhow!("Expected code,minor_units,in_use but got {}", line)),
+    }
+}
@@ -1,7 +1,13 @@
+pub mod category;
 pub mod classified_ad;
 pub mod classified_ad_events;
+pub mod classified_ad_state;
+pub mod currency_lookup;
+pub mod location;
+pub mod picture;
 pub mod ports;
 pub mod simple_types;
+pub mod user_account;
 pub mod user_profile;
 
 pub use ports::*;
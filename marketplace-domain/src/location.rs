@@ -0,0 +1,84 @@
+use anyhow::{anyhow, Result};
+
+/// Mean radius of the earth, used for distances along its surface.
+pub const EARTH_RADIUS_KM: f64 = 6371.0088;
+
+const POSTAL_AREA_MAX_LENGTH: usize = 100;
+
+// ================================================================================
+// Value Objects
+// ================================================================================
+
+/// Where the item of an ad can be picked up: a point, in degrees, and the postal
+/// area shown to buyers, e.g. "2000 Sydney".
+#[derive(Debug, Clone, PartialEq)]
+pub struct Location {
+    _latitude: f64,
+    _longitude: f64,
+    _postal_area: String,
+}
+
+impl Location {
+    pub fn new(latitude: f64, longitude: f64, postal_area: &str) -> Result<Self> {
+        check_point(latitude, longitude)?;
+        let postal_area = postal_area.trim();
+        if postal_area.is_empty() || postal_area.chars().count() > POSTAL_AREA_MAX_LENGTH {
+            return Err(anyhow!(
+                "Postal area must be between 1 and {} characters",
+                POSTAL_AREA_MAX_LENGTH
+            ));
+        }
+        Ok(Self {
+            _latitude: latitude,
+            _longitude: longitude,
+            _postal_area: postal_area.to_string(),
+        })
+    }
+
+    /// Restores a location that was already checked when its event was raised.
+    pub(crate) fn from_event(latitude: f64, longitude: f64, postal_area: String) -> Self {
+        Self {
+            _latitude: latitude,
+            _longitude: longitude,
+            _postal_area: postal_area,
+        }
+    }
+
+    pub fn latitude(&self) -> f64 {
+        self._latitude
+    }
+
+    pub fn longitude(&self) -> f64 {
+        self._longitude
+    }
+
+    pub fn postal_area(&self) -> &str {
+        &self._postal_area
+    }
+}
+
+/// Checks that a latitude and longitude, in degrees, are a point on the earth.
+pub fn check_point(latitude: f64, longitude: f64) -> Result<()> {
+    if !latitude.is_finite() || !(-90.0..=90.0).contains(&latitude) {
+        return Err(anyhow!("Latitude must be between -90 and 90 degrees"));
+    }
+    if !longitude.is_finite() || !(-180.0..=180.0).contains(&longitude) {
+        return Err(anyhow!("Longitude must be between -180 and 180 degrees"));
+    }
+    Ok(())
+}
+
+/// Great-circle distance in kilometres between two points given in degrees,
+/// by the haversine formula.
+pub fn distance_km(
+    latitude: f64,
+    longitude: f64,
+    other_latitude: f64,
+    other_longitude: f64,
+) -> f64 {
+    let (lat1, lat2) = (latitude.to_radians(), other_latitude.to_radians());
+    let d_lat = lat2 - lat1;
+    let d_lon = (other_longitude - longitude).to_radians();
+    let a = (d_lat / 2.0).sin().powi(2) + lat1.cos() * lat2.cos() * (d_lon / 2.0).sin().powi(2);
+    2.0 * EARTH_RADIUS_KM * a.sqrt().min(1.0).asin()
+}
@@ -0,0 +1,102 @@
+use anyhow::{anyhow, Result};
+use uuid::Uuid;
+
+/// Smallest picture accepted for a published ad.
+pub const MINIMUM_PICTURE_SIZE: PictureSize = PictureSize {
+    _width: 800,
+    _height: 600,
+};
+
+// ================================================================================
+// Value Objects
+// ================================================================================
+#[derive(Clone, Hash, PartialEq, Eq, Copy, Debug)]
+pub struct PictureId {
+    _value: Uuid,
+}
+
+impl PictureId {
+    pub fn new(value: Uuid) -> Self {
+        Self { _value: value }
+    }
+
+    pub fn value(&self) -> Uuid {
+        self._value
+    }
+}
+
+#[derive(Clone, PartialEq, Eq, Copy, Debug)]
+pub struct PictureSize {
+    _width: u32,
+    _height: u32,
+}
+
+impl PictureSize {
+    pub fn new(width: u32, height: u32) -> Result<Self> {
+        if width == 0 || height == 0 {
+            return Err(anyhow!("Picture width and height must be positive"));
+        }
+        Ok(Self {
+            _width: width,
+            _height: height,
+        })
+    }
+
+    pub fn width(&self) -> u32 {
+        self._width
+    }
+
+    pub fn height(&self) -> u32 {
+        self._height
+    }
+
+    pub fn covers(&self, other: PictureSize) -> bool {
+        self._width >= other._width && self._height >= other._height
+    }
+}
+
+// ================================================================================
+// Entity
+// ================================================================================
+#[derive(Clone, PartialEq, Debug)]
+pub struct Picture {
+    _id: PictureId,
+    _size: PictureSize,
+    _location: String,
+    _order: u32,
+}
+
+impl Picture {
+    pub fn new(id: PictureId, size: PictureSize, location: String, order: u32) -> Self {
+        Self {
+            _id: id,
+            _size: size,
+            _location: location,
+            _order: order,
+        }
+    }
+
+    pub fn id(&self) -> PictureId {
+        self._id
+    }
+
+    pub fn size(&self) -> PictureSize {
+        self._size
+    }
+
+    pub fn location(&self) -> String {
+        self._location.to_owned()
+    }
+
+    pub fn order(&self) -> u32 {
+        self._order
+    }
+
+    pub fn resize(&mut self, size: PictureSize) {
+        self._size = size;
+    }
+
+    pub fn has_correct_size(&self) -> bool {
+        self._size.covers(MINIMUM_PICTURE_SIZE)
+    }
+}
@@ -1,6 +1,107 @@
-use crate::simple_types::*;
+use crate::{
+    category::{CategoryId, CategorySchema},
+    simple_types::*,
+};
+
+use std::sync::Arc;
 
 use anyhow::Result;
 pub trait ICurrencyLookup {
     fn find_currency(&self, currency_code: CurrencyCode) -> Result<CurrencyDetails>;
 }
+
+impl<T: ICurrencyLookup + ?Sized> ICurrencyLookup for &T {
+    fn find_currency(&self, currency_code: CurrencyCode) -> Result<CurrencyDetails> {
+        (**self).find_currency(currency_code)
+    }
+}
+
+impl<T: ICurrencyLookup + ?Sized> ICurrencyLookup for Arc<T> {
+    fn find_currency(&self, currency_code: CurrencyCode) -> Result<CurrencyDetails> {
+        (**self).find_currency(currency_code)
+    }
+}
+
+/// Source of the categories ads are placed in.
+pub trait ICategoryLookup {
+    /// The attributes ads in a category can have. Fails for unknown categories.
+    fn category_schema(&self, category_id: CategoryId) -> Result<CategorySchema>;
+}
+
+impl<T: ICategoryLookup + ?Sized> ICategoryLookup for &T {
+    fn category_schema(&self, category_id: CategoryId) -> Result<CategorySchema> {
+        (**self).category_schema(category_id)
+    }
+}
+
+impl<T: ICategoryLookup + ?Sized> ICategoryLookup for Arc<T> {
+    fn category_schema(&self, category_id: CategoryId) -> Result<CategorySchema> {
+        (**self).category_schema(category_id)
+    }
+}
+
+/// Source of exchange rates between currencies.
+pub trait IExchangeRates {
+    /// How many units of `to` one unit of `from` buys.
+    fn get_rate(&self, from: CurrencyCode, to: CurrencyCode) -> Result<ExchangeRate>;
+}
+
+impl<T: IExchangeRates + ?Sized> IExchangeRates for &T {
+    fn get_rate(&self, from: CurrencyCode, to: CurrencyCode) -> Result<ExchangeRate> {
+        (**self).get_rate(from, to)
+    }
+}
+
+impl<T: IExchangeRates + ?Sized> IExchangeRates for Arc<T> {
+    fn get_rate(&self, from: CurrencyCode, to: CurrencyCode) -> Result<ExchangeRate> {
+        (**self).get_rate(from, to)
+    }
+}
+
+/// Checks free text entered by users, such as display names and ad titles,
+/// for offensive language.
+pub trait ITextModeration {
+    fn contains_profanity(&self, text: &str) -> Result<bool>;
+}
+
+/// Any `Fn(&str) -> Result<bool>` can be used as a check, like the
+/// `CheckTextForProfanity` delegate in the book.
+impl<F> ITextModeration for F
+where
+    F: Fn(&str) -> Result<bool>,
+{
+    fn contains_profanity(&self, text: &str) -> Result<bool> {
+        self(text)
+    }
+}
+
+impl<T: ITextModeration + ?Sized> ITextModeration for Arc<T> {
+    fn contains_profanity(&self, text: &str) -> Result<bool> {
+        (**self).contains_profanity(text)
+    }
+}
+
+/// One-way password hashing, e.g. Argon2id.
+pub trait IPasswordHasher {
+    /// Hashes a password into a self-describing string, salt and parameters included.
+    fn hash(&self, password: &str) -> Result<String>;
+    fn verify(&self, password: &str, hash: &str) -> Result<bool>;
+}
+
+impl<T: IPasswordHasher + ?Sized> IPasswordHasher for &T {
+    fn hash(&self, password: &str) -> Result<String> {
+        (**self).hash(password)
+    }
+    fn verify(&self, password: &str, hash: &str) -> Result<bool> {
+        (**self).verify(password, hash)
+    }
+}
+
+impl<T: IPasswordHasher + ?Sized> IPasswordHasher for Arc<T> {
+    fn hash(&self, password: &str) -> Result<String> {
+        (**self).hash(password)
+    }
+    fn verify(&self, password: &str, hash: &str) -> Result<bool> {
+        (**self).verify(password, hash)
+    }
+}
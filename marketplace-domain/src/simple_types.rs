@@ -1,7 +1,12 @@
 use crate::ports::*;
 use anyhow::{anyhow, Result};
-use math::round;
-use std::ops::{Add, Sub};
+use rust_decimal::{Decimal, RoundingStrategy};
+use std::{
+    fmt,
+    ops::{Add, Sub},
+    str::FromStr,
+    time::SystemTime,
+};
 use uuid::Uuid;
 
 #[derive(Clone)]
@@ -20,51 +25,238 @@ impl UserId {
     }
 }
 
-#[derive(PartialEq, Debug, Clone, Copy)]
-pub enum CurrencyCode {
-    EUR,
-    AUD,
+/// Three letter ISO 4217 currency code, e.g. "EUR".
+/// Parsing only checks the format; whether the currency exists is up to an `ICurrencyLookup`.
+#[derive(PartialEq, Eq, Hash, Clone, Copy)]
+pub struct CurrencyCode {
+    _value: [u8; 3],
 }
+
+impl CurrencyCode {
+    pub const EUR: CurrencyCode = CurrencyCode { _value: *b"EUR" };
+    pub const AUD: CurrencyCode = CurrencyCode { _value: *b"AUD" };
+
+    pub fn as_str(&self) -> &str {
+        // Only ever built from ASCII letters
+        std::str::from_utf8(&self._value).unwrap_or_default()
+    }
+}
+
+impl FromStr for CurrencyCode {
+    type Err = anyhow::Error;
+
+    fn from_str(code: &str) -> Result<Self> {
+        let upper = code.trim().to_ascii_uppercase();
+        let value: [u8; 3] = upper
+            .as_bytes()
+            .try_into()
+            .map_err(|_| anyhow!("Currency code {} must have three letters", code))?;
+        if !value.iter().all(u8::is_ascii_uppercase) {
+            return Err(anyhow!("Currency code {} must only contain letters", code));
+        }
+        Ok(CurrencyCode { _value: value })
+    }
+}
+
+impl fmt::Display for CurrencyCode {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+impl fmt::Debug for CurrencyCode {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
 const DEFAULT_CURRENCY_CODE: CurrencyCode = CurrencyCode::EUR;
 
-#[derive(PartialEq, Debug, Clone, Copy)]
+/// An exact amount of money in a currency. The amount is always kept at the
+/// currency's number of decimal places, so "12.5 EUR" is stored as 12.50.
+#[derive(PartialEq, Eq, Debug, Clone, Copy)]
 pub struct Money {
-    pub amount: f64,
+    pub amount: Decimal,
     pub currency_code: CurrencyCode,
+    pub decimal_places: u32,
 }
 
 impl Money {
-    pub fn new(amount: f64, currency: CurrencyCode) -> Self {
-        Self {
-            amount,
-            currency_code: currency,
+    pub fn new(amount: Decimal, currency: &CurrencyDetails) -> Result<Self> {
+        let decimal_places = u32::try_from(currency.decimal_places).map_err(|_| {
+            anyhow!(
+                "Currency {} has no valid decimal places",
+                currency.currency_code
+            )
+        })?;
+        if amount.normalize().scale() > decimal_places {
+            return Err(anyhow!(
+                "Amount in {:?} cannot have more than {} decimals",
+                currency.currency_code,
+                decimal_places
+            ));
         }
+        Ok(Self {
+            amount: at_scale(amount, decimal_places)?,
+            currency_code: currency.currency_code,
+            decimal_places,
+        })
     }
+
     pub fn from_decimal(
-        amount: f64,
+        amount: Decimal,
         currency_code: Option<CurrencyCode>,
         currency_lookup: impl ICurrencyLookup,
     ) -> Result<Self> {
-        let currency_code = match currency_code {
-            Some(code) => {
-                let currency = currency_lookup.find_currency(code)?;
-                if !currency.in_use {
-                    return Err(anyhow!("Currency code {:?} is not valid", code));
-                }
-                let rounded = round::half_towards_zero(amount, currency.decimal_places);
-                if rounded != amount {
-                    return Err(anyhow!(
-                        "Amount in {:?} cannot have more than {} decimals",
-                        currency.currency_code,
-                        currency.decimal_places
-                    ));
-                }
-                currency.currency_code
+        let code = currency_code.unwrap_or(DEFAULT_CURRENCY_CODE);
+        let currency = currency_lookup.find_currency(code)?;
+        if !currency.in_use {
+            return Err(anyhow!("Currency code {:?} is not valid", code));
+        }
+        Money::new(amount, &currency)
+    }
+
+    fn with_amount(&self, amount: Decimal) -> Result<Money> {
+        Ok(Money {
+            amount: at_scale(amount, self.decimal_places)?,
+            ..*self
+        })
+    }
+
+    fn ensure_same_currency(&self, other: &Money) -> Result<()> {
+        if self.currency_code != other.currency_code {
+            return Err(anyhow!("Not same currency code"));
+        }
+        Ok(())
+    }
+
+    pub fn checked_add(&self, other: &Money) -> Result<Money> {
+        self.ensure_same_currency(other)?;
+        let amount = self
+            .amount
+            .checked_add(other.amount)
+            .ok_or_else(|| anyhow!("Money amount overflowed"))?;
+        self.with_amount(amount)
+    }
+
+    pub fn checked_sub(&self, other: &Money) -> Result<Money> {
+        self.ensure_same_currency(other)?;
+        let amount = self
+            .amount
+            .checked_sub(other.amount)
+            .ok_or_else(|| anyhow!("Money amount overflowed"))?;
+        self.with_amount(amount)
+    }
+
+    /// The total for `quantity` items at this amount each.
+    pub fn multiply(&self, quantity: u32) -> Result<Money> {
+        let amount = self
+            .amount
+            .checked_mul(Decimal::from(quantity))
+            .ok_or_else(|| anyhow!("Money amount overflowed"))?;
+        self.with_amount(amount)
+    }
+
+    /// Splits the amount according to `ratios` without losing any minor units.
+    /// Leftover minor units go to the first shares, one each.
+    pub fn allocate(&self, ratios: &[u32]) -> Result<Vec<Money>> {
+        let total_ratio: i128 = ratios.iter().map(|r| i128::from(*r)).sum();
+        if total_ratio == 0 {
+            return Err(anyhow!("Cannot allocate money over no ratios"));
+        }
+        // At a scale of `decimal_places` the mantissa is the amount in minor units
+        let total_minor = self.amount.mantissa();
+        let mut shares: Vec<i128> = ratios
+            .iter()
+            .map(|r| {
+                total_minor
+                    .checked_mul(i128::from(*r))
+                    .map(|product| product / total_ratio)
+                    .ok_or_else(|| anyhow!("Money amount overflowed"))
+            })
+            .collect::<Result<_>>()?;
+        let mut remainder = total_minor - shares.iter().sum::<i128>();
+        let step = remainder.signum();
+        for share in shares.iter_mut() {
+            if remainder == 0 {
+                break;
             }
-            _ => DEFAULT_CURRENCY_CODE,
+            *share += step;
+            remainder -= step;
+        }
+        shares
+            .into_iter()
+            .map(|minor| {
+                Decimal::try_from_i128_with_scale(minor, self.decimal_places)
+                    .map_err(|e| anyhow!("Cannot allocate money: {}", e))
+                    .and_then(|amount| self.with_amount(amount))
+            })
+            .collect()
+    }
+
+    /// Converts into another currency, rounding half away from zero to the
+    /// target currency's decimal places. Returns the rate that was used.
+    pub fn convert_to(
+        &self,
+        currency: &CurrencyDetails,
+        exchange_rates: impl IExchangeRates,
+    ) -> Result<(Money, ExchangeRate)> {
+        let rate = if currency.currency_code == self.currency_code {
+            ExchangeRate::identity(self.currency_code)
+        } else {
+            exchange_rates.get_rate(self.currency_code, currency.currency_code)?
         };
+        if rate.from != self.currency_code || rate.to != currency.currency_code {
+            return Err(anyhow!(
+                "Got a {} to {} rate when converting {} to {}",
+                rate.from,
+                rate.to,
+                self.currency_code,
+                currency.currency_code
+            ));
+        }
+        let decimal_places = u32::try_from(currency.decimal_places).map_err(|_| {
+            anyhow!(
+                "Currency {} has no valid decimal places",
+                currency.currency_code
+            )
+        })?;
+        let amount = self
+            .amount
+            .checked_mul(rate.rate)
+            .ok_or_else(|| anyhow!("Money amount overflowed"))?
+            .round_dp_with_strategy(decimal_places, RoundingStrategy::MidpointAwayFromZero);
+        Ok((Money::new(amount, currency)?, rate))
+    }
 
-        Ok(Money::new(amount, currency_code))
+    /// Splits the amount into `parts` equal shares, as evenly as the minor unit allows.
+    pub fn split(&self, parts: usize) -> Result<Vec<Money>> {
+        self.allocate(&vec![1; parts])
+    }
+}
+
+/// Rescales `amount` to exactly `decimal_places`. Amounts too large to have that
+/// many decimals are an error, rather than being kept at fewer.
+fn at_scale(amount: Decimal, decimal_places: u32) -> Result<Decimal> {
+    let mut amount = amount;
+    amount.rescale(decimal_places);
+    if amount.scale() != decimal_places {
+        return Err(anyhow!(
+            "Amount {} is too large to keep {} decimals",
+            amount,
+            decimal_places
+        ));
+    }
+    Ok(amount)
+}
+
+impl fmt::Display for Money {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{:.*} {}",
+            self.decimal_places as usize, self.amount, self.currency_code
+        )
     }
 }
 
@@ -72,20 +264,14 @@ impl Add for Money {
     type Output = Result<Money>;
 
     fn add(self, rhs: Self) -> Self::Output {
-        if self.currency_code != rhs.currency_code {
-            return Err(anyhow!("Not same currency code"));
-        }
-        Ok(Money::new(self.amount + rhs.amount, self.currency_code))
+        self.checked_add(&rhs)
     }
 }
 impl Sub for Money {
     type Output = Result<Money>;
 
     fn sub(self, rhs: Self) -> Self::Output {
-        if self.currency_code != rhs.currency_code {
-            return Err(anyhow!("Not same currency code"));
-        }
-        Ok(Money::new(self.amount - rhs.amount, self.currency_code))
+        self.checked_sub(&rhs)
     }
 }
 
@@ -93,14 +279,34 @@ impl Add<Result<Money>> for Money {
     type Output = Result<Money>;
 
     fn add(self, rhs: Result<Money>) -> Self::Output {
-        Ok(Money::new(self.amount + (rhs?.amount), self.currency_code))
+        self.checked_add(&rhs?)
     }
 }
 impl Add<Money> for Result<Money> {
     type Output = Result<Money>;
 
     fn add(self, rhs: Money) -> Self::Output {
-        Ok(Money::new(self?.amount + rhs.amount, rhs.currency_code))
+        self?.checked_add(&rhs)
+    }
+}
+
+/// Rate at which one unit of `from` converts into `to`, and when it was quoted.
+#[derive(PartialEq, Eq, Debug, Clone, Copy)]
+pub struct ExchangeRate {
+    pub from: CurrencyCode,
+    pub to: CurrencyCode,
+    pub rate: Decimal,
+    pub quoted_at: SystemTime,
+}
+
+impl ExchangeRate {
+    pub fn identity(currency_code: CurrencyCode) -> Self {
+        Self {
+            from: currency_code,
+            to: currency_code,
+            rate: Decimal::ONE,
+            quoted_at: SystemTime::now(),
+        }
     }
 }
 
@@ -111,14 +317,14 @@ pub struct Price {
 
 impl Price {
     pub fn is_zero(&self) -> bool {
-        self.money.amount == 0.
+        self.money.amount.is_zero()
     }
     pub fn from_decimal(
-        amount: f64,
+        amount: Decimal,
         currency: Option<CurrencyCode>,
         lookup: impl ICurrencyLookup,
     ) -> Result<Self> {
-        if amount < 0. {
+        if amount.is_sign_negative() && !amount.is_zero() {
             return Err(anyhow!("Price cannot be negative"));
         }
         Ok(Self {
@@ -133,3 +339,131 @@ pub struct CurrencyDetails {
     pub in_use: bool,
     pub decimal_places: i8,
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use proptest::prelude::*;
+
+    fn currency(code: &str, decimal_places: i8) -> CurrencyDetails {
+        CurrencyDetails {
+            currency_code: code.parse().unwrap(),
+            in_use: true,
+            decimal_places,
+        }
+    }
+
+    fn money(minor_units: i64, currency: &CurrencyDetails) -> Money {
+        let amount = Decimal::new(minor_units, currency.decimal_places as u32);
+        Money::new(amount, currency).unwrap()
+    }
+
+    fn minor_units(money: &Money) -> i128 {
+        money.amount.mantissa()
+    }
+
+    proptest! {
+        #[test]
+        fn allocate_keeps_the_total_and_each_share_within_a_minor_unit(
+            total in any::<i64>(),
+            ratios in prop::collection::vec(0u32..1000, 1..10),
+        ) {
+            prop_assume!(ratios.iter().any(|r| *r > 0));
+            let eur = currency("EUR", 2);
+            let shares = money(total, &eur).allocate(&ratios).unwrap();
+            prop_assert_eq!(shares.len(), ratios.len());
+            prop_assert_eq!(shares.iter().map(minor_units).sum::<i128>(), i128::from(total));
+            let total_ratio: i128 = ratios.iter().map(|r| i128::from(*r)).sum();
+            for (share, ratio) in shares.iter().zip(&ratios) {
+                prop_assert_eq!(share.currency_code, CurrencyCode::EUR);
+                let exact = i128::from(total) * i128::from(*ratio);
+                prop_assert!((minor_units(share) * total_ratio - exact).abs() <= total_ratio);
+            }
+        }
+
+        #[test]
+        fn split_keeps_the_total_and_shares_differ_by_at_most_a_minor_unit(
+            total in any::<i64>(),
+            parts in 1usize..20,
+        ) {
+            let eur = currency("EUR", 2);
+            let shares = money(total, &eur).split(parts).unwrap();
+            prop_assert_eq!(shares.len(), parts);
+            prop_assert_eq!(shares.iter().map(minor_units).sum::<i128>(), i128::from(total));
+            let largest = shares.iter().map(minor_units).max().unwrap();
+            let smallest = shares.iter().map(minor_units).min().unwrap();
+            prop_assert!(largest - smallest <= 1);
+        }
+
+        #[test]
+        fn adding_then_subtracting_gives_the_amount_back(
+            a in any::<i64>(),
+            b in any::<i64>(),
+            decimal_places in 0i8..4,
+        ) {
+            let currency = currency("AUD", decimal_places);
+            let (a, b) = (money(a, &currency), money(b, &currency));
+            prop_assert_eq!(a.checked_add(&b).unwrap().checked_sub(&b).unwrap(), a);
+            prop_assert_eq!(a.checked_sub(&b).unwrap().checked_add(&b).unwrap(), a);
+        }
+
+        #[test]
+        fn overflowing_is_an_error(minor in 1i64..=i64::MAX) {
+            let jpy = currency("JPY", 0);
+            let largest = Money::new(Decimal::MAX, &jpy).unwrap();
+            let smallest = Money::new(Decimal::MIN, &jpy).unwrap();
+            let amount = money(minor, &jpy);
+            prop_assert!(largest.checked_add(&amount).is_err());
+            prop_assert!(smallest.checked_sub(&amount).is_err());
+            prop_assert!(largest.multiply(2).is_err());
+        }
+
+        #[test]
+        fn amounts_near_the_largest_keep_the_decimal_places_or_are_rejected(
+            below_max in 0u64..=u64::MAX,
+            scale in 0u32..4,
+            decimal_places in 0i8..4,
+        ) {
+            let mantissa = Decimal::MAX.mantissa() - i128::from(below_max);
+            let amount = Decimal::from_i128_with_scale(mantissa, scale);
+            let currency = currency("AUD", decimal_places);
+            match Money::new(amount, &currency) {
+                Ok(money) => {
+                    prop_assert_eq!(money.amount.scale(), decimal_places as u32);
+                    prop_assert_eq!(money.amount, amount);
+                }
+                Err(_) => {
+                    let decimal_places = decimal_places as u32;
+                    prop_assert!(scale < decimal_places || amount.normalize().scale() > decimal_places);
+                }
+            }
+        }
+
+        #[test]
+        fn allocating_amounts_near_the_largest_keeps_the_total_or_is_an_error(
+            below_max in 0u64..=u64::MAX,
+            ratios in prop::collection::vec(any::<u32>(), 1..10),
+        ) {
+            prop_assume!(ratios.iter().any(|r| *r > 0));
+            let jpy = currency("JPY", 0);
+            let total = Decimal::MAX.mantissa() - i128::from(below_max);
+            let largest = Money::new(Decimal::from_i128_with_scale(total, 0), &jpy).unwrap();
+            match largest.allocate(&ratios) {
+                Ok(shares) => {
+                    prop_assert_eq!(shares.iter().map(minor_units).sum::<i128>(), total);
+                }
+                Err(_) => prop_assert!(ratios.iter().any(|r| total.checked_mul(i128::from(*r)).is_none())),
+            }
+        }
+
+        #[test]
+        fn mixing_currencies_is_an_error(a in any::<i64>(), b in any::<i64>()) {
+            let eur = money(a, &currency("EUR", 2));
+            let aud = money(b, &currency("AUD", 2));
+            prop_assert!(eur.checked_add(&aud).is_err());
+            prop_assert!(eur.checked_sub(&aud).is_err());
+            prop_assert!((eur + aud).is_err());
+            prop_assert!((aud - eur).is_err());
+        }
+    }
+}
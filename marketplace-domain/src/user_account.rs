@@ -0,0 +1,324 @@
+use std::{fmt, time::SystemTime};
+
+use crate::{IPasswordHasher, UserId};
+use anyhow::{anyhow, Result};
+use marketplace_framework::AggregateRoot;
+
+// ================================================================================
+// Value Objects
+// ================================================================================
+
+const EMAIL_MAX_LENGTH: usize = 254;
+const PASSWORD_MIN_LENGTH: usize = 8;
+const PASSWORD_MAX_LENGTH: usize = 128;
+
+/// An email address, trimmed and lowercased so it can be compared for uniqueness.
+#[derive(Debug, PartialEq, Eq, Hash, Clone)]
+pub struct EmailAddress {
+    _value: String,
+}
+
+impl EmailAddress {
+    pub fn new(value: &str) -> Result<Self> {
+        let value = value.trim().to_lowercase();
+        if value.len() > EMAIL_MAX_LENGTH {
+            return Err(anyhow!(
+                "Email address cannot be longer than {} characters",
+                EMAIL_MAX_LENGTH
+            ));
+        }
+        let valid = match value.split_once('@') {
+            Some((local, domain)) => {
+                !local.is_empty()
+                    && !domain.contains('@')
+                    && domain.contains('.')
+                    && !domain.starts_with('.')
+                    && !domain.ends_with('.')
+                    && !value.chars().any(char::is_whitespace)
+            }
+            None => false,
+        };
+        if !valid {
+            return Err(anyhow!("{} is not a valid email address", value));
+        }
+        Ok(Self { _value: value })
+    }
+
+    pub fn value(&self) -> String {
+        self._value.to_owned()
+    }
+}
+
+/// A new password, checked against the password rules. Only its hash is kept.
+pub struct Password {
+    _value: String,
+}
+
+impl Password {
+    pub fn new(value: &str) -> Result<Self> {
+        let length = value.chars().count();
+        if !(PASSWORD_MIN_LENGTH..=PASSWORD_MAX_LENGTH).contains(&length) {
+            return Err(anyhow!(
+                "Password must be between {} and {} characters",
+                PASSWORD_MIN_LENGTH,
+                PASSWORD_MAX_LENGTH
+            ));
+        }
+        Ok(Self {
+            _value: value.to_string(),
+        })
+    }
+
+    fn hash(&self, hasher: impl IPasswordHasher) -> Result<String> {
+        hasher.hash(&self._value)
+    }
+}
+
+/// Raised when a password or reset token does not match.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct InvalidCredentials {
+    pub reason: String,
+}
+
+impl fmt::Display for InvalidCredentials {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Invalid credentials: {}", self.reason)
+    }
+}
+
+impl std::error::Error for InvalidCredentials {}
+
+// ================================================================================
+// Events
+// ================================================================================
+
+#[derive(Clone)]
+pub struct AccountRegistered {
+    pub id: UserId,
+    pub email: EmailAddress,
+    pub password_hash: String,
+}
+impl From<AccountRegistered> for AccountEvents {
+    fn from(e: AccountRegistered) -> Self {
+        AccountEvents::AccountRegistered(e)
+    }
+}
+
+#[derive(Clone)]
+pub struct PasswordChanged {
+    pub id: UserId,
+    pub password_hash: String,
+}
+impl From<PasswordChanged> for AccountEvents {
+    fn from(e: PasswordChanged) -> Self {
+        AccountEvents::PasswordChanged(e)
+    }
+}
+
+/// Only a hash of the token is recorded; the token itself goes to the user by mail.
+#[derive(Clone)]
+pub struct PasswordResetRequested {
+    pub id: UserId,
+    pub token_hash: String,
+    pub expires_at: SystemTime,
+}
+impl From<PasswordResetRequested> for AccountEvents {
+    fn from(e: PasswordResetRequested) -> Self {
+        AccountEvents::PasswordResetRequested(e)
+    }
+}
+
+#[derive(Clone)]
+pub struct PasswordReset {
+    pub id: UserId,
+    pub password_hash: String,
+}
+impl From<PasswordReset> for AccountEvents {
+    fn from(e: PasswordReset) -> Self {
+        AccountEvents::PasswordReset(e)
+    }
+}
+
+#[derive(Clone)]
+pub enum AccountEvents {
+    AccountRegistered(AccountRegistered),
+    PasswordChanged(PasswordChanged),
+    PasswordResetRequested(PasswordResetRequested),
+    PasswordReset(PasswordReset),
+}
+
+// ================================================================================
+// Aggregate
+// ================================================================================
+
+fn invalid_credentials(reason: &str) -> anyhow::Error {
+    InvalidCredentials {
+        reason: reason.to_string(),
+    }
+    .into()
+}
+
+pub trait UserAccountAggregate: AggregateRoot<Id = UserId, Event = AccountEvents> {
+    // Aggregate State Properties
+    fn id(&self) -> UserId;
+    fn email(&self) -> EmailAddress;
+    fn password_hash(&self) -> String;
+    /// Hash and expiry of the outstanding password reset token, if any.
+    fn reset_token(&self) -> Option<(String, SystemTime)>;
+
+    fn verify_password(&self, password: &str, hasher: impl IPasswordHasher) -> Result<bool> {
+        hasher.verify(password, &self.password_hash())
+    }
+
+    // Commands
+    fn register(
+        &mut self,
+        id: UserId,
+        email: EmailAddress,
+        password: Password,
+        hasher: impl IPasswordHasher,
+    ) -> Result<()> {
+        let password_hash = password.hash(hasher)?;
+        self.apply(AccountRegistered {
+            id,
+            email,
+            password_hash,
+        })
+    }
+    fn change_password(
+        &mut self,
+        current_password: &str,
+        new_password: Password,
+        hasher: impl IPasswordHasher,
+    ) -> Result<()> {
+        if !self.verify_password(current_password, &hasher)? {
+            return Err(invalid_credentials("the current password is incorrect"));
+        }
+        let password_hash = new_password.hash(&hasher)?;
+        self.apply(PasswordChanged {
+            id: self.id(),
+            password_hash,
+        })
+    }
+    fn request_password_reset(&mut self, token_hash: String, expires_at: SystemTime) -> Result<()> {
+        self.apply(PasswordResetRequested {
+            id: self.id(),
+            token_hash,
+            expires_at,
+        })
+    }
+    /// Sets a new password if `token_hash` matches the outstanding, unexpired token.
+    /// A token can be used once.
+    fn reset_password(
+        &mut self,
+        token_hash: &str,
+        new_password: Password,
+        hasher: impl IPasswordHasher,
+    ) -> Result<()> {
+        match self.reset_token() {
+            Some((expected, expires_at))
+                if expected == token_hash && expires_at > SystemTime::now() => {}
+            _ => {
+                return Err(invalid_credentials(
+                    "the reset token is invalid or has expired",
+                ))
+            }
+        }
+        let password_hash = new_password.hash(hasher)?;
+        self.apply(PasswordReset {
+            id: self.id(),
+            password_hash,
+        })
+    }
+}
+
+#[derive(Clone)]
+pub struct UserAccount {
+    _id: Option<UserId>,
+    _email: Option<EmailAddress>,
+    _password_hash: Option<String>,
+    _reset_token: Option<(String, SystemTime)>,
+    _changes: Vec<AccountEvents>,
+    _version: i64,
+}
+
+impl UserAccount {
+    pub fn new_empty() -> Self {
+        Self {
+            _id: None,
+            _email: None,
+            _password_hash: None,
+            _reset_token: None,
+            _changes: vec![],
+            _version: 0,
+        }
+    }
+
+    /// Events applied since the account was created, oldest first; read models
+    /// catch up from these.
+    pub fn changes(&self) -> &[AccountEvents] {
+        &self._changes
+    }
+}
+
+impl AggregateRoot for UserAccount {
+    type Id = UserId;
+    type Event = AccountEvents;
+
+    fn version(&self) -> i64 {
+        self._version
+    }
+
+    fn ensure_valid_state(&self) -> Result<()> {
+        if self._id.is_none() || self._email.is_none() || self._password_hash.is_none() {
+            return Err(anyhow!("User account must have an id, email and password"));
+        }
+        Ok(())
+    }
+
+    fn when(&mut self, event: Self::Event) -> Result<()> {
+        match event {
+            AccountEvents::AccountRegistered(e) => {
+                self._id = Some(e.id);
+                self._email = Some(e.email);
+                self._password_hash = Some(e.password_hash);
+            }
+            AccountEvents::PasswordChanged(e) => {
+                self._password_hash = Some(e.password_hash);
+                self._reset_token = None;
+            }
+            AccountEvents::PasswordResetRequested(e) => {
+                self._reset_token = Some((e.token_hash, e.expires_at));
+            }
+            AccountEvents::PasswordReset(e) => {
+                self._password_hash = Some(e.password_hash);
+                self._reset_token = None;
+            }
+        };
+        Ok(())
+    }
+
+    fn store_changes(&mut self, event: Self::Event) -> Result<()> {
+        self._changes.push(event);
+        self._version += 1;
+        Ok(())
+    }
+}
+
+impl UserAccountAggregate for UserAccount {
+    fn id(&self) -> UserId {
+        self._id.clone().unwrap() // Can be none
+    }
+
+    fn email(&self) -> EmailAddress {
+        self._email.clone().unwrap() // Can be none
+    }
+
+    fn password_hash(&self) -> String {
+        self._password_hash.clone().unwrap() // Can be none
+    }
+
+    fn reset_token(&self) -> Option<(String, SystemTime)> {
+        self._reset_token.clone()
+    }
+}
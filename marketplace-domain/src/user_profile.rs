@@ -3,18 +3,137 @@
  * is a much more recent iteration on the approach
  * used for marketplace ads
  */
-use crate::UserId;
-use anyhow::Result;
+use std::{fmt, str::FromStr, time::SystemTime};
+
+use crate::{ITextModeration, UserId};
+use anyhow::{anyhow, Result};
 use marketplace_framework::AggregateRoot;
+use unicode_normalization::{char::is_combining_mark, UnicodeNormalization};
 
 // ================================================================================
 // Value Objects
 // ================================================================================
 
-#[derive(Clone)]
-pub struct FullName {}
-#[derive(Clone)]
-pub struct DisplayName {}
+const FULL_NAME_MAX_LENGTH: usize = 100;
+const DISPLAY_NAME_MIN_LENGTH: usize = 3;
+const DISPLAY_NAME_MAX_LENGTH: usize = 30;
+
+/// NFC-normalises a name, trims it and collapses runs of whitespace to one space.
+fn normalise_name(value: &str) -> String {
+    value
+        .nfc()
+        .collect::<String>()
+        .split_whitespace()
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub struct FullName {
+    _value: String,
+}
+
+impl FullName {
+    /// Letters (in any script), spaces, and the punctuation found in names
+    /// such as "O'Brien", "Jean-Luc" or "Martin Luther King, Jr."
+    pub fn new(value: &str) -> Result<Self> {
+        let value = normalise_name(value);
+        if value.is_empty() {
+            return Err(anyhow!("Full name cannot be empty"));
+        }
+        if value.chars().count() > FULL_NAME_MAX_LENGTH {
+            return Err(anyhow!(
+                "Full name cannot be longer than {} characters",
+                FULL_NAME_MAX_LENGTH
+            ));
+        }
+        if let Some(c) = value.chars().find(|&c| {
+            !(c.is_alphabetic()
+                || is_combining_mark(c)
+                || matches!(c, ' ' | '\'' | '’' | '-' | '.' | ','))
+        }) {
+            return Err(anyhow!("Full name cannot contain '{}'", c));
+        }
+        Ok(Self { _value: value })
+    }
+
+    pub fn value(&self) -> String {
+        self._value.to_owned()
+    }
+}
+
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub struct DisplayName {
+    _value: String,
+}
+
+impl DisplayName {
+    /// Letters and digits in any script, spaces, `_`, `-` and `.`
+    pub fn new(value: &str) -> Result<Self> {
+        let value = normalise_name(value);
+        let length = value.chars().count();
+        if !(DISPLAY_NAME_MIN_LENGTH..=DISPLAY_NAME_MAX_LENGTH).contains(&length) {
+            return Err(anyhow!(
+                "Display name must be between {} and {} characters",
+                DISPLAY_NAME_MIN_LENGTH,
+                DISPLAY_NAME_MAX_LENGTH
+            ));
+        }
+        if let Some(c) = value.chars().find(|&c| {
+            !(c.is_alphanumeric() || is_combining_mark(c) || matches!(c, ' ' | '_' | '-' | '.'))
+        }) {
+            return Err(anyhow!("Display name cannot contain '{}'", c));
+        }
+        Ok(Self { _value: value })
+    }
+
+    pub fn value(&self) -> String {
+        self._value.to_owned()
+    }
+}
+
+/// What a user may do beyond managing their own ads and profile. Roles are
+/// ordered: an admin can do everything a moderator can.
+#[derive(Debug, PartialEq, Eq, Hash, PartialOrd, Ord, Clone, Copy)]
+pub enum Role {
+    User,
+    Moderator,
+    Admin,
+}
+
+impl Role {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Role::User => "user",
+            Role::Moderator => "moderator",
+            Role::Admin => "admin",
+        }
+    }
+
+    /// Whether holding this role meets a requirement for `required`.
+    pub fn includes(&self, required: Role) -> bool {
+        *self >= required
+    }
+}
+
+impl FromStr for Role {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s.trim().to_lowercase().as_str() {
+            "user" => Ok(Role::User),
+            "moderator" => Ok(Role::Moderator),
+            "admin" => Ok(Role::Admin),
+            other => Err(anyhow!("Unknown role {}", other)),
+        }
+    }
+}
+
+impl fmt::Display for Role {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
 
 // ================================================================================
 // Events
@@ -22,9 +141,9 @@ pub struct DisplayName {}
 
 #[derive(Clone)]
 pub struct UserRegistered {
-    id: UserId,
-    full_name: FullName,
-    display_name: DisplayName,
+    pub id: UserId,
+    pub full_name: FullName,
+    pub display_name: DisplayName,
 }
 
 impl From<UserRegistered> for UserEvents {
@@ -35,8 +154,8 @@ impl From<UserRegistered> for UserEvents {
 
 #[derive(Clone)]
 pub struct UserFullNameUpdated {
-    full_name: FullName,
-    id: UserId,
+    pub full_name: FullName,
+    pub id: UserId,
 }
 impl From<UserFullNameUpdated> for UserEvents {
     fn from(e: UserFullNameUpdated) -> Self {
@@ -45,8 +164,8 @@ impl From<UserFullNameUpdated> for UserEvents {
 }
 #[derive(Clone)]
 pub struct UserDisplayNameUpdated {
-    display_name: DisplayName,
-    id: UserId,
+    pub display_name: DisplayName,
+    pub id: UserId,
 }
 impl From<UserDisplayNameUpdated> for UserEvents {
     fn from(e: UserDisplayNameUpdated) -> Self {
@@ -54,17 +173,65 @@ impl From<UserDisplayNameUpdated> for UserEvents {
     }
 }
 
+#[derive(Clone)]
+pub struct ProfilePhotoUploaded {
+    pub photo_url: String,
+    pub thumbnail_url: String,
+    pub id: UserId,
+}
+impl From<ProfilePhotoUploaded> for UserEvents {
+    fn from(e: ProfilePhotoUploaded) -> Self {
+        UserEvents::ProfilePhotoUploaded(e)
+    }
+}
+
+#[derive(Clone)]
+pub struct RoleGranted {
+    pub id: UserId,
+    pub role: Role,
+    pub granted_by: UserId,
+    pub granted_at: SystemTime,
+}
+impl From<RoleGranted> for UserEvents {
+    fn from(e: RoleGranted) -> Self {
+        UserEvents::RoleGranted(e)
+    }
+}
+
+#[derive(Clone)]
+pub struct RoleRevoked {
+    pub id: UserId,
+    pub role: Role,
+    pub revoked_by: UserId,
+    pub revoked_at: SystemTime,
+}
+impl From<RoleRevoked> for UserEvents {
+    fn from(e: RoleRevoked) -> Self {
+        UserEvents::RoleRevoked(e)
+    }
+}
+
 #[derive(Clone)]
 pub enum UserEvents {
     UserRegistered(UserRegistered),
     UserFullNameUpdated(UserFullNameUpdated),
     UserDisplayNameUpdated(UserDisplayNameUpdated),
+    ProfilePhotoUploaded(ProfilePhotoUploaded),
+    RoleGranted(RoleGranted),
+    RoleRevoked(RoleRevoked),
 }
 
 // ================================================================================
 // Aggregate
 // ================================================================================
 
+fn check_display_name(display_name: &DisplayName, moderation: impl ITextModeration) -> Result<()> {
+    if moderation.contains_profanity(&display_name.value())? {
+        return Err(anyhow!("Display name contains offensive language"));
+    }
+    Ok(())
+}
+
 pub trait UserProfileAggregate: AggregateRoot<Id = UserId, Event = UserEvents> {
     // Aggregate State Properties
     fn full_name(&self) -> FullName;
@@ -72,6 +239,16 @@ pub trait UserProfileAggregate: AggregateRoot<Id = UserId, Event = UserEvents> {
 
     fn display_name(&self) -> DisplayName;
 
+    fn photo_url(&self) -> Option<String>;
+
+    fn thumbnail_url(&self) -> Option<String>;
+
+    /// Roles granted on top of the user role every registered user has.
+    fn roles(&self) -> Vec<Role>;
+
+    /// Role grants and revocations, oldest first, kept for auditing.
+    fn role_history(&self) -> Vec<UserEvents>;
+
     fn db_id(&self) -> String {
         let id = self.id().value();
         format!("UserProfile/{id}")
@@ -83,7 +260,9 @@ pub trait UserProfileAggregate: AggregateRoot<Id = UserId, Event = UserEvents> {
         id: UserId,
         full_name: FullName,
         display_name: DisplayName,
+        moderation: impl ITextModeration,
     ) -> Result<()> {
+        check_display_name(&display_name, moderation)?;
         self.apply(UserRegistered {
             id,
             full_name,
@@ -94,16 +273,68 @@ pub trait UserProfileAggregate: AggregateRoot<Id = UserId, Event = UserEvents> {
     fn update_full_name(&mut self, id: UserId, full_name: FullName) -> Result<()> {
         self.apply(UserFullNameUpdated { id, full_name })
     }
-    fn update_display_name(&mut self, id: UserId, display_name: DisplayName) -> Result<()> {
+    fn update_display_name(
+        &mut self,
+        id: UserId,
+        display_name: DisplayName,
+        moderation: impl ITextModeration,
+    ) -> Result<()> {
+        check_display_name(&display_name, moderation)?;
         self.apply(UserDisplayNameUpdated { id, display_name })
     }
+    fn update_profile_photo(
+        &mut self,
+        id: UserId,
+        photo_url: String,
+        thumbnail_url: String,
+    ) -> Result<()> {
+        if photo_url.is_empty() || thumbnail_url.is_empty() {
+            return Err(anyhow!("Photo and thumbnail urls cannot be empty"));
+        }
+        self.apply(ProfilePhotoUploaded {
+            id,
+            photo_url,
+            thumbnail_url,
+        })
+    }
+    fn grant_role(&mut self, id: UserId, role: Role, granted_by: UserId) -> Result<()> {
+        if role == Role::User {
+            return Err(anyhow!("Every registered user has the user role"));
+        }
+        if self.roles().contains(&role) {
+            return Err(anyhow!("User already has the {} role", role));
+        }
+        self.apply(RoleGranted {
+            id,
+            role,
+            granted_by,
+            granted_at: SystemTime::now(),
+        })
+    }
+    fn revoke_role(&mut self, id: UserId, role: Role, revoked_by: UserId) -> Result<()> {
+        if !self.roles().contains(&role) {
+            return Err(anyhow!("User does not have the {} role", role));
+        }
+        self.apply(RoleRevoked {
+            id,
+            role,
+            revoked_by,
+            revoked_at: SystemTime::now(),
+        })
+    }
 }
 
+#[derive(Clone)]
 pub struct UserProfile {
     _id: Option<UserId>,
     _full_name: Option<FullName>,
     _display_name: Option<DisplayName>,
+    _photo_url: Option<String>,
+    _thumbnail_url: Option<String>,
+    _roles: Vec<Role>,
+    _role_history: Vec<UserEvents>,
     _changes: Vec<UserEvents>,
+    _version: i64,
 }
 
 impl UserProfile {
@@ -112,7 +343,12 @@ impl UserProfile {
             _id: None,
             _full_name: None,
             _display_name: None,
+            _photo_url: None,
+            _thumbnail_url: None,
+            _roles: vec![],
+            _role_history: vec![],
             _changes: vec![],
+            _version: 0,
         }
     }
 }
@@ -121,13 +357,23 @@ impl AggregateRoot for UserProfile {
     type Id = UserId;
     type Event = UserEvents;
 
+    fn version(&self) -> i64 {
+        self._version
+    }
+
     fn ensure_valid_state(&self) -> Result<()> {
+        if self._id.is_none() || self._full_name.is_none() || self._display_name.is_none() {
+            return Err(anyhow!(
+                "User profile must have an id, full name and display name"
+            ));
+        }
         Ok(())
     }
 
     fn when(&mut self, event: Self::Event) -> Result<()> {
         match event {
             UserEvents::UserRegistered(e) => {
+                self._id = Some(e.id);
                 self._display_name = Some(e.display_name);
                 self._full_name = Some(e.full_name);
             }
@@ -137,12 +383,26 @@ impl AggregateRoot for UserProfile {
             UserEvents::UserDisplayNameUpdated(e) => {
                 self._display_name = Some(e.display_name);
             }
+            UserEvents::ProfilePhotoUploaded(e) => {
+                self._photo_url = Some(e.photo_url);
+                self._thumbnail_url = Some(e.thumbnail_url);
+            }
+            UserEvents::RoleGranted(e) => {
+                self._roles.push(e.role);
+                self._roles.sort();
+                self._role_history.push(e.into());
+            }
+            UserEvents::RoleRevoked(e) => {
+                self._roles.retain(|role| *role != e.role);
+                self._role_history.push(e.into());
+            }
         };
         Ok(())
     }
 
     fn store_changes(&mut self, event: Self::Event) -> Result<()> {
         self._changes.push(event);
+        self._version += 1;
         Ok(())
     }
 }
@@ -159,4 +419,20 @@ impl UserProfileAggregate for UserProfile {
     fn display_name(&self) -> DisplayName {
         self._display_name.clone().unwrap() // Can be none
     }
+
+    fn photo_url(&self) -> Option<String> {
+        self._photo_url.clone()
+    }
+
+    fn thumbnail_url(&self) -> Option<String> {
+        self._thumbnail_url.clone()
+    }
+
+    fn roles(&self) -> Vec<Role> {
+        self._roles.clone()
+    }
+
+    fn role_history(&self) -> Vec<UserEvents> {
+        self._role_history.clone()
+    }
 }
@@ -0,0 +1,180 @@
+use std::{
+    any::{type_name, Any, TypeId},
+    collections::HashMap,
+    sync::Arc,
+};
+
+use anyhow::{anyhow, Result};
+
+use crate::ExpectedVersionMismatch;
+
+pub trait ICommandHandler<C>: Send + Sync {
+    fn handle(&self, command: C, metadata: &CommandMetadata) -> Result<()>;
+}
+
+impl<C, F> ICommandHandler<C> for F
+where
+    F: Fn(C, &CommandMetadata) -> Result<()> + Send + Sync,
+{
+    fn handle(&self, command: C, metadata: &CommandMetadata) -> Result<()> {
+        self(command, metadata)
+    }
+}
+
+type ErasedHandler =
+    Box<dyn Fn(&(dyn Any + Send + Sync), &CommandMetadata) -> Result<()> + Send + Sync>;
+
+/// Out-of-band information travelling with a command, e.g. the idempotency key
+/// supplied by a client or the authenticated caller.
+#[derive(Clone, Default)]
+pub struct CommandMetadata {
+    pub idempotency_key: Option<String>,
+    /// Who sent the command. Idempotency keys are only matched against the
+    /// caller's own earlier commands.
+    pub caller_id: Option<String>,
+    /// Stable representation of the command payload, used to detect an
+    /// idempotency key being reused for a different request.
+    pub payload_fingerprint: Option<String>,
+    /// Versions of the aggregate the caller based the command on, if they care;
+    /// any of them will do.
+    pub expected_versions: Option<Vec<i64>>,
+    _extensions: HashMap<TypeId, Arc<dyn Any + Send + Sync>>,
+}
+
+impl CommandMetadata {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn with_idempotency_key(mut self, key: impl Into<String>) -> Self {
+        self.idempotency_key = Some(key.into());
+        self
+    }
+
+    pub fn with_caller_id(mut self, caller_id: impl Into<String>) -> Self {
+        self.caller_id = Some(caller_id.into());
+        self
+    }
+
+    pub fn with_expected_versions(mut self, versions: Vec<i64>) -> Self {
+        self.expected_versions = Some(versions);
+        self
+    }
+
+    /// Fails with [`ExpectedVersionMismatch`] unless the caller expects
+    /// `actual_version`, or did not say which version they expect.
+    pub fn check_version(&self, actual_version: i64) -> Result<()> {
+        match &self.expected_versions {
+            Some(versions) if !versions.contains(&actual_version) => Err(ExpectedVersionMismatch {
+                expected_versions: versions.clone(),
+                actual_version,
+            }
+            .into()),
+            _ => Ok(()),
+        }
+    }
+
+    pub fn with_payload_fingerprint(mut self, fingerprint: impl Into<String>) -> Self {
+        self.payload_fingerprint = Some(fingerprint.into());
+        self
+    }
+
+    /// Attach a typed value, replacing any previous value of the same type.
+    pub fn insert<T: Any + Send + Sync>(&mut self, value: T) {
+        self._extensions.insert(TypeId::of::<T>(), Arc::new(value));
+    }
+
+    pub fn get<T: Any + Send + Sync>(&self) -> Option<&T> {
+        self._extensions
+            .get(&TypeId::of::<T>())
+            .and_then(|value| value.downcast_ref::<T>())
+    }
+}
+
+pub struct CommandEnvelope<'a> {
+    pub command: &'a (dyn Any + Send + Sync),
+    pub command_name: &'static str,
+    pub metadata: &'a CommandMetadata,
+}
+
+impl CommandEnvelope<'_> {
+    pub fn command<C: Any>(&self) -> Option<&C> {
+        self.command.downcast_ref::<C>()
+    }
+}
+
+pub type Next<'a> = &'a dyn Fn(&CommandEnvelope) -> Result<()>;
+
+pub trait ICommandMiddleware: Send + Sync {
+    /// Runs around the rest of the pipeline; call `next` to continue towards the handler.
+    fn handle(&self, envelope: &CommandEnvelope, next: Next<'_>) -> Result<()>;
+}
+
+#[derive(Default)]
+pub struct CommandBus {
+    _handlers: HashMap<TypeId, ErasedHandler>,
+    _middleware: Vec<Box<dyn ICommandMiddleware>>,
+}
+
+impl CommandBus {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Register the handler for commands of type `C`, replacing any existing one.
+    pub fn register<C>(mut self, handler: impl ICommandHandler<C> + 'static) -> Self
+    where
+        C: Clone + Send + Sync + 'static,
+    {
+        let erased: ErasedHandler = Box::new(move |command, metadata| {
+            let command = command
+                .downcast_ref::<C>()
+                .ok_or_else(|| anyhow!("Handler received wrong command type"))?;
+            handler.handle(command.clone(), metadata)
+        });
+        self._handlers.insert(TypeId::of::<C>(), erased);
+        self
+    }
+
+    /// Append a middleware. Middleware runs in the order it was added, outermost first.
+    pub fn with(mut self, middleware: impl ICommandMiddleware + 'static) -> Self {
+        self._middleware.push(Box::new(middleware));
+        self
+    }
+
+    pub fn dispatch<C>(&self, command: C) -> Result<()>
+    where
+        C: Send + Sync + 'static,
+    {
+        self.dispatch_with(command, CommandMetadata::default())
+    }
+
+    pub fn dispatch_with<C>(&self, command: C, metadata: CommandMetadata) -> Result<()>
+    where
+        C: Send + Sync + 'static,
+    {
+        let envelope = CommandEnvelope {
+            command: &command,
+            command_name: type_name::<C>(),
+            metadata: &metadata,
+        };
+        self.run(0, &envelope)
+    }
+
+    fn run(&self, index: usize, envelope: &CommandEnvelope) -> Result<()> {
+        match self._middleware.get(index) {
+            Some(middleware) => {
+                middleware.handle(envelope, &|envelope| self.run(index + 1, envelope))
+            }
+            None => {
+                let handler = self
+                    ._handlers
+                    .get(&(*envelope.command).type_id())
+                    .ok_or_else(|| {
+                        anyhow!("No handler registered for {}", envelope.command_name)
+                    })?;
+                handler(envelope.command, envelope.metadata)
+            }
+        }
+    }
+}
@@ -0,0 +1,121 @@
+use std::fmt;
+
+/// Raised when an aggregate was changed by someone else between being loaded and saved.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ConcurrencyConflict {
+    pub expected_version: i64,
+    pub actual_version: i64,
+}
+
+impl fmt::Display for ConcurrencyConflict {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "Concurrency conflict: expected version {} but found {}",
+            self.expected_version, self.actual_version
+        )
+    }
+}
+
+impl std::error::Error for ConcurrencyConflict {}
+
+/// Raised when an idempotency key is replayed with a different command or payload
+/// than the one it was first used with.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct IdempotencyKeyReused {
+    pub key: String,
+}
+
+impl fmt::Display for IdempotencyKeyReused {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "Idempotency key {} was already used for a different request",
+            self.key
+        )
+    }
+}
+
+impl std::error::Error for IdempotencyKeyReused {}
+
+/// Raised when a command arrives with an idempotency key whose first command is
+/// still being handled.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct IdempotencyKeyInProgress {
+    pub key: String,
+}
+
+impl fmt::Display for IdempotencyKeyInProgress {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "A request with idempotency key {} is still being processed",
+            self.key
+        )
+    }
+}
+
+impl std::error::Error for IdempotencyKeyInProgress {}
+
+/// Raised when a command was issued against a different version of an aggregate
+/// than the one currently stored, e.g. from a stale `If-Match` header.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ExpectedVersionMismatch {
+    /// Any of these would have done; none when the caller only gave tags that
+    /// can never match.
+    pub expected_versions: Vec<i64>,
+    pub actual_version: i64,
+}
+
+impl fmt::Display for ExpectedVersionMismatch {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let expected: Vec<String> = self.expected_versions.iter().map(i64::to_string).collect();
+        match expected.as_slice() {
+            [] => write!(
+                f,
+                "No expected version can match; the current version is {}",
+                self.actual_version
+            ),
+            [version] => write!(
+                f,
+                "Expected version {} but the current version is {}",
+                version, self.actual_version
+            ),
+            versions => write!(
+                f,
+                "Expected one of versions {} but the current version is {}",
+                versions.join(", "),
+                self.actual_version
+            ),
+        }
+    }
+}
+
+impl std::error::Error for ExpectedVersionMismatch {}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct EntityNotFound {
+    pub id: String,
+}
+
+impl fmt::Display for EntityNotFound {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Could not find entity with id {}", self.id)
+    }
+}
+
+impl std::error::Error for EntityNotFound {}
+
+/// Raised when the caller is not allowed to issue a command.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Forbidden {
+    pub reason: String,
+}
+
+impl fmt::Display for Forbidden {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Forbidden: {}", self.reason)
+    }
+}
+
+impl std::error::Error for Forbidden {}
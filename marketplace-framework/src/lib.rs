@@ -1,8 +1,18 @@
+pub mod command_bus;
+pub mod errors;
+pub mod middleware;
+
+pub use command_bus::*;
+pub use errors::*;
+pub use middleware::*;
+
 use anyhow::Result;
 
 pub trait AggregateRoot {
     type Id;
     type Event: Clone;
+    /// Number of events applied to the aggregate over its lifetime.
+    fn version(&self) -> i64;
     fn ensure_valid_state(&self) -> Result<()>;
     fn when(&mut self, event: Self::Event) -> Result<()>;
     fn store_changes(&mut self, event: Self::Event) -> Result<()>;
@@ -0,0 +1,312 @@
+use std::{
+    any::{Any, TypeId},
+    collections::HashMap,
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+use anyhow::Result;
+
+use crate::{
+    CommandEnvelope, ConcurrencyConflict, ICommandMiddleware, IdempotencyKeyInProgress,
+    IdempotencyKeyReused, Next,
+};
+
+pub struct LoggingMiddleware;
+
+impl ICommandMiddleware for LoggingMiddleware {
+    fn handle(&self, envelope: &CommandEnvelope, next: Next<'_>) -> Result<()> {
+        tracing::info!(command = envelope.command_name, "Handling command");
+        let result = next(envelope);
+        match &result {
+            Ok(()) => tracing::info!(command = envelope.command_name, "Command handled"),
+            Err(e) => tracing::warn!(command = envelope.command_name, error = %e, "Command failed"),
+        }
+        result
+    }
+}
+
+type Validator = Box<dyn Fn(&(dyn Any + Send + Sync)) -> Result<()> + Send + Sync>;
+
+/// Runs the validators registered for a command type before it reaches its handler.
+/// Commands without a validator pass straight through.
+#[derive(Default)]
+pub struct ValidationMiddleware {
+    _validators: HashMap<TypeId, Validator>,
+}
+
+impl ValidationMiddleware {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn validate<C: Any>(
+        mut self,
+        validator: impl Fn(&C) -> Result<()> + Send + Sync + 'static,
+    ) -> Self {
+        let erased: Validator = Box::new(move |command| match command.downcast_ref::<C>() {
+            Some(command) => validator(command),
+            None => Ok(()),
+        });
+        self._validators.insert(TypeId::of::<C>(), erased);
+        self
+    }
+}
+
+impl ICommandMiddleware for ValidationMiddleware {
+    fn handle(&self, envelope: &CommandEnvelope, next: Next<'_>) -> Result<()> {
+        if let Some(validator) = self._validators.get(&(*envelope.command).type_id()) {
+            validator(envelope.command)?;
+        }
+        next(envelope)
+    }
+}
+
+pub trait IAuthorizeCommand: Send + Sync {
+    fn authorize(&self, envelope: &CommandEnvelope) -> Result<()>;
+}
+
+impl<F> IAuthorizeCommand for F
+where
+    F: Fn(&CommandEnvelope) -> Result<()> + Send + Sync,
+{
+    fn authorize(&self, envelope: &CommandEnvelope) -> Result<()> {
+        self(envelope)
+    }
+}
+
+pub struct AuthorizationMiddleware {
+    _policy: Box<dyn IAuthorizeCommand>,
+}
+
+impl AuthorizationMiddleware {
+    pub fn new(policy: impl IAuthorizeCommand + 'static) -> Self {
+        Self {
+            _policy: Box::new(policy),
+        }
+    }
+}
+
+impl ICommandMiddleware for AuthorizationMiddleware {
+    fn handle(&self, envelope: &CommandEnvelope, next: Next<'_>) -> Result<()> {
+        self._policy.authorize(envelope)?;
+        next(envelope)
+    }
+}
+
+/// Re-runs the rest of the pipeline when it fails with a [`ConcurrencyConflict`],
+/// so the handler gets to reload the aggregate and try again.
+pub struct RetryMiddleware {
+    _max_attempts: u32,
+}
+
+impl RetryMiddleware {
+    pub fn new(max_attempts: u32) -> Self {
+        Self {
+            _max_attempts: max_attempts.max(1),
+        }
+    }
+}
+
+impl ICommandMiddleware for RetryMiddleware {
+    fn handle(&self, envelope: &CommandEnvelope, next: Next<'_>) -> Result<()> {
+        let mut attempt = 1;
+        loop {
+            match next(envelope) {
+                Err(e) if e.is::<ConcurrencyConflict>() && attempt < self._max_attempts => {
+                    tracing::debug!(command = envelope.command_name, attempt, "Retrying command");
+                    attempt += 1;
+                }
+                result => return result,
+            }
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct CommandStats {
+    pub dispatched: u64,
+    pub succeeded: u64,
+    pub failed: u64,
+    pub total_duration: Duration,
+}
+
+#[derive(Clone, Default)]
+pub struct CommandMetrics {
+    _stats: Arc<Mutex<HashMap<&'static str, CommandStats>>>,
+}
+
+impl CommandMetrics {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn snapshot(&self) -> HashMap<&'static str, CommandStats> {
+        self._stats.lock().unwrap().clone()
+    }
+
+    fn record(&self, command_name: &'static str, succeeded: bool, duration: Duration) {
+        let mut stats = self._stats.lock().unwrap();
+        let entry = stats.entry(command_name).or_default();
+        entry.dispatched += 1;
+        if succeeded {
+            entry.succeeded += 1;
+        } else {
+            entry.failed += 1;
+        }
+        entry.total_duration += duration;
+    }
+}
+
+pub struct MetricsMiddleware {
+    _metrics: CommandMetrics,
+}
+
+impl MetricsMiddleware {
+    pub fn new(metrics: CommandMetrics) -> Self {
+        Self { _metrics: metrics }
+    }
+}
+
+impl ICommandMiddleware for MetricsMiddleware {
+    fn handle(&self, envelope: &CommandEnvelope, next: Next<'_>) -> Result<()> {
+        let started = Instant::now();
+        let result = next(envelope);
+        self._metrics
+            .record(envelope.command_name, result.is_ok(), started.elapsed());
+        result
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CommandOutcome {
+    /// The command is still being handled.
+    Pending,
+    Succeeded,
+}
+
+/// What was recorded for an idempotency key: how far the command got and the
+/// fingerprint of the command that used the key.
+#[derive(Debug, Clone)]
+pub struct IdempotencyRecord {
+    pub outcome: CommandOutcome,
+    pub fingerprint: String,
+    pub expires_at: Instant,
+}
+
+pub trait IIdempotencyStore: Send + Sync {
+    /// Records `record` for a key unless an unexpired record already exists for
+    /// it, which is returned instead. Checking and recording happen atomically.
+    fn reserve(&self, key: &str, record: IdempotencyRecord) -> Option<IdempotencyRecord>;
+    fn save(&self, key: &str, record: IdempotencyRecord);
+    fn remove(&self, key: &str);
+}
+
+#[derive(Default)]
+pub struct InMemoryIdempotencyStore {
+    _records: Mutex<HashMap<String, IdempotencyRecord>>,
+}
+
+impl InMemoryIdempotencyStore {
+    pub fn new() -> Self {
+        Self::default()
+    }
+}
+
+impl IIdempotencyStore for InMemoryIdempotencyStore {
+    fn reserve(&self, key: &str, record: IdempotencyRecord) -> Option<IdempotencyRecord> {
+        let mut records = self._records.lock().unwrap();
+        let now = Instant::now();
+        records.retain(|_, record| record.expires_at > now);
+        if let Some(existing) = records.get(key) {
+            return Some(existing.clone());
+        }
+        records.insert(key.to_string(), record);
+        None
+    }
+
+    fn save(&self, key: &str, record: IdempotencyRecord) {
+        self._records
+            .lock()
+            .unwrap()
+            .insert(key.to_string(), record);
+    }
+
+    fn remove(&self, key: &str) {
+        self._records.lock().unwrap().remove(key);
+    }
+}
+
+/// Replays success for commands carrying an idempotency key that has already
+/// been handled, instead of running them again. Keys belong to the caller that
+/// sent them. A key is reserved before its command runs, so a retry arriving
+/// while the first attempt is still running fails with
+/// [`IdempotencyKeyInProgress`]. Failures are not recorded: a retry after one
+/// runs the command again, and gets its error as it is then. Reusing a key for
+/// a different command or payload fails with [`IdempotencyKeyReused`].
+pub struct IdempotencyMiddleware {
+    _store: Box<dyn IIdempotencyStore>,
+    _ttl: Duration,
+}
+
+impl IdempotencyMiddleware {
+    pub fn new(store: impl IIdempotencyStore + 'static, ttl: Duration) -> Self {
+        Self {
+            _store: Box::new(store),
+            _ttl: ttl,
+        }
+    }
+}
+
+impl ICommandMiddleware for IdempotencyMiddleware {
+    fn handle(&self, envelope: &CommandEnvelope, next: Next<'_>) -> Result<()> {
+        let metadata = envelope.metadata;
+        let key = match &metadata.idempotency_key {
+            Some(key) => key,
+            None => return next(envelope),
+        };
+        let scoped_key = format!(
+            "{}:{}",
+            metadata.caller_id.as_deref().unwrap_or_default(),
+            key
+        );
+        let fingerprint = format!(
+            "{}:{}",
+            envelope.command_name,
+            metadata.payload_fingerprint.as_deref().unwrap_or_default()
+        );
+        let record = |outcome| IdempotencyRecord {
+            outcome,
+            fingerprint: fingerprint.clone(),
+            expires_at: Instant::now() + self._ttl,
+        };
+        if let Some(existing) = self
+            ._store
+            .reserve(&scoped_key, record(CommandOutcome::Pending))
+        {
+            if existing.fingerprint != fingerprint {
+                return Err(IdempotencyKeyReused { key: key.clone() }.into());
+            }
+            return match existing.outcome {
+                CommandOutcome::Pending => {
+                    Err(IdempotencyKeyInProgress { key: key.clone() }.into())
+                }
+                CommandOutcome::Succeeded => {
+                    tracing::debug!(
+                        command = envelope.command_name,
+                        "Replaying idempotent command"
+                    );
+                    Ok(())
+                }
+            };
+        }
+        let result = next(envelope);
+        match &result {
+            Ok(()) => self
+                ._store
+                .save(&scoped_key, record(CommandOutcome::Succeeded)),
+            Err(_) => self._store.remove(&scoped_key),
+        }
+        result
+    }
+}